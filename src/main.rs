@@ -1,37 +1,165 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Child as OsChild, Command, Stdio};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
 use std::time::{Duration, Instant};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
 use anyhow::{Context as AnyhowCtx, Result};
+use base64::Engine as _;
 use egui::Context;
 use chrono::{DateTime, Local};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use eframe::egui::{self, *};
 use lazy_static::lazy_static;
 use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use unicode_width::UnicodeWidthChar;
 use vte::{Params, Parser, Perform};
 
 const AI_RAM_LIMIT_BYTES: u64 = 1_610_612_736;
 const APP_VERSION: &str = "BETA-0.1";
+const CELL_PROBE_GLYPHS: [char; 7] = ['W', 'M', 'i', 'l', '0', ' ', '_'];
+
+static CONFIG_PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+static ACTIVE_PROFILE: Mutex<String> = Mutex::new(String::new());
+const DEFAULT_PROFILE: &str = "default";
+
+fn app_config_dir() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("spiltixal")
+}
+
+fn themes_dir() -> PathBuf {
+    app_config_dir().join("themes")
+}
+
+const THEME_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ThemeFile {
+    #[serde(default = "default_theme_schema_version")]
+    schema_version: u32,
+    #[serde(flatten)]
+    theme: Theme,
+}
+
+fn default_theme_schema_version() -> u32 { THEME_SCHEMA_VERSION }
+
+fn app_data_dir() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("spiltixal")
+}
+
+fn expand_tilde(raw: &str) -> PathBuf {
+    let rest = raw.strip_prefix('~').filter(|rest| rest.is_empty() || rest.starts_with('/'));
+    match rest.zip(dirs::home_dir()) {
+        Some((rest, home)) => home.join(rest.trim_start_matches('/')),
+        None => PathBuf::from(raw),
+    }
+}
+
+fn expand_config_path(raw: &str) -> PathBuf {
+    let path = expand_tilde(raw);
+    if path.is_absolute() { path } else { app_data_dir().join(path) }
+}
+
+fn active_profile() -> String {
+    let name = ACTIVE_PROFILE.lock().map(|p| p.clone()).unwrap_or_default();
+    if name.is_empty() { DEFAULT_PROFILE.into() } else { name }
+}
+
+fn set_active_profile(name: &str) {
+    if let Ok(mut p) = ACTIVE_PROFILE.lock() { *p = name.to_string(); }
+}
+
+fn profiles_root() -> PathBuf {
+    app_config_dir().join("profiles")
+}
+
+fn profile_dir(name: &str) -> Option<PathBuf> {
+    if name == DEFAULT_PROFILE { None } else { Some(profiles_root().join(name)) }
+}
+
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 40 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn list_profiles() -> Vec<String> {
+    let mut out = vec![DEFAULT_PROFILE.to_string()];
+    if let Ok(entries) = std::fs::read_dir(profiles_root()) {
+        let mut named = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| n != DEFAULT_PROFILE && is_valid_profile_name(n))
+            .collect::<Vec<_>>();
+        named.sort();
+        out.extend(named);
+    }
+    out
+}
 
 fn is_hyprland() -> bool {
     std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok()
 }
 
+const FALLBACK_TERM: &str = "xterm-256color";
+const SPILTIXAL_TERM: &str = "spiltixal";
+const SPILTIXAL_TERMINFO: &str = "spiltixal|Spiltixal terminal emulator,
+\tSmulx=\\E[4:%p1%dm,
+\tSetulc=\\E[58;2;%p1%{65536}%/%d;%p1%{256}%/%{255}%&%d;%p1%{255}%&%dm,
+\tbce@, ccc@, mc5i@, mir@,
+\tcbt@, flash@, initc@, mc0@, mc4@, mc5@, meml@, memu@, mgc@, oc@, rep@,
+\trmam@, rmir@, rmm@, smam@, smglp@, smglr@, smgrp@, smir@, smm@,
+\tu6@, u7@, u8@, u9@,
+\tuse=xterm-256color,
+";
+
+fn terminfo_dirs() -> Vec<PathBuf> {
+    let mut dirs_out = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") { dirs_out.push(PathBuf::from(dir)); }
+    if let Some(home) = dirs::home_dir() { dirs_out.push(home.join(".terminfo")); }
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        dirs_out.extend(list.split(':').filter(|d| !d.is_empty()).map(PathBuf::from));
+    }
+    dirs_out.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo"].map(PathBuf::from));
+    dirs_out
+}
+
+fn terminfo_installed(name: &str) -> bool {
+    let Some(first) = name.chars().next() else { return false };
+    terminfo_dirs().iter().any(|dir| {
+        dir.join(first.to_string()).join(name).is_file()
+            || dir.join(format!("{:x}", first as u32)).join(name).is_file()
+    })
+}
+
+fn resolve_term(config: &Config) -> String {
+    let configured = config.term.trim();
+    if !configured.is_empty() { return configured.to_string(); }
+    if terminfo_installed(SPILTIXAL_TERM) { SPILTIXAL_TERM.into() } else { FALLBACK_TERM.into() }
+}
+
+fn install_terminfo() -> Result<PathBuf> {
+    let target = dirs::home_dir().context("No home directory")?.join(".terminfo");
+    let source = std::env::temp_dir().join(format!("spiltixal-{}.terminfo", std::process::id()));
+    std::fs::write(&source, SPILTIXAL_TERMINFO).context("Failed to write terminfo source")?;
+    let status = Command::new("tic").arg("-x").arg("-o").arg(&target).arg(&source).status();
+    let _ = std::fs::remove_file(&source);
+    if !status.context("Failed to run tic (is ncurses installed?)")?.success() {
+        anyhow::bail!("tic failed to compile the spiltixal terminfo entry");
+    }
+    Ok(target)
+}
+
 fn find_icon_fonts() -> Vec<PathBuf> {
     let mut out = Vec::new();
     let nerd_candidates = [
@@ -61,17 +189,185 @@ fn find_icon_fonts() -> Vec<PathBuf> {
     out
 }
 
-fn image_from_path(path: &PathBuf) -> Option<ColorImage> {
-    let mut img = image::open(path).ok()?;
-    let max_side = 2048u32;
-    if img.width() > max_side || img.height() > max_side {
-        img = img.thumbnail(max_side, max_side);
+#[derive(Clone, Debug)]
+pub struct MonoFont { pub family: String, pub path: PathBuf }
+
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("/usr/share/fonts"), PathBuf::from("/usr/local/share/fonts")];
+    if let Some(data) = dirs::data_dir() { dirs.push(data.join("fonts")); }
+    if let Some(home) = dirs::home_dir() { dirs.push(home.join(".fonts")); }
+    dirs
+}
+
+fn find_mono_fonts() -> Vec<MonoFont> {
+    let mut found: Vec<MonoFont> = Vec::new();
+    let mut pending: Vec<(PathBuf, usize)> = font_dirs().into_iter().map(|d| (d, 0)).collect();
+    while let Some((dir, depth)) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.is_dir() {
+                if depth < 4 { pending.push((path, depth + 1)); }
+                continue;
+            }
+            let is_font = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("ttf") || e.eq_ignore_ascii_case("otf"));
+            let Some(stem) = path.file_stem().and_then(|s| s.to_str()).filter(|_| is_font) else { continue };
+            let (family, style) = stem.split_once('-').unwrap_or((stem, "Regular"));
+            let lower = family.to_lowercase();
+            let monospace = ["mono", "code", "consol", "courier", "fixed", "term"].iter().any(|k| lower.contains(k));
+            if !monospace || !matches!(style, "Regular" | "R") || found.iter().any(|f| f.family == family) { continue; }
+            found.push(MonoFont { family: family.to_string(), path });
+        }
     }
-    let img = img.to_rgba8();
+    found.sort_by_key(|f| f.family.to_lowercase());
+    found
+}
+
+fn font_name_key(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+fn wants_custom_font(family: &str) -> bool {
+    let key = font_name_key(family);
+    !key.is_empty() && key != "monospace"
+}
+
+fn find_font_family(family: &str) -> Option<PathBuf> {
+    if !wants_custom_font(family) { return None; }
+    let key = font_name_key(family);
+    find_mono_fonts().into_iter().find(|f| font_name_key(&f.family) == key).map(|f| f.path)
+}
+
+const TERM_STYLE_FAMILIES: [&str; 3] = ["TermBold", "TermItalic", "TermBoldItalic"];
+
+pub struct FontSetup { pub nerd_loaded: bool, pub styled: [bool; 3], pub family_found: bool }
+
+fn install_fonts(ctx: &Context, family: &str) -> FontSetup {
+    let mut fonts = egui::FontDefinitions::default();
+    let mut nerd_loaded = false;
+    for (idx, font_path) in find_icon_fonts().into_iter().enumerate() {
+        if let Ok(bytes) = std::fs::read(&font_path) {
+            let key = format!("IconFont{idx}");
+            if idx == 0 { nerd_loaded = true; }
+            fonts.font_data.insert(key.clone(), egui::FontData::from_owned(bytes));
+            fonts.families.entry(FontFamily::Monospace).or_default().insert(0, key.clone());
+            fonts.families.entry(FontFamily::Proportional).or_default().insert(0, key);
+        }
+    }
+    let primary = find_font_family(family);
+    let family_found = primary.as_ref().and_then(|p| std::fs::read(p).ok()).map(|bytes| {
+        fonts.font_data.insert("TermFamily".into(), egui::FontData::from_owned(bytes));
+        fonts.families.entry(FontFamily::Monospace).or_default().insert(0, "TermFamily".into());
+    }).is_some();
+    if !family_found && wants_custom_font(family) {
+        log::warn!("font family {family:?} not found, using the built-in monospace font");
+    }
+    let mut styled = [false; 3];
+    let mono_chain = fonts.families.get(&FontFamily::Monospace).cloned().unwrap_or_default();
+    for (slot, path) in find_styled_mono_fonts(primary.filter(|_| family_found).as_deref()).into_iter().enumerate() {
+        let Some(bytes) = path.and_then(|p| std::fs::read(p).ok()) else { continue };
+        let name = TERM_STYLE_FAMILIES[slot].to_string();
+        fonts.font_data.insert(name.clone(), egui::FontData::from_owned(bytes));
+        let mut chain = mono_chain.clone();
+        chain.insert(0, name.clone());
+        fonts.families.insert(FontFamily::Name(name.into()), chain);
+        styled[slot] = true;
+    }
+    ctx.set_fonts(fonts);
+    FontSetup { nerd_loaded, styled, family_found }
+}
+
+fn find_styled_mono_fonts(primary: Option<&Path>) -> [Option<PathBuf>; 3] {
+    let Some(regular) = primary.map(Path::to_path_buf).or_else(|| find_icon_fonts().into_iter().next()) else { return Default::default() };
+    styled_font_candidates(&regular).map(|candidates| candidates.into_iter().find(|p| p.exists()))
+}
+
+fn styled_font_candidates(regular: &Path) -> [Vec<PathBuf>; 3] {
+    let stem = regular.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let ext = regular.extension().and_then(|e| e.to_str()).unwrap_or("ttf");
+    let base = stem.strip_suffix("Regular")
+        .or_else(|| stem.strip_suffix('R').filter(|b| b.ends_with('-')))
+        .map_or_else(|| format!("{stem}-"), str::to_string);
+    [&["Bold", "B"][..], &["Italic", "Oblique", "RI", "I"], &["BoldItalic", "BoldOblique", "BI"]]
+        .map(|styles| styles.iter().map(|style| regular.with_file_name(format!("{base}{style}.{ext}"))).collect())
+}
+
+fn load_downscaled(path: &PathBuf, max_side: u32) -> Option<image::DynamicImage> {
+    let img = image::open(path).ok()?;
+    if img.width() > max_side || img.height() > max_side { Some(img.thumbnail(max_side, max_side)) } else { Some(img) }
+}
+
+fn image_from_path(path: &PathBuf) -> Option<ColorImage> {
+    let img = load_downscaled(path, 2048)?.to_rgba8();
     let (w, h) = img.dimensions();
     Some(ColorImage::from_rgba_unmultiplied([w as usize, h as usize], img.as_raw()))
 }
 
+const GIF_MAX_FRAMES: usize = 300;
+
+fn gif_frames_from_path(path: &Path) -> Option<Vec<(ColorImage, Duration)>> {
+    use image::AnimationDecoder;
+    let file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+    let decoder = image::codecs::gif::GifDecoder::new(file).ok()?;
+    let frames: Vec<_> = decoder.into_frames().take(GIF_MAX_FRAMES).map_while(Result::ok).collect();
+    if frames.len() < 2 { return None; }
+    Some(frames.into_iter().map(|frame| {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let ms = numer.checked_div(denom).unwrap_or(0);
+        let delay = Duration::from_millis(if ms <= 10 { 100 } else { ms as u64 });
+        let mut img = frame.into_buffer();
+        if img.width() > 2048 || img.height() > 2048 {
+            img = image::DynamicImage::ImageRgba8(img).thumbnail(2048, 2048).to_rgba8();
+        }
+        let (w, h) = img.dimensions();
+        (ColorImage::from_rgba_unmultiplied([w as usize, h as usize], img.as_raw()), delay)
+    }).collect())
+}
+
+type GifDecode = Receiver<Vec<(ColorImage, Duration)>>;
+
+fn spawn_gif_decode(path: &Path) -> Option<GifDecode> {
+    let is_gif = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+    if !is_gif { return None; }
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    let path = path.to_path_buf();
+    thread::spawn(move || { let _ = tx.send(gif_frames_from_path(&path).unwrap_or_default()); });
+    Some(rx)
+}
+
+fn poll_gif_decode(ctx: &Context, name: &str, decode: &mut Option<GifDecode>) -> Option<Vec<(TextureHandle, Duration)>> {
+    let frames = match decode.as_ref()?.try_recv() {
+        Ok(frames) => frames,
+        Err(crossbeam_channel::TryRecvError::Empty) => {
+            ctx.request_repaint_after(Duration::from_millis(50));
+            return None;
+        }
+        Err(crossbeam_channel::TryRecvError::Disconnected) => Vec::new(),
+    };
+    *decode = None;
+    Some(frames.into_iter()
+        .enumerate()
+        .map(|(i, (ci, delay))| (ctx.load_texture(format!("{name}-{i}"), ci, TextureOptions::LINEAR), delay))
+        .collect())
+}
+
+fn animation_frame(frames: &[(TextureHandle, Duration)], t: f32) -> Option<(&TextureHandle, Duration)> {
+    let total: Duration = frames.iter().map(|(_, d)| *d).sum();
+    if total.is_zero() { return None; }
+    let mut at = Duration::from_secs_f64((t as f64).max(0.0) % total.as_secs_f64());
+    for (tex, delay) in frames {
+        if at < *delay { return Some((tex, *delay - at)); }
+        at -= *delay;
+    }
+    frames.last().map(|(tex, delay)| (tex, *delay))
+}
+
+fn encode_image_for_ai(path: &PathBuf) -> Option<String> {
+    let img = load_downscaled(path, 1024)?;
+    let mut png = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).ok()?;
+    Some(base64::engine::general_purpose::STANDARD.encode(png))
+}
+
 fn video_poster_path(path: &PathBuf) -> PathBuf {
     let mut hasher = DefaultHasher::new();
     path.hash(&mut hasher);
@@ -95,6 +391,55 @@ fn extract_video_poster(path: &PathBuf) -> Option<ColorImage> {
     image_from_path(&out)
 }
 
+const VIDEO_MAX_SIDE: usize = 960;
+const VIDEO_FPS: u32 = 24;
+
+struct VideoPlayer {
+    child:  OsChild,
+    frames: Receiver<ColorImage>,
+}
+
+impl VideoPlayer {
+    fn spawn(path: &Path, poster_size: [usize; 2]) -> Option<Self> {
+        let scale = (VIDEO_MAX_SIDE as f32 / poster_size[0].max(poster_size[1]).max(1) as f32).min(1.0);
+        let w = ((poster_size[0] as f32 * scale) as usize / 2 * 2).max(2);
+        let h = ((poster_size[1] as f32 * scale) as usize / 2 * 2).max(2);
+        let mut child = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-stream_loop", "-1", "-re", "-i"])
+            .arg(path)
+            .args(["-an", "-vf", &format!("fps={VIDEO_FPS},scale={w}:{h}"), "-f", "rawvideo", "-pix_fmt", "rgba", "pipe:1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut stdout = child.stdout.take()?;
+        let (tx, rx) = crossbeam_channel::bounded(2);
+        thread::spawn(move || {
+            let mut buf = vec![0u8; w * h * 4];
+            while stdout.read_exact(&mut buf).is_ok() {
+                if tx.send(ColorImage::from_rgba_unmultiplied([w, h], &buf)).is_err() { break; }
+            }
+        });
+        Some(Self { child, frames: rx })
+    }
+
+    fn latest_frame(&self) -> Option<ColorImage> {
+        self.frames.try_iter().last()
+    }
+
+    fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for VideoPlayer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GradientStop {
     pub position: f32,
@@ -106,6 +451,31 @@ impl GradientStop {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BobDock {
+    #[default]
+    Floating,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellExitAction {
+    #[default]
+    Close,
+    Restart,
+    Hold,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Background {
     Solid([u8; 4]),
@@ -118,6 +488,7 @@ impl Default for Background {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Theme {
     pub background:      Background,
     pub foreground:      [u8; 4],
@@ -202,8 +573,141 @@ impl Theme {
         };
         Color32::from_rgba_unmultiplied(c[0], c[1], c[2], c[3])
     }
+    pub fn ansi_palette(&self) -> [[u8; 4]; 16] {
+        [
+            self.black, self.red, self.green, self.yellow,
+            self.blue, self.magenta, self.cyan, self.white,
+            self.bright_black, self.bright_red, self.bright_green, self.bright_yellow,
+            self.bright_blue, self.bright_magenta, self.bright_cyan, self.bright_white,
+        ]
+    }
+    pub fn generate(seed: u64) -> Self {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            ((z ^ (z >> 31)) >> 40) as f32 / (1u64 << 24) as f32
+        };
+        let base = next() * 360.0;
+        let tint = 0.25 + next() * 0.35;
+        let sat = 0.45 + next() * 0.35;
+        let light = 0.62 + next() * 0.1;
+        let pull = next() * 0.25;
+        let bg = hsl_to_rgba(base, tint, 0.06 + next() * 0.06);
+        let fg = lighten_until_contrast(base, tint * 0.4, 0.82, bg, MIN_TEXT_CONTRAST);
+        let mut palette = [[0u8; 4]; 16];
+        palette[0] = hsl_to_rgba(base, tint, 0.2);
+        palette[8] = lighten_until_contrast(base, tint * 0.6, 0.4, bg, 3.0);
+        palette[7] = hsl_to_rgba(base, tint * 0.3, 0.78);
+        palette[15] = hsl_to_rgba(base, tint * 0.2, 0.94);
+        for (i, hue) in [(1, 355.0), (2, 120.0), (3, 48.0), (4, 220.0), (5, 295.0), (6, 185.0)] {
+            let delta = ((base - hue + 540.0) % 360.0) - 180.0;
+            let h = hue + delta * pull;
+            palette[i] = lighten_until_contrast(h, sat, light, bg, MIN_ANSI_CONTRAST);
+            palette[i + 8] = lighten_until_contrast(h, (sat + 0.15).min(1.0), light + 0.1, bg, MIN_ANSI_CONTRAST);
+        }
+        let mut theme = Self {
+            background:      Background::Solid(bg),
+            foreground:      fg,
+            cursor_color:    palette[12],
+            selection_color: { let c = palette[4]; [c[0], c[1], c[2], 100] },
+            ..Self::default()
+        };
+        theme.set_ansi_palette(&palette);
+        theme
+    }
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: ThemeFile = serde_json::from_str(&data).with_context(|| format!("{} is not a valid theme", path.display()))?;
+        if file.schema_version > THEME_SCHEMA_VERSION {
+            anyhow::bail!("{} was made by a newer Spiltixal (theme schema {})", path.display(), file.schema_version);
+        }
+        Ok(file.theme)
+    }
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file = ThemeFile { schema_version: THEME_SCHEMA_VERSION, theme: self.clone() };
+        let json = serde_json::to_string_pretty(&file).context("Failed to serialize theme")?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+    pub fn discover() -> Vec<(String, PathBuf)> {
+        let Ok(entries) = std::fs::read_dir(themes_dir()) else { return Vec::new() };
+        let mut found: Vec<(String, PathBuf)> = entries.flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")))
+            .filter_map(|p| Some((p.file_stem()?.to_str()?.to_string(), p)))
+            .collect();
+        found.sort_by_key(|(name, _)| name.to_lowercase());
+        found
+    }
+    pub fn import(src: &Path) -> Result<(String, Self)> {
+        let theme = Self::from_file(src)?;
+        let name = src.file_stem().and_then(|s| s.to_str()).context("Theme file needs a name")?.to_string();
+        let dir = themes_dir();
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let dest = dir.join(format!("{name}.json"));
+        if dest.exists() && src.canonicalize().ok() == dest.canonicalize().ok() { return Ok((name, theme)); }
+        let name = (1..).map(|n| if n == 1 { name.clone() } else { format!("{name}-{n}") })
+            .find(|candidate| !dir.join(format!("{candidate}.json")).exists())
+            .context("No free theme name")?;
+        let dest = dir.join(format!("{name}.json"));
+        std::fs::copy(src, &dest).with_context(|| format!("Failed to copy to {}", dest.display()))?;
+        Ok((name, theme))
+    }
+    pub fn set_ansi_palette(&mut self, p: &[[u8; 4]; 16]) {
+        [
+            self.black, self.red, self.green, self.yellow,
+            self.blue, self.magenta, self.cyan, self.white,
+            self.bright_black, self.bright_red, self.bright_green, self.bright_yellow,
+            self.bright_blue, self.bright_magenta, self.bright_cyan, self.bright_white,
+        ] = *p;
+    }
+}
+
+const MIN_TEXT_CONTRAST: f32 = 7.0;
+const MIN_ANSI_CONTRAST: f32 = 4.5;
+
+fn hsl_to_rgba(h: f32, s: f32, l: f32) -> [u8; 4] {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let hp = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (hp % 2.0 - 1.0).abs());
+    let (r, g, b) = match hp as u32 {
+        0 => (c, x, 0.0), 1 => (x, c, 0.0), 2 => (0.0, c, x),
+        3 => (0.0, x, c), 4 => (x, 0.0, c), _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    let to = |v: f32| ((v + m).clamp(0.0, 1.0) * 255.0).round() as u8;
+    [to(r), to(g), to(b), 255]
+}
+
+fn relative_luminance(c: [u8; 4]) -> f32 {
+    let lin = |v: u8| {
+        let v = v as f32 / 255.0;
+        if v <= 0.03928 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    0.2126 * lin(c[0]) + 0.7152 * lin(c[1]) + 0.0722 * lin(c[2])
+}
+
+fn contrast_ratio(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let (la, lb) = (relative_luminance(a), relative_luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+fn lighten_until_contrast(h: f32, s: f32, mut l: f32, bg: [u8; 4], min: f32) -> [u8; 4] {
+    let mut c = hsl_to_rgba(h, s, l);
+    while contrast_ratio(c, bg) < min && l < 0.97 {
+        l += 0.02;
+        c = hsl_to_rgba(h, s, l);
+    }
+    c
 }
 
+const ANSI_COLOR_NAMES: [&str; 8] = ["Black", "Red", "Green", "Yellow", "Blue", "Magenta", "Cyan", "White"];
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     pub theme:                Theme,
@@ -222,19 +726,91 @@ pub struct Config {
     pub theme_preset:         String,
     #[serde(default)]
     pub install_prompt_done:  bool,
+    #[serde(default = "default_terminal_padding")]
+    pub terminal_padding:     f32,
+    #[serde(default = "default_line_height_factor")]
+    pub line_height_factor:   f32,
+    #[serde(default)]
+    pub editor_command:       String,
+    #[serde(default)]
+    pub prompt_regex:         String,
+    #[serde(default)]
+    pub login_shell:          bool,
+    #[serde(default)]
+    pub shell_args:           Vec<String>,
+    #[serde(default)]
+    pub working_directory:    String,
+    #[serde(default)]
+    pub env:                  Vec<(String, String)>,
+    #[serde(default)]
+    pub on_shell_exit:        ShellExitAction,
+    #[serde(default = "default_confirm_close")]
+    pub confirm_close:        bool,
+    #[serde(default)]
+    pub log_output_path:      String,
+    #[serde(default)]
+    pub term:                 String,
+    #[serde(default = "default_typing_speed_ms")]
+    pub typing_speed_ms:      Option<u64>,
+    #[serde(default)]
+    pub reduce_motion:        bool,
+    #[serde(default)]
+    pub animate_video_background: bool,
+    #[serde(default = "default_bob_width")]
+    pub bob_width:            f32,
+    #[serde(default = "default_bob_height")]
+    pub bob_height:           f32,
+    #[serde(default)]
+    pub bob_dock:             BobDock,
+    #[serde(default)]
+    pub dim_on_unfocus:       bool,
+    #[serde(default = "default_unfocused_opacity")]
+    pub unfocused_opacity:    f32,
+    #[serde(default)]
+    pub show_timestamps:      bool,
+    #[serde(default = "default_scroll_on_input")]
+    pub scroll_on_input:      bool,
+    #[serde(default = "default_blink_rate_hz")]
+    pub blink_rate_hz:        f32,
+    #[serde(default)]
+    pub cursor_shape:         CursorShape,
+    #[serde(default)]
+    pub danger_rules:         Vec<DangerRuleConfig>,
+    #[serde(default)]
+    pub disabled_danger_rules: Vec<String>,
+    #[serde(default)]
+    pub ai_backend:           AiBackend,
+    #[serde(default)]
+    pub ai_api_key:           String,
+    #[serde(default = "default_ai_context_chars")]
+    pub ai_context_chars:     usize,
+    #[serde(default)]
+    pub ai_vision:            bool,
 }
+
+fn default_terminal_padding() -> f32 { 6.0 }
+fn default_line_height_factor() -> f32 { 1.0 }
+fn default_typing_speed_ms() -> Option<u64> { Some(18) }
+fn default_bob_width() -> f32 { 430.0 }
+fn default_bob_height() -> f32 { 520.0 }
+fn default_unfocused_opacity() -> f32 { 0.6 }
+fn default_blink_rate_hz() -> f32 { 1.0 }
+fn default_ai_context_chars() -> usize { 12_000 }
+fn default_confirm_close() -> bool { true }
+fn default_scroll_on_input() -> bool { true }
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             theme:            Theme::default(),
             shell:            std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".into()),
             ai_enabled:       false,
-            ai_endpoint:      "http://localhost:11434/api/generate".into(),
+            ai_endpoint:      "http://localhost:11434/api/chat".into(),
             ai_model:         "qwen2.5:0.5b".into(),
             ai_system_prompt: "You are Bob inside a terminal app called Spiltixal. \
                                You can see what's on the terminal screen when the user asks something. \
                                You are attached to the live PTY terminal and allowed to run commands through user-approved actions. \
-                               Supported direct actions are /run <command>, /ctrl c, /ctrl z, /ctrl \\\\, and /signal <INT|TSTP|QUIT>. \
+                               Supported direct actions are /run <command>, /cd <path>, /ctrl c, /ctrl z, /ctrl \\\\, and /signal <INT|TSTP|QUIT>. \
                                You can analyze files, code, images and videos when given their paths or content. \
                                Keep responses short, direct, and practical. Plain text only. \
                                When analyzing code or files, \
@@ -247,72 +823,200 @@ impl Default for Config {
             custom_mate_thinking: None,
             theme_preset:         "Default".into(),
             install_prompt_done:  false,
+            terminal_padding:     default_terminal_padding(),
+            line_height_factor:   default_line_height_factor(),
+            editor_command:       String::new(),
+            prompt_regex:         String::new(),
+            login_shell:          false,
+            shell_args:           Vec::new(),
+            working_directory:    String::new(),
+            env:                  Vec::new(),
+            on_shell_exit:        ShellExitAction::Close,
+            confirm_close:        default_confirm_close(),
+            log_output_path:      String::new(),
+            term:                 String::new(),
+            typing_speed_ms:      default_typing_speed_ms(),
+            reduce_motion:        false,
+            animate_video_background: false,
+            bob_width:            default_bob_width(),
+            bob_height:           default_bob_height(),
+            bob_dock:             BobDock::Floating,
+            dim_on_unfocus:       false,
+            unfocused_opacity:    default_unfocused_opacity(),
+            show_timestamps:      false,
+            scroll_on_input:      default_scroll_on_input(),
+            blink_rate_hz:        default_blink_rate_hz(),
+            cursor_shape:         CursorShape::Block,
+            danger_rules:         Vec::new(),
+            disabled_danger_rules: Vec::new(),
+            ai_backend:           AiBackend::Ollama,
+            ai_api_key:           String::new(),
+            ai_context_chars:     default_ai_context_chars(),
+            ai_vision:            false,
         }
     }
 }
 impl Config {
+    pub fn blink_half_period(&self) -> Option<f32> {
+        (!self.reduce_motion && self.blink_rate_hz > 0.0).then(|| 0.5 / self.blink_rate_hz)
+    }
+    pub fn typing_interval(&self) -> Option<Duration> {
+        if self.reduce_motion { return None; }
+        self.typing_speed_ms.filter(|ms| *ms > 0).map(Duration::from_millis)
+    }
     pub fn load() -> Self {
+        if !Self::path().exists() { return Self::default(); }
+        Self::read_from_disk().unwrap_or_default()
+    }
+    pub fn read_from_disk() -> Result<Self> {
         let path = Self::path();
-        if path.exists() {
-            if let Ok(data) = std::fs::read_to_string(&path) {
-                if let Ok(mut c) = serde_json::from_str::<Config>(&data) {
-                    if c.theme_preset == "Cosmic Purple" {
-                        c.theme_preset = "1".into();
-                        c.save();
-                    }
-                    return c;
-                }
-            }
+        let data = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut c = serde_json::from_str::<Config>(&data).with_context(|| format!("Failed to parse {}", path.display()))?;
+        if c.theme_preset == "Cosmic Purple" {
+            c.theme_preset = "1".into();
+            c.save();
         }
-        Self::default()
+        match parse_ai_endpoint(&c.ai_endpoint) {
+            Ok(url) => c.ai_endpoint = url.to_string(),
+            Err(e)  => log::warn!("{e:#}"),
+        }
+        Ok(c)
+    }
+    pub fn modified() -> Option<std::time::SystemTime> {
+        std::fs::metadata(Self::path()).and_then(|m| m.modified()).ok()
     }
     pub fn save(&self) {
         if let Some(dir) = Self::path().parent() { let _ = std::fs::create_dir_all(dir); }
         if let Ok(json) = serde_json::to_string_pretty(self) { let _ = std::fs::write(Self::path(), json); }
     }
     fn path() -> PathBuf {
-        dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("spiltixal").join("config.json")
+        CONFIG_PATH_OVERRIDE.get().cloned().unwrap_or_else(|| {
+            profile_dir(&active_profile()).unwrap_or_else(app_config_dir).join("config.json")
+        })
+    }
+    pub fn dir() -> PathBuf {
+        Self::path().parent().map(Path::to_path_buf).unwrap_or_else(app_config_dir)
     }
 }
 
-struct DangerRule { pattern: Regex, reason: &'static str }
+struct DangerRule { id: &'static str, pattern: Regex, reason: &'static str }
 
 lazy_static! {
     static ref DANGER_RULES: Vec<DangerRule> = vec![
-        DangerRule { pattern: Regex::new(r"(?i)sudo\s+rm\s+-[a-z]*rf?\s+/").unwrap(),
+        DangerRule { id: "sudo-rm-root", pattern: Regex::new(r"(?i)sudo\s+rm\s+-[a-z]*rf?\s+/").unwrap(),
             reason: "Recursively removes files from the root filesystem with elevated privileges." },
-        DangerRule { pattern: Regex::new(r"(?i)rm\s+-[a-z]*rf?\s+/\*?").unwrap(),
+        DangerRule { id: "rm-root", pattern: Regex::new(r"(?i)rm\s+-[a-z]*rf?\s+/\*?").unwrap(),
             reason: "Recursively removes files starting from the root directory or its contents." },
-        DangerRule { pattern: Regex::new(r"(?i)dd\s+.*if=/dev/zero\s+.*of=/dev/").unwrap(),
+        DangerRule { id: "dd-zero-device", pattern: Regex::new(r"(?i)dd\s+.*if=/dev/zero\s+.*of=/dev/").unwrap(),
             reason: "Overwrites a block device with zeros — destroys all data on the drive." },
-        DangerRule { pattern: Regex::new(r"(?i)dd\s+.*of=/dev/(sd|nvme|hd)[a-z]").unwrap(),
+        DangerRule { id: "dd-device", pattern: Regex::new(r"(?i)dd\s+.*of=/dev/(sd|nvme|hd)[a-z]").unwrap(),
             reason: "Writes directly to a block device, which can destroy data irreversibly." },
-        DangerRule { pattern: Regex::new(r"(?i)mv\s+/\s+/dev/null").unwrap(),
+        DangerRule { id: "mv-root-null", pattern: Regex::new(r"(?i)mv\s+/\s+/dev/null").unwrap(),
             reason: "Moves the entire root filesystem into /dev/null, destroying all data." },
-        DangerRule { pattern: Regex::new(r":\(\)\{:\|:&\};:").unwrap(),
+        DangerRule { id: "fork-bomb", pattern: Regex::new(r":\(\)\{:\|:&\};:").unwrap(),
             reason: "Fork bomb — creates processes exponentially until the system crashes." },
-        DangerRule { pattern: Regex::new(r"echo\s+[bBsSuU]\s*>\s*/proc/sysrq-trigger").unwrap(),
+        DangerRule { id: "sysrq-trigger", pattern: Regex::new(r"echo\s+[bBsSuU]\s*>\s*/proc/sysrq-trigger").unwrap(),
             reason: "Triggers a kernel SysRq event (reboot/poweroff/crash) immediately." },
-        DangerRule { pattern: Regex::new(r"(?i)sudo\s+rm\s+-[a-z]*rf?\s+/etc/fstab").unwrap(),
+        DangerRule { id: "rm-fstab", pattern: Regex::new(r"(?i)sudo\s+rm\s+-[a-z]*rf?\s+/etc/fstab").unwrap(),
             reason: "Deletes the filesystem table — the system will not boot properly." },
-        DangerRule { pattern: Regex::new(r"(?i)chmod\s+-R\s+777\s+/").unwrap(),
+        DangerRule { id: "chmod-777-root", pattern: Regex::new(r"(?i)chmod\s+-R\s+777\s+/").unwrap(),
             reason: "Grants full permissions to every file on the system — massive security hole." },
-        DangerRule { pattern: Regex::new(r"(?i)mkfs\.(ext[234]|btrfs|xfs|vfat)\s+/dev/(sd|nvme|hd)[a-z]").unwrap(),
+        DangerRule { id: "mkfs-device", pattern: Regex::new(r"(?i)mkfs\.(ext[234]|btrfs|xfs|vfat)\s+/dev/(sd|nvme|hd)[a-z]").unwrap(),
             reason: "Formats a block device, erasing all data on it." },
-        DangerRule { pattern: Regex::new(r"(?i)(sudo\s+)?shred\s+-[a-z]*z?\s+/dev/(sd|nvme|hd)[a-z]").unwrap(),
+        DangerRule { id: "shred-device", pattern: Regex::new(r"(?i)(sudo\s+)?shred\s+-[a-z]*z?\s+/dev/(sd|nvme|hd)[a-z]").unwrap(),
             reason: "Securely erases a block device — all data is unrecoverable." },
-        DangerRule { pattern: Regex::new(r"(?i)>\s*/dev/(sd|nvme|hd)[a-z]").unwrap(),
+        DangerRule { id: "redirect-device", pattern: Regex::new(r"(?i)>\s*/dev/(sd|nvme|hd)[a-z]").unwrap(),
             reason: "Redirects output directly to a block device, overwriting its contents." },
     ];
 }
 
-fn check_dangerous(command: &str) -> Option<&'static str> {
-    for rule in DANGER_RULES.iter() {
-        if rule.pattern.is_match(command.trim()) { return Some(rule.reason); }
+lazy_static! {
+    static ref ERROR_LINE_RE: Regex = Regex::new(r"(?i)\b(error|failed|fatal|panicked|exception|traceback|segmentation fault)\b").unwrap();
+}
+
+lazy_static! {
+    static ref FILE_LINK_RE: Regex = Regex::new(r"([\w.~/+\-]+):(\d+)(?::(\d+))?").unwrap();
+    static ref URL_RE: Regex = Regex::new(r#"https?://[^\s<>"'`]+"#).unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub struct UrlLink { pub url: String, pub spans: Vec<(usize, usize, usize)> }
+impl UrlLink {
+    pub fn covers(&self, row: usize, col: usize) -> bool {
+        self.spans.iter().any(|&(r, start, end)| r == row && col >= start && col < end)
+    }
+}
+
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let Some(last) = url.chars().last() else { return url };
+        let unbalanced = match last {
+            ')' => url.matches(')').count() > url.matches('(').count(),
+            ']' => url.matches(']').count() > url.matches('[').count(),
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' => true,
+            _ => false,
+        };
+        if !unbalanced { return url; }
+        url = &url[..url.len() - last.len_utf8()];
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FileLink { pub row: usize, pub start_col: usize, pub end_col: usize, pub path: PathBuf, pub line: u32, pub col: u32 }
+
+fn find_file_link(line: &str, col: usize, row: usize, cwd: Option<&Path>) -> Option<FileLink> {
+    for caps in FILE_LINK_RE.captures_iter(line) {
+        let whole = caps.get(0)?;
+        let start_col = line[..whole.start()].chars().count();
+        let end_col = start_col + whole.as_str().chars().count();
+        if col < start_col || col >= end_col { continue; }
+        let raw = caps.get(1)?.as_str();
+        let path = expand_tilde(raw);
+        let path = if path.is_absolute() { path } else { cwd?.join(path) };
+        if !path.is_file() { return None; }
+        let line_no = caps.get(2)?.as_str().parse().ok()?;
+        let col_no = caps.get(3).and_then(|c| c.as_str().parse().ok()).unwrap_or(1);
+        return Some(FileLink { row, start_col, end_col, path, line: line_no, col: col_no });
     }
     None
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DangerRuleConfig {
+    pub pattern: String,
+    pub reason:  String,
+    #[serde(default = "default_rule_enabled")]
+    pub enabled: bool,
+}
+
+fn default_rule_enabled() -> bool { true }
+
+pub struct DangerRules { rules: Vec<(Regex, String)> }
+impl DangerRules {
+    pub fn compile(config: &Config) -> (Self, Vec<String>) {
+        let mut rules: Vec<(Regex, String)> = DANGER_RULES.iter()
+            .filter(|r| !config.disabled_danger_rules.iter().any(|id| id == r.id))
+            .map(|r| (r.pattern.clone(), r.reason.to_string()))
+            .collect();
+        let mut errors = Vec::new();
+        for rule in config.danger_rules.iter().filter(|r| r.enabled) {
+            match Regex::new(&rule.pattern) {
+                Ok(re) => rules.push((re, rule.reason.clone())),
+                Err(e) => errors.push(format!("`{}`: {}", rule.pattern, e.to_string().lines().last().unwrap_or("invalid pattern").trim())),
+            }
+        }
+        (Self { rules }, errors)
+    }
+    pub fn check(&self, command: &str) -> Option<String> {
+        self.rules.iter().find(|(re, _)| re.is_match(command.trim())).map(|(_, reason)| reason.clone())
+    }
+}
+
+fn danger_rule_errors_message(errors: &[String]) -> String {
+    format!("ignored {} invalid danger rule(s): {}", errors.len(), errors.join("; "))
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TermColor { Default, Ansi(u8), Ansi256(u8), Rgb(u8, u8, u8) }
 impl TermColor {
@@ -352,10 +1056,43 @@ fn ansi256_to_color32(idx: u8) -> Color32 {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+impl UnderlineStyle {
+    pub fn from_sgr(sub: Option<u16>) -> Self {
+        match sub {
+            Some(0) => UnderlineStyle::None,
+            Some(2) => UnderlineStyle::Double,
+            Some(3) => UnderlineStyle::Curly,
+            Some(4) => UnderlineStyle::Dotted,
+            Some(5) => UnderlineStyle::Dashed,
+            _ => UnderlineStyle::Single,
+        }
+    }
+    pub fn css(self) -> Option<&'static str> {
+        match self {
+            UnderlineStyle::Double => Some("double"),
+            UnderlineStyle::Curly  => Some("wavy"),
+            UnderlineStyle::Dotted => Some("dotted"),
+            UnderlineStyle::Dashed => Some("dashed"),
+            UnderlineStyle::None | UnderlineStyle::Single => None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Attrs {
-    pub bold: bool, pub dim: bool, pub italic: bool, pub underline: bool,
+    pub bold: bool, pub dim: bool, pub italic: bool, pub underline: UnderlineStyle,
     pub blink: bool, pub reverse: bool, pub invisible: bool, pub strikeout: bool,
+    pub underline_color: Option<TermColor>,
 }
 
 #[derive(Clone, Debug)]
@@ -367,39 +1104,224 @@ impl Default for Cell {
         Self { ch: ' ', fg: TermColor::Default, bg: TermColor::Default, attrs: Attrs::default(), width: 1 }
     }
 }
+impl Cell {
+    pub fn is_blank(&self) -> bool {
+        self.ch == ' ' && self.width == 1 && self.bg == TermColor::Default && self.attrs == Attrs::default()
+    }
+}
+
+type ReflowLine = (Vec<Cell>, Option<DateTime<Local>>, bool, LineSize);
 
 pub struct Grid {
     pub rows: usize, pub cols: usize,
     pub cells: Vec<Vec<Cell>>,
     pub cursor_x: usize, pub cursor_y: usize,
     pub scroll_top: usize, pub scroll_bot: usize,
-    pub scrollback: Vec<Vec<Cell>>,
+    pub scrollback: VecDeque<Vec<Cell>>,
     pub max_scrollback: usize,
     pub scroll_offset: usize,
+    pub evicted_lines: usize,
+    pub stamp_lines: bool,
+    pub line_stamps: Vec<Option<DateTime<Local>>>,
+    pub scrollback_stamps: VecDeque<Option<DateTime<Local>>>,
+    pub line_wrapped: Vec<bool>,
+    pub scrollback_wrapped: VecDeque<bool>,
+    pub line_sizes: Vec<LineSize>,
+    pub scrollback_sizes: VecDeque<LineSize>,
+    pub alt_screen: bool,
+    pub saved_cursor: Option<SavedCursor>,
+    pub tab_stops: Vec<bool>,
+    pub cursor_visible_app: bool,
+    pub bracketed_paste: bool,
+    pub app_cursor_keys: bool,
+    pub mouse_mode: MouseMode,
+    pub mouse_sgr: bool,
+    pub charsets: [Charset; 2],
+    pub active_charset: usize,
+    pub cursor_style: Option<(CursorShape, bool)>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineSize {
+    #[default]
+    Single,
+    DoubleWidth,
+    DoubleTop,
+    DoubleBottom,
+}
+impl LineSize {
+    pub fn is_double(self) -> bool { self != LineSize::Single }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    DecSpecial,
+}
+
+const DEC_SPECIAL_GRAPHICS: [char; 31] = [
+    '◆', '▒', '␉', '␌', '␍', '␊', '°', '±', '␤', '␋', '┘', '┐', '┌', '└', '┼', '⎺',
+    '⎻', '─', '⎼', '⎽', '├', '┤', '┴', '┬', '│', '≤', '≥', 'π', '≠', '£', '·',
+];
+
+impl Charset {
+    pub fn translate(self, ch: char) -> char {
+        match (self, ch) {
+            (Charset::DecSpecial, '\u{60}'..='\u{7e}') => DEC_SPECIAL_GRAPHICS[ch as usize - 0x60],
+            _ => ch,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseMode {
+    #[default]
+    Off,
+    Click,
+    Drag,
+    Motion,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SavedCursor {
+    pub x: usize, pub y: usize,
+    pub fg: TermColor, pub bg: TermColor, pub attrs: Attrs,
 }
 impl Grid {
     pub fn new(rows: usize, cols: usize, max_scrollback: usize) -> Self {
         Self {
             rows, cols, cells: vec![vec![Cell::default(); cols]; rows],
             cursor_x: 0, cursor_y: 0, scroll_top: 0, scroll_bot: rows.saturating_sub(1),
-            scrollback: Vec::new(), max_scrollback, scroll_offset: 0,
+            scrollback: VecDeque::new(), max_scrollback, scroll_offset: 0, evicted_lines: 0,
+            stamp_lines: false, line_stamps: vec![None; rows], scrollback_stamps: VecDeque::new(),
+            line_wrapped: vec![false; rows], scrollback_wrapped: VecDeque::new(),
+            line_sizes: vec![LineSize::Single; rows], scrollback_sizes: VecDeque::new(),
+            alt_screen: false, saved_cursor: None, tab_stops: (0..cols).map(|c| c % 8 == 0).collect(),
+            cursor_visible_app: true, bracketed_paste: false, app_cursor_keys: false,
+            mouse_mode: MouseMode::Off, mouse_sgr: false,
+            charsets: [Charset::Ascii; 2], active_charset: 0, cursor_style: None,
         }
     }
+    pub fn resize_buffer(cells: &mut Vec<Vec<Cell>>, stamps: &mut Vec<Option<DateTime<Local>>>, wrapped: &mut Vec<bool>, sizes: &mut Vec<LineSize>, new_rows: usize, new_cols: usize) {
+        for row in cells.iter_mut() { row.resize(new_cols, Cell::default()); }
+        cells.resize(new_rows, vec![Cell::default(); new_cols]);
+        stamps.resize(new_rows, None);
+        wrapped.resize(new_rows, false);
+        sizes.resize(new_rows, LineSize::Single);
+    }
     pub fn resize(&mut self, new_rows: usize, new_cols: usize) {
-        for row in &mut self.cells { row.resize(new_cols, Cell::default()); }
-        if new_rows > self.rows {
-            for _ in 0..(new_rows - self.rows) { self.cells.push(vec![Cell::default(); new_cols]); }
-        } else { self.cells.truncate(new_rows); }
-        self.rows = new_rows; self.cols = new_cols;
-        self.scroll_bot = new_rows.saturating_sub(1);
+        Self::resize_buffer(&mut self.cells, &mut self.line_stamps, &mut self.line_wrapped, &mut self.line_sizes, new_rows, new_cols);
+        self.set_size(new_rows, new_cols);
         self.cursor_x = self.cursor_x.min(new_cols.saturating_sub(1));
         self.cursor_y = self.cursor_y.min(new_rows.saturating_sub(1));
     }
+    pub fn reflow(&mut self, new_rows: usize, new_cols: usize) -> Vec<usize> {
+        let old_cols = self.cols;
+        let cursor_line = self.scrollback.len() + self.cursor_y;
+        let scrollback = std::mem::take(&mut self.scrollback).into_iter()
+            .zip(std::mem::take(&mut self.scrollback_stamps))
+            .zip(std::mem::take(&mut self.scrollback_wrapped))
+            .zip(std::mem::take(&mut self.scrollback_sizes));
+        let screen = std::mem::take(&mut self.cells).into_iter()
+            .zip(std::mem::take(&mut self.line_stamps))
+            .zip(std::mem::take(&mut self.line_wrapped))
+            .zip(std::mem::take(&mut self.line_sizes));
+        let mut lines: Vec<ReflowLine> = scrollback.chain(screen)
+            .map(|(((row, stamp), wrapped), size)| (row, stamp, wrapped, size)).collect();
+        let old_total = lines.len();
+        while lines.len() > cursor_line + 1 && lines.last().is_some_and(|(row, ..)| row.iter().all(Cell::is_blank)) { lines.pop(); }
+        let line_cols = |cols: usize, size: LineSize| if size.is_double() { (cols / 2).max(1) } else { cols };
+
+        let mut out: Vec<ReflowLine> = Vec::new();
+        let mut remap = Vec::with_capacity(old_total + 1);
+        let (mut new_cx, mut new_cy) = (0, 0);
+        let mut i = 0;
+        while i < lines.len() {
+            let start = i;
+            let size = lines[start].3;
+            while i + 1 < lines.len() && lines[i].2 && lines[i + 1].3 == size { i += 1; }
+            let (old_width, new_width) = (line_cols(old_cols, size), line_cols(new_cols, size));
+            let mut logical: Vec<Cell> = Vec::new();
+            let mut cursor_offset = None;
+            let mut stamp = None;
+            for j in start..=i {
+                let row = &lines[j].0;
+                if j == cursor_line { cursor_offset = Some(logical.len() + self.cursor_x); }
+                let mut take = row.len().min(old_width);
+                let wide_next = j < i && lines[j + 1].0.first().is_some_and(|c| c.width == 2);
+                if wide_next && take > 0 && row[take - 1].is_blank() { take -= 1; }
+                logical.extend_from_slice(&row[..take]);
+                stamp = lines[j].1.or(stamp);
+            }
+            let mut len = logical.len();
+            while len > 0 && logical[len - 1].is_blank() { len -= 1; }
+            logical.truncate(len.max(cursor_offset.unwrap_or(0)));
+
+            let first_new = out.len();
+            let mut pos = 0;
+            loop {
+                let mut end = (pos + new_width).min(logical.len());
+                if end < logical.len() && end > pos + 1 && logical[end].width == 0 { end -= 1; }
+                let more = end < logical.len() || cursor_offset.is_some_and(|off| off >= end && end - pos == new_width);
+                if let Some(off) = cursor_offset {
+                    if off >= pos && (off < end || !more) { new_cy = out.len(); new_cx = off - pos; }
+                }
+                let mut row = logical[pos..end].to_vec();
+                row.resize(new_cols, Cell::default());
+                out.push((row, stamp, more, size));
+                pos = end;
+                if !more { break; }
+            }
+            let last_new = out.len() - 1;
+            remap.extend((0..=i - start).map(|k| (first_new + k * old_cols / new_cols.max(1)).min(last_new)));
+            i += 1;
+        }
+        remap.resize(old_total + 1, out.len());
+
+        let screen_start = out.len().saturating_sub(new_rows).min(new_cy);
+        let mut screen = out.split_off(screen_start);
+        screen.truncate(new_rows);
+        screen.resize(new_rows, (vec![Cell::default(); new_cols], None, false, LineSize::Single));
+        let excess = out.len().saturating_sub(self.max_scrollback);
+        out.drain(..excess);
+        self.evicted_lines += excess;
+
+        for (row, stamp, wrapped, size) in out {
+            self.scrollback.push_back(row);
+            self.scrollback_stamps.push_back(stamp);
+            self.scrollback_wrapped.push_back(wrapped);
+            self.scrollback_sizes.push_back(size);
+        }
+        for (row, stamp, wrapped, size) in screen {
+            self.cells.push(row);
+            self.line_stamps.push(stamp);
+            self.line_wrapped.push(wrapped);
+            self.line_sizes.push(size);
+        }
+        self.set_size(new_rows, new_cols);
+        self.cursor_x = new_cx.min(new_cols.saturating_sub(1));
+        self.cursor_y = (new_cy - screen_start).min(new_rows.saturating_sub(1));
+        self.scroll_offset = self.scroll_offset.min(self.scrollback.len());
+        remap
+    }
+    fn set_size(&mut self, new_rows: usize, new_cols: usize) {
+        let old_cols = self.tab_stops.len();
+        self.tab_stops.truncate(new_cols);
+        self.tab_stops.extend((old_cols..new_cols).map(|c| c % 8 == 0));
+        self.rows = new_rows; self.cols = new_cols;
+        self.scroll_top = 0;
+        self.scroll_bot = new_rows.saturating_sub(1);
+    }
+    pub fn line_cols(&self, y: usize) -> usize {
+        if self.line_sizes.get(y).is_some_and(|s| s.is_double()) { (self.cols / 2).max(1) } else { self.cols }
+    }
     pub fn put_char(&mut self, ch: char, fg: TermColor, bg: TermColor, attrs: Attrs) {
         if self.cursor_y >= self.rows { return; }
-        if self.cursor_x >= self.cols { self.cursor_x = 0; self.newline(); }
+        if self.cursor_x >= self.line_cols(self.cursor_y) { self.line_wrapped[self.cursor_y] = true; self.cursor_x = 0; self.newline(); }
         let width = UnicodeWidthChar::width(ch).unwrap_or(1).clamp(1, 2) as u8;
-        if width == 2 && self.cursor_x + 1 >= self.cols {
+        if width == 2 && self.cursor_x + 1 >= self.line_cols(self.cursor_y) {
+            self.line_wrapped[self.cursor_y] = true;
             self.cursor_x = 0;
             self.newline();
             if self.cursor_y >= self.rows { return; }
@@ -416,23 +1338,98 @@ impl Grid {
         }
     }
     pub fn newline(&mut self) {
+        if self.stamp_lines {
+            if let Some(stamp) = self.line_stamps.get_mut(self.cursor_y) { *stamp = Some(Local::now()); }
+        }
         if self.cursor_y >= self.scroll_bot { self.scroll_up(1); } else { self.cursor_y += 1; }
     }
     pub fn scroll_up(&mut self, n: usize) {
         for _ in 0..n {
             if !self.cells.is_empty() {
                 let evicted = self.cells.remove(self.scroll_top);
-                self.scrollback.push(evicted);
-                if self.scrollback.len() > self.max_scrollback { self.scrollback.remove(0); }
+                let stamp = self.line_stamps.remove(self.scroll_top);
+                let wrapped = self.line_wrapped.remove(self.scroll_top);
+                let size = self.line_sizes.remove(self.scroll_top);
+                if !self.alt_screen {
+                    self.scrollback.push_back(evicted);
+                    self.scrollback_stamps.push_back(stamp);
+                    self.scrollback_wrapped.push_back(wrapped);
+                    self.scrollback_sizes.push_back(size);
+                }
+                if self.scrollback.len() > self.max_scrollback {
+                    self.scrollback.pop_front();
+                    self.scrollback_stamps.pop_front();
+                    self.scrollback_wrapped.pop_front();
+                    self.scrollback_sizes.pop_front();
+                    self.evicted_lines += 1;
+                }
                 self.cells.insert(self.scroll_bot, vec![Cell::default(); self.cols]);
+                self.line_stamps.insert(self.scroll_bot, None);
+                self.line_wrapped.insert(self.scroll_bot, false);
+                self.line_sizes.insert(self.scroll_bot, LineSize::Single);
             }
         }
     }
     pub fn scroll_down(&mut self, n: usize) {
         for _ in 0..n {
-            if self.cells.len() > self.scroll_bot { self.cells.remove(self.scroll_bot); }
+            if self.cells.len() > self.scroll_bot {
+                self.cells.remove(self.scroll_bot);
+                self.line_stamps.remove(self.scroll_bot);
+                self.line_wrapped.remove(self.scroll_bot);
+                self.line_sizes.remove(self.scroll_bot);
+            }
             self.cells.insert(self.scroll_top, vec![Cell::default(); self.cols]);
+            self.line_stamps.insert(self.scroll_top, None);
+            self.line_wrapped.insert(self.scroll_top, false);
+            self.line_sizes.insert(self.scroll_top, LineSize::Single);
+        }
+    }
+    pub fn cursor_key_seq(&self, code: char) -> String {
+        if self.app_cursor_keys { format!("\x1bO{code}") } else { format!("\x1b[{code}") }
+    }
+    pub fn paste_seq(&self, text: &str) -> String {
+        if !self.bracketed_paste { return text.to_string(); }
+        let clean = text.replace("\x1b[201~", "").replace("\x1b[200~", "");
+        format!("\x1b[200~{clean}\x1b[201~")
+    }
+    pub fn next_tab_stop(&self) -> usize {
+        (self.cursor_x + 1..self.cols).find(|&c| self.tab_stops.get(c).copied().unwrap_or(false))
+            .unwrap_or(self.cols.saturating_sub(1))
+    }
+    pub fn insert_lines(&mut self, n: usize) {
+        let y = self.cursor_y;
+        if y < self.scroll_top || y > self.scroll_bot || self.scroll_bot >= self.cells.len() { return; }
+        for _ in 0..n.min(self.scroll_bot - y + 1) {
+            self.cells.remove(self.scroll_bot);
+            self.line_stamps.remove(self.scroll_bot);
+            self.line_wrapped.remove(self.scroll_bot);
+            self.line_sizes.remove(self.scroll_bot);
+            self.cells.insert(y, vec![Cell::default(); self.cols]);
+            self.line_stamps.insert(y, None);
+            self.line_wrapped.insert(y, false);
+            self.line_sizes.insert(y, LineSize::Single);
         }
+        self.cursor_x = 0;
+    }
+    pub fn delete_lines(&mut self, n: usize) {
+        let y = self.cursor_y;
+        if y < self.scroll_top || y > self.scroll_bot || self.scroll_bot >= self.cells.len() { return; }
+        for _ in 0..n.min(self.scroll_bot - y + 1) {
+            self.cells.remove(y);
+            self.line_stamps.remove(y);
+            self.line_wrapped.remove(y);
+            self.line_sizes.remove(y);
+            self.cells.insert(self.scroll_bot, vec![Cell::default(); self.cols]);
+            self.line_stamps.insert(self.scroll_bot, None);
+            self.line_wrapped.insert(self.scroll_bot, false);
+            self.line_sizes.insert(self.scroll_bot, LineSize::Single);
+        }
+        self.cursor_x = 0;
+    }
+    pub fn erase_chars(&mut self, n: usize, bg: TermColor) {
+        let (x, cols) = (self.cursor_x, self.cols);
+        let Some(row) = self.cells.get_mut(self.cursor_y) else { return };
+        for c in row.iter_mut().take(cols).skip(x).take(n) { *c = Cell { bg, ..Cell::default() }; }
     }
     pub fn erase_line(&mut self, mode: u8) {
         if self.cursor_y >= self.rows { return; }
@@ -450,22 +1447,174 @@ impl Grid {
             0 => {
                 self.erase_line(0);
                 for y in (self.cursor_y + 1)..self.rows { for c in &mut self.cells[y] { *c = Cell::default(); } }
+                for size in self.line_sizes.iter_mut().skip(self.cursor_y + 1) { *size = LineSize::Single; }
             }
             1 => {
                 for y in 0..self.cursor_y { for c in &mut self.cells[y] { *c = Cell::default(); } }
+                for size in self.line_sizes.iter_mut().take(self.cursor_y) { *size = LineSize::Single; }
                 self.erase_line(1);
             }
             2 | 3 => {
                 for row in &mut self.cells { for c in row.iter_mut() { *c = Cell::default(); } }
+                self.line_stamps.fill(None);
+                self.line_wrapped.fill(false);
+                self.line_sizes.fill(LineSize::Single);
                 self.cursor_x = 0; self.cursor_y = 0;
             }
             _ => {}
         }
     }
-    pub fn visible_row(&self, y: usize) -> Option<&Vec<Cell>> {
+    pub fn clear_all(&mut self) {
+        self.evicted_lines += self.scrollback.len() + self.rows;
+        self.scrollback.clear();
+        self.scrollback_stamps.clear();
+        self.scrollback_wrapped.clear();
+        self.scrollback_sizes.clear();
+        self.scroll_offset = 0;
+        self.erase_display(2);
+    }
+    pub fn snap_cursor_to_cell(&mut self, forward: bool) {
+        let Some(row) = self.cells.get(self.cursor_y) else { return };
+        if row.get(self.cursor_x).is_some_and(|c| c.width == 0) {
+            if forward && self.cursor_x + 1 < self.cols { self.cursor_x += 1; }
+            else { self.cursor_x = self.cursor_x.saturating_sub(1); }
+        }
+    }
+    pub fn set_max_scrollback(&mut self, max: usize) {
+        self.max_scrollback = max;
+        let excess = self.scrollback.len().saturating_sub(max);
+        if excess == 0 { return; }
+        self.scrollback.drain(..excess);
+        self.scrollback_stamps.drain(..excess.min(self.scrollback_stamps.len()));
+        self.scrollback_wrapped.drain(..excess.min(self.scrollback_wrapped.len()));
+        self.scrollback_sizes.drain(..excess.min(self.scrollback_sizes.len()));
+        self.evicted_lines += excess;
+        self.scroll_offset = self.scroll_offset.min(self.scrollback.len());
+    }
+    pub fn absolute_line(&self, y: usize) -> usize { self.evicted_lines + self.scrollback.len() + y }
+    pub fn view_start_line(&self) -> usize {
         let total = self.scrollback.len() + self.rows;
-        let view_start = total.saturating_sub(self.rows + self.scroll_offset);
-        let idx = view_start + y;
+        self.evicted_lines + total.saturating_sub(self.rows + self.scroll_offset)
+    }
+    pub fn line_at(&self, abs: usize) -> Option<&Vec<Cell>> {
+        let idx = abs.checked_sub(self.evicted_lines)?;
+        if idx < self.scrollback.len() { self.scrollback.get(idx) }
+        else { self.cells.get(idx - self.scrollback.len()) }
+    }
+    pub fn line_size_at(&self, abs: usize) -> LineSize {
+        let Some(idx) = abs.checked_sub(self.evicted_lines) else { return LineSize::Single };
+        if idx < self.scrollback_sizes.len() { self.scrollback_sizes[idx] }
+        else { self.line_sizes.get(idx - self.scrollback_sizes.len()).copied().unwrap_or_default() }
+    }
+    pub fn line_wrapped_at(&self, abs: usize) -> bool {
+        let Some(idx) = abs.checked_sub(self.evicted_lines) else { return false };
+        if idx < self.scrollback_wrapped.len() { self.scrollback_wrapped[idx] }
+        else { self.line_wrapped.get(idx - self.scrollback_wrapped.len()).copied().unwrap_or(false) }
+    }
+    pub fn word_bounds(&self, abs: usize, col: usize) -> Option<(usize, usize)> {
+        let row = self.line_at(abs)?;
+        let mut col = col.min(row.len().checked_sub(1)?);
+        while col > 0 && row[col].width == 0 { col -= 1; }
+        let is_word = |c: &Cell| c.width == 0 || !(c.ch.is_whitespace() || WORD_DELIMITERS.contains(c.ch));
+        if !is_word(&row[col]) { return Some((col, col)); }
+        let mut start = col;
+        while start > 0 && is_word(&row[start - 1]) { start -= 1; }
+        let mut end = col;
+        while end + 1 < row.len() && is_word(&row[end + 1]) { end += 1; }
+        Some((start, end))
+    }
+    pub fn logical_line_bounds(&self, abs: usize) -> (usize, usize) {
+        let mut start = abs;
+        while start > self.evicted_lines && self.line_wrapped_at(start - 1) { start -= 1; }
+        let mut end = abs;
+        while self.line_wrapped_at(end) { end += 1; }
+        (start, end)
+    }
+    pub fn export_rows(&self) -> Vec<(&Vec<Cell>, Option<DateTime<Local>>)> {
+        let mut rows: Vec<_> = self.scrollback.iter().zip(self.scrollback_stamps.iter().copied())
+            .chain(self.cells.iter().zip(self.line_stamps.iter().copied()))
+            .collect();
+        while rows.last().is_some_and(|(row, _)| row.iter().all(Cell::is_blank)) { rows.pop(); }
+        rows
+    }
+    pub fn export_text(&self, stamps: bool) -> String {
+        let mut out = String::new();
+        for (row, stamp) in self.export_rows() {
+            if let Some(stamp) = stamp.filter(|_| stamps) { out.push_str(&stamp.format("[%H:%M:%S] ").to_string()); }
+            let line: String = row.iter().filter(|c| c.width != 0).map(|c| c.ch).collect();
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+    pub fn export_html(&self, theme: &Theme, stamps: bool) -> String {
+        let hex = |c: Color32| format!("#{:02x}{:02x}{:02x}", c.r(), c.g(), c.b());
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Spiltixal export</title></head>\n<body style=\"margin:0;background:{bg}\"><pre style=\"margin:0;padding:12px;color:{fg};background:{bg};font-family:monospace\">",
+            bg = hex(theme.bg()), fg = hex(theme.fg()),
+        );
+        for (row, stamp) in self.export_rows() {
+            if let Some(stamp) = stamp.filter(|_| stamps) {
+                out.push_str(&format!("<span style=\"opacity:0.6\">{}</span>", stamp.format("[%H:%M:%S] ")));
+            }
+            let end = row.iter().rposition(|c| !c.is_blank()).map_or(0, |i| i + 1);
+            let mut run: Option<(String, String)> = None;
+            for cell in row[..end].iter().filter(|c| c.width != 0) {
+                let (fg, bg) = if cell.attrs.reverse {
+                    (cell.bg.resolve(false, theme), cell.fg.resolve(true, theme))
+                } else {
+                    (cell.fg.resolve(true, theme), cell.bg.resolve(false, theme))
+                };
+                let mut style = format!("color:{}", hex(fg));
+                if cell.attrs.reverse || cell.bg != TermColor::Default { style.push_str(&format!(";background:{}", hex(bg))); }
+                if cell.attrs.bold { style.push_str(";font-weight:bold"); }
+                if cell.attrs.dim { style.push_str(";opacity:0.6"); }
+                if cell.attrs.italic { style.push_str(";font-style:italic"); }
+                if cell.attrs.invisible { style.push_str(";visibility:hidden"); }
+                let underline = cell.attrs.underline != UnderlineStyle::None;
+                match (underline, cell.attrs.strikeout) {
+                    (true, true) => style.push_str(";text-decoration:underline line-through"),
+                    (true, false) => style.push_str(";text-decoration:underline"),
+                    (false, true) => style.push_str(";text-decoration:line-through"),
+                    (false, false) => {}
+                }
+                if let Some(css) = cell.attrs.underline.css() { style.push_str(&format!(";text-decoration-style:{css}")); }
+                if let Some(color) = cell.attrs.underline_color.filter(|_| underline) {
+                    style.push_str(&format!(";text-decoration-color:{}", hex(color.resolve(true, theme))));
+                }
+                let text = match cell.ch {
+                    '&' => "&amp;".to_string(),
+                    '<' => "&lt;".to_string(),
+                    '>' => "&gt;".to_string(),
+                    c => c.to_string(),
+                };
+                match &mut run {
+                    Some((s, t)) if *s == style => t.push_str(&text),
+                    _ => {
+                        if let Some((s, t)) = run.replace((style, text)) { out.push_str(&format!("<span style=\"{s}\">{t}</span>")); }
+                    }
+                }
+            }
+            if let Some((s, t)) = run { out.push_str(&format!("<span style=\"{s}\">{t}</span>")); }
+            out.push('\n');
+        }
+        out.push_str("</pre></body></html>\n");
+        out
+    }
+    pub fn line_text(&self, abs: usize, from_col: usize) -> Option<String> {
+        let row = self.line_at(abs)?;
+        Some(row.iter().skip(from_col).filter(|c| c.width != 0).map(|c| c.ch).collect::<String>().trim_end().to_string())
+    }
+    pub fn visible_stamp(&self, y: usize) -> Option<DateTime<Local>> {
+        let total = self.scrollback.len() + self.rows;
+        let idx = total.saturating_sub(self.rows + self.scroll_offset) + y;
+        if idx < self.scrollback_stamps.len() { self.scrollback_stamps[idx] }
+        else { self.line_stamps.get(idx - self.scrollback.len()).copied().flatten() }
+    }
+    pub fn visible_row(&self, y: usize) -> Option<&Vec<Cell>> {
+        let total = self.scrollback.len() + self.rows;
+        let view_start = total.saturating_sub(self.rows + self.scroll_offset);
+        let idx = view_start + y;
         if idx < self.scrollback.len() { self.scrollback.get(idx) }
         else { self.cells.get(idx - self.scrollback.len()) }
     }
@@ -475,45 +1624,84 @@ struct Performer<'a> {
     grid: &'a mut Grid,
     current_fg: TermColor, current_bg: TermColor, current_attrs: Attrs,
     title: &'a mut String,
+    last_exit_code: &'a mut Option<i32>,
+    command_marks: &'a mut Vec<CommandMark>,
+    primary_screen: &'a mut Option<PrimaryScreen>,
+    cwd: &'a mut Option<PathBuf>,
 }
 impl<'a> Perform for Performer<'a> {
     fn print(&mut self, ch: char) {
+        let ch = self.grid.charsets[self.grid.active_charset].translate(ch);
         self.grid.put_char(ch, self.current_fg, self.current_bg, self.current_attrs);
     }
     fn execute(&mut self, byte: u8) {
         match byte {
             b'\n' | 0x0B | 0x0C => self.grid.newline(),
             b'\r' => self.grid.cursor_x = 0,
-            b'\t' => { self.grid.cursor_x = ((self.grid.cursor_x / 8 + 1) * 8).min(self.grid.cols - 1); }
-            0x08  => { if self.grid.cursor_x > 0 { self.grid.cursor_x -= 1; } }
+            b'\t' => { self.grid.cursor_x = self.grid.next_tab_stop(); }
+            0x08 if self.grid.cursor_x > 0 => { self.grid.cursor_x -= 1; }
+            0x0E => self.grid.active_charset = 1,
+            0x0F => self.grid.active_charset = 0,
             _     => {}
         }
     }
-    fn csi_dispatch(&mut self, params: &Params, _ints: &[u8], _ignore: bool, action: char) {
+    fn csi_dispatch(&mut self, params: &Params, ints: &[u8], _ignore: bool, action: char) {
         let ps: Vec<u16> = params.iter().map(|p| p[0]).collect();
         let p0 = ps.first().copied().unwrap_or(0);
         let pn = |i: usize| -> usize { ps.get(i).copied().unwrap_or(1).max(1) as usize };
         let p1 = || -> usize { ps.first().copied().unwrap_or(1).max(1) as usize };
+        if ints == [b'?'] && matches!(action, 'h' | 'l') {
+            for &mode in &ps { self.set_private_mode(mode, action == 'h'); }
+            return;
+        }
         match action {
             'A' => { self.grid.cursor_y = self.grid.cursor_y.saturating_sub(p1()); }
             'B' => { self.grid.cursor_y = (self.grid.cursor_y + p1()).min(self.grid.rows - 1); }
-            'C' => { self.grid.cursor_x = (self.grid.cursor_x + p1()).min(self.grid.cols - 1); }
-            'D' => { self.grid.cursor_x = self.grid.cursor_x.saturating_sub(p1()); }
+            'C' => {
+                self.grid.cursor_x = (self.grid.cursor_x + p1()).min(self.grid.line_cols(self.grid.cursor_y) - 1);
+                self.grid.snap_cursor_to_cell(true);
+            }
+            'D' => {
+                self.grid.cursor_x = self.grid.cursor_x.saturating_sub(p1());
+                self.grid.snap_cursor_to_cell(false);
+            }
             'H' | 'f' => {
                 self.grid.cursor_y = (pn(0).saturating_sub(1)).min(self.grid.rows - 1);
-                self.grid.cursor_x = (pn(1).saturating_sub(1)).min(self.grid.cols - 1);
+                self.grid.cursor_x = (pn(1).saturating_sub(1)).min(self.grid.line_cols(self.grid.cursor_y) - 1);
             }
             'J' => self.grid.erase_display(p0 as u8),
             'K' => self.grid.erase_line(p0 as u8),
-            'S' | 'L' => self.grid.scroll_up(p1()),
-            'T' | 'M' => self.grid.scroll_down(p1()),
-            'm' => self.handle_sgr(&ps),
+            'X' => self.grid.erase_chars(p1(), self.current_bg),
+            'S' => self.grid.scroll_up(p1()),
+            'T' => self.grid.scroll_down(p1()),
+            'L' => self.grid.insert_lines(p1()),
+            'M' => self.grid.delete_lines(p1()),
+            'm' => self.handle_sgr(params),
             'r' => {
                 self.grid.scroll_top = pn(0).saturating_sub(1);
                 self.grid.scroll_bot = (pn(1).saturating_sub(1)).min(self.grid.rows - 1);
             }
+            'g' => match p0 {
+                0 => { let x = self.grid.cursor_x; if let Some(stop) = self.grid.tab_stops.get_mut(x) { *stop = false; } }
+                3 => self.grid.tab_stops.fill(false),
+                _ => {}
+            },
+            'q' if ints == [b' '] => {
+                self.grid.cursor_style = match p0 {
+                    1 | 2 => Some((CursorShape::Block, p0 == 1)),
+                    3 | 4 => Some((CursorShape::Underline, p0 == 3)),
+                    5 | 6 => Some((CursorShape::Bar, p0 == 5)),
+                    _ => None,
+                };
+            }
+            'p' if ints == [b'!'] => self.soft_reset(),
+            's' if ints.is_empty() => self.save_cursor(),
+            'u' if ints.is_empty() => self.restore_cursor(),
             'd' => { self.grid.cursor_y = (p0 as usize).saturating_sub(1).min(self.grid.rows - 1); }
-            'G' => { self.grid.cursor_x = (p0 as usize).saturating_sub(1).min(self.grid.cols - 1); }
+            'G' => {
+                self.grid.cursor_x = (p0 as usize).saturating_sub(1).min(self.grid.line_cols(self.grid.cursor_y) - 1);
+                self.grid.snap_cursor_to_cell(false);
+            }
             'P' => {
                 let n = p1(); let y = self.grid.cursor_y; let x = self.grid.cursor_x; let cols = self.grid.cols;
                 if y < self.grid.rows {
@@ -535,11 +1723,70 @@ impl<'a> Perform for Performer<'a> {
         if params.len() >= 2 && (params[0] == b"0" || params[0] == b"2") {
             if let Ok(t) = std::str::from_utf8(params[1]) { *self.title = t.to_string(); }
         }
+        if params.len() >= 2 && params[0] == b"7" {
+            let uri = params[1..].join(&b';');
+            if let Some(dir) = std::str::from_utf8(&uri).ok().and_then(parse_osc7_uri) { *self.cwd = Some(dir); }
+        }
+        if params.len() >= 2 && params[0] == b"133" {
+            let line = self.grid.absolute_line(self.grid.cursor_y);
+            match params[1] {
+                b"A" => {
+                    self.command_marks.push(CommandMark { prompt_line: line, ..Default::default() });
+                    if self.command_marks.len() > MAX_COMMAND_MARKS { self.command_marks.remove(0); }
+                }
+                b"B" => {
+                    if let Some(mark) = self.command_marks.last_mut() {
+                        mark.command_line = Some(line);
+                        mark.command_col = self.grid.cursor_x;
+                    }
+                }
+                b"C" => {
+                    if let Some(mark) = self.command_marks.last_mut() { mark.output_line = Some(line); }
+                }
+                b"D" => {
+                    let code = params.get(2).and_then(|c| std::str::from_utf8(c).ok()).and_then(|c| c.trim().parse::<i32>().ok());
+                    if let Some(code) = code { *self.last_exit_code = Some(code); }
+                    if let Some(mark) = self.command_marks.last_mut().filter(|m| m.output_line.is_some() && m.end_line.is_none()) {
+                        mark.end_line = Some(line);
+                        mark.exit_code = code;
+                    }
+                }
+                _ => {}
+            }
+        }
     }
-    fn esc_dispatch(&mut self, _ints: &[u8], _ignore: bool, byte: u8) {
-        if byte == b'M' {
-            if self.grid.cursor_y <= self.grid.scroll_top { self.grid.scroll_down(1); }
-            else { self.grid.cursor_y = self.grid.cursor_y.saturating_sub(1); }
+    fn esc_dispatch(&mut self, ints: &[u8], _ignore: bool, byte: u8) {
+        if let [slot @ (b'(' | b')')] = ints {
+            let charset = if byte == b'0' { Charset::DecSpecial } else { Charset::Ascii };
+            self.grid.charsets[usize::from(*slot == b')')] = charset;
+            return;
+        }
+        if ints == [b'#'] {
+            let size = match byte {
+                b'3' => LineSize::DoubleTop,
+                b'4' => LineSize::DoubleBottom,
+                b'5' => LineSize::Single,
+                b'6' => LineSize::DoubleWidth,
+                _ => return,
+            };
+            let y = self.grid.cursor_y;
+            if let Some(slot) = self.grid.line_sizes.get_mut(y) { *slot = size; }
+            self.grid.cursor_x = self.grid.cursor_x.min(self.grid.line_cols(y).saturating_sub(1));
+            return;
+        }
+        match byte {
+            b'M' => {
+                if self.grid.cursor_y <= self.grid.scroll_top { self.grid.scroll_down(1); }
+                else { self.grid.cursor_y = self.grid.cursor_y.saturating_sub(1); }
+            }
+            b'H' => {
+                let x = self.grid.cursor_x;
+                if let Some(stop) = self.grid.tab_stops.get_mut(x) { *stop = true; }
+            }
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'c' if ints.is_empty() => self.full_reset(),
+            _ => {}
         }
     }
     fn hook(&mut self, _: &Params, _: &[u8], _: bool, _: char) {}
@@ -547,50 +1794,144 @@ impl<'a> Perform for Performer<'a> {
     fn unhook(&mut self) {}
 }
 impl<'a> Performer<'a> {
-    fn handle_sgr(&mut self, ps: &[u16]) {
+    fn set_private_mode(&mut self, mode: u16, on: bool) {
+        match mode {
+            1 => self.grid.app_cursor_keys = on,
+            25 => self.grid.cursor_visible_app = on,
+            2004 => self.grid.bracketed_paste = on,
+            1000 | 1002 | 1003 => {
+                self.grid.mouse_mode = match (on, mode) {
+                    (false, _)  => MouseMode::Off,
+                    (_, 1000)   => MouseMode::Click,
+                    (_, 1002)   => MouseMode::Drag,
+                    _           => MouseMode::Motion,
+                };
+            }
+            1006 => self.grid.mouse_sgr = on,
+            47 | 1047 | 1049 => self.set_alt_screen(on, mode == 1049),
+            _ => {}
+        }
+    }
+    fn set_alt_screen(&mut self, on: bool, save_cursor: bool) {
+        if on == self.primary_screen.is_some() { return; }
+        if on {
+            if save_cursor { self.save_cursor(); }
+            let grid = &mut *self.grid;
+            let blank = vec![vec![Cell::default(); grid.cols]; grid.rows];
+            *self.primary_screen = Some(PrimaryScreen {
+                cells:  std::mem::replace(&mut grid.cells, blank),
+                stamps: std::mem::replace(&mut grid.line_stamps, vec![None; grid.rows]),
+                wrapped: std::mem::replace(&mut grid.line_wrapped, vec![false; grid.rows]),
+                sizes:   std::mem::replace(&mut grid.line_sizes, vec![LineSize::Single; grid.rows]),
+            });
+            grid.alt_screen = true;
+            grid.scroll_offset = 0;
+        } else if let Some(mut primary) = self.primary_screen.take() {
+            let grid = &mut *self.grid;
+            Grid::resize_buffer(&mut primary.cells, &mut primary.stamps, &mut primary.wrapped, &mut primary.sizes, grid.rows, grid.cols);
+            grid.cells = primary.cells;
+            grid.line_stamps = primary.stamps;
+            grid.line_wrapped = primary.wrapped;
+            grid.line_sizes = primary.sizes;
+            grid.alt_screen = false;
+            if save_cursor { self.restore_cursor(); }
+        }
+    }
+    fn save_cursor(&mut self) {
+        self.grid.saved_cursor = Some(SavedCursor {
+            x: self.grid.cursor_x, y: self.grid.cursor_y,
+            fg: self.current_fg, bg: self.current_bg, attrs: self.current_attrs,
+        });
+    }
+    fn soft_reset(&mut self) {
+        self.reset_attrs();
+        let grid = &mut *self.grid;
+        grid.scroll_top = 0;
+        grid.scroll_bot = grid.rows.saturating_sub(1);
+        grid.cursor_visible_app = true;
+        grid.app_cursor_keys = false;
+        grid.charsets = [Charset::Ascii; 2];
+        grid.active_charset = 0;
+        grid.saved_cursor = None;
+    }
+    fn full_reset(&mut self) {
+        self.set_alt_screen(false, false);
+        self.soft_reset();
+        let grid = &mut *self.grid;
+        grid.erase_display(2);
+        grid.tab_stops = (0..grid.cols).map(|c| c % 8 == 0).collect();
+        grid.bracketed_paste = false;
+        grid.mouse_mode = MouseMode::Off;
+        grid.mouse_sgr = false;
+        grid.cursor_style = None;
+        grid.scroll_offset = 0;
+    }
+    fn restore_cursor(&mut self) {
+        let saved = self.grid.saved_cursor.unwrap_or(SavedCursor {
+            x: 0, y: 0, fg: TermColor::Default, bg: TermColor::Default, attrs: Attrs::default(),
+        });
+        self.grid.cursor_x = saved.x.min(self.grid.cols.saturating_sub(1));
+        self.grid.cursor_y = saved.y.min(self.grid.rows.saturating_sub(1));
+        self.current_fg = saved.fg; self.current_bg = saved.bg; self.current_attrs = saved.attrs;
+    }
+    fn handle_sgr(&mut self, params: &Params) {
+        let groups: Vec<&[u16]> = params.iter().collect();
         let mut i = 0;
-        if ps.is_empty() { self.reset_attrs(); return; }
-        while i < ps.len() {
-            match ps[i] {
+        if groups.is_empty() { self.reset_attrs(); return; }
+        while i < groups.len() {
+            let p = groups[i][0];
+            match p {
                 0  => self.reset_attrs(),
                 1  => self.current_attrs.bold      = true,
                 2  => self.current_attrs.dim       = true,
                 3  => self.current_attrs.italic    = true,
-                4  => self.current_attrs.underline = true,
+                4  => self.current_attrs.underline = UnderlineStyle::from_sgr(groups[i].get(1).copied()),
                 5  => self.current_attrs.blink     = true,
                 7  => self.current_attrs.reverse   = true,
                 8  => self.current_attrs.invisible = true,
                 9  => self.current_attrs.strikeout = true,
+                21 => self.current_attrs.underline = UnderlineStyle::Double,
                 22 => { self.current_attrs.bold = false; self.current_attrs.dim = false; }
                 23 => self.current_attrs.italic    = false,
-                24 => self.current_attrs.underline = false,
+                24 => self.current_attrs.underline = UnderlineStyle::None,
                 25 => self.current_attrs.blink     = false,
                 27 => self.current_attrs.reverse   = false,
                 28 => self.current_attrs.invisible = false,
                 29 => self.current_attrs.strikeout = false,
-                30..=37   => self.current_fg = TermColor::Ansi((ps[i] - 30) as u8),
-                38        => { if let Some(c) = self.parse_ext(ps, &mut i) { self.current_fg = c; } }
+                30..=37   => self.current_fg = TermColor::Ansi((p - 30) as u8),
+                38        => { if let Some(c) = Self::parse_ext(&groups, &mut i) { self.current_fg = c; } }
                 39        => self.current_fg = TermColor::Default,
-                40..=47   => self.current_bg = TermColor::Ansi((ps[i] - 40) as u8),
-                48        => { if let Some(c) = self.parse_ext(ps, &mut i) { self.current_bg = c; } }
+                40..=47   => self.current_bg = TermColor::Ansi((p - 40) as u8),
+                48        => { if let Some(c) = Self::parse_ext(&groups, &mut i) { self.current_bg = c; } }
                 49        => self.current_bg = TermColor::Default,
-                90..=97   => self.current_fg = TermColor::Ansi((ps[i] - 90 + 8) as u8),
-                100..=107 => self.current_bg = TermColor::Ansi((ps[i] - 100 + 8) as u8),
+                58        => { if let Some(c) = Self::parse_ext(&groups, &mut i) { self.current_attrs.underline_color = Some(c); } }
+                59        => self.current_attrs.underline_color = None,
+                90..=97   => self.current_fg = TermColor::Ansi((p - 90 + 8) as u8),
+                100..=107 => self.current_bg = TermColor::Ansi((p - 100 + 8) as u8),
                 _ => {}
             }
             i += 1;
         }
     }
-    fn parse_ext(&self, ps: &[u16], i: &mut usize) -> Option<TermColor> {
-        match ps.get(*i + 1).copied() {
-            Some(2) => {
-                let r = ps.get(*i + 2).copied()? as u8;
-                let g = ps.get(*i + 3).copied()? as u8;
-                let b = ps.get(*i + 4).copied()? as u8;
-                *i += 4; Some(TermColor::Rgb(r, g, b))
-            }
-            Some(5) => { let idx = ps.get(*i + 2).copied()? as u8; *i += 2; Some(TermColor::Ansi256(idx)) }
+    fn parse_ext(groups: &[&[u16]], i: &mut usize) -> Option<TermColor> {
+        let byte = |v: u16| u8::try_from(v).unwrap_or(u8::MAX);
+        let group = groups[*i];
+        if group.len() > 1 {
+            return match (group[1], &group[2..]) {
+                (2, [_, r, g, b, ..]) | (2, [r, g, b]) => Some(TermColor::Rgb(byte(*r), byte(*g), byte(*b))),
+                (5, [idx, ..]) => Some(TermColor::Ansi256(byte(*idx))),
+                _ => None,
+            };
+        }
+        let next = |k: usize| groups.get(*i + k).map(|g| g[0]);
+        let color = match next(1) {
+            Some(2) => next(2).zip(next(3)).zip(next(4)).map(|((r, g), b)| (TermColor::Rgb(byte(r), byte(g), byte(b)), 4)),
+            Some(5) => next(2).map(|idx| (TermColor::Ansi256(byte(idx)), 2)),
             _ => None,
+        };
+        match color {
+            Some((color, len)) => { *i += len; Some(color) }
+            None => { *i = groups.len(); None }
         }
     }
     fn reset_attrs(&mut self) {
@@ -600,15 +1941,117 @@ impl<'a> Performer<'a> {
     }
 }
 
+fn paint_scaled_galley(painter: &Painter, galley: Arc<Galley>, pos: Pos2, anchor: Pos2, scale: Vec2, color: Color32) {
+    let ctx = painter.ctx();
+    let mut tessellator = egui::epaint::Tessellator::new(
+        ctx.pixels_per_point(),
+        Default::default(),
+        ctx.fonts(|f| f.font_image_size()),
+        Vec::new(),
+    );
+    let mut mesh = egui::epaint::Mesh::default();
+    tessellator.tessellate_text(&egui::epaint::TextShape::new(pos, galley, color), &mut mesh);
+    for vertex in &mut mesh.vertices { vertex.pos = anchor + (vertex.pos - anchor) * scale; }
+    painter.add(egui::Shape::mesh(mesh));
+}
+
+fn paint_underline(painter: &Painter, span: std::ops::RangeInclusive<f32>, y: f32, style: UnderlineStyle, color: Color32) {
+    let (x0, x1) = (*span.start(), *span.end());
+    let stroke = Stroke::new(1.0, color);
+    match style {
+        UnderlineStyle::Double => {
+            painter.line_segment([pos2(x0, y), pos2(x1, y)], stroke);
+            painter.line_segment([pos2(x0, y - 2.5), pos2(x1, y - 2.5)], stroke);
+        }
+        UnderlineStyle::Curly => {
+            let steps = ((x1 - x0) / 1.5).ceil().max(2.0) as usize;
+            let points = (0..=steps).map(|i| {
+                let x = x0 + (x1 - x0) * i as f32 / steps as f32;
+                pos2(x, y - 1.2 + 1.2 * ((x - x0) / 4.0 * std::f32::consts::TAU).sin())
+            }).collect();
+            painter.add(egui::Shape::line(points, stroke));
+        }
+        UnderlineStyle::Dotted => {
+            let mut x = x0 + 0.5;
+            while x < x1 { painter.circle_filled(pos2(x, y), 0.6, color); x += 2.0; }
+        }
+        UnderlineStyle::Dashed => {
+            let mut x = x0;
+            while x < x1 { painter.line_segment([pos2(x, y), pos2((x + 3.0).min(x1), y)], stroke); x += 5.0; }
+        }
+        UnderlineStyle::None => {}
+        UnderlineStyle::Single => { painter.line_segment([pos2(x0, y), pos2(x1, y)], stroke); }
+    }
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn local_hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname").ok().map(|h| h.trim().to_string())
+}
+
+fn parse_osc7_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let slash = rest.find('/')?;
+    let (host, path) = rest.split_at(slash);
+    let local = host.is_empty()
+        || host.eq_ignore_ascii_case("localhost")
+        || local_hostname().is_some_and(|h| h.eq_ignore_ascii_case(host));
+    if !local { return None; }
+    let path = PathBuf::from(percent_decode(path)?);
+    path.is_absolute().then_some(path)
+}
+
+const MAX_COMMAND_MARKS: usize = 1000;
+const MAX_CONTEXT_OUTPUT_LINES: usize = 200;
+const MAX_SUMMARY_CHARS: usize = 16_000;
+
+pub struct PrimaryScreen {
+    pub cells:  Vec<Vec<Cell>>,
+    pub stamps: Vec<Option<DateTime<Local>>>,
+    pub wrapped: Vec<bool>,
+    pub sizes:  Vec<LineSize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandMark {
+    pub prompt_line:  usize,
+    pub command_line: Option<usize>,
+    pub command_col:  usize,
+    pub output_line:  Option<usize>,
+    pub end_line:     Option<usize>,
+    pub exit_code:    Option<i32>,
+}
+
 pub struct TerminalState {
     pub grid: Grid, pub title: String,
+    pub last_exit_code: Option<i32>,
+    pub command_marks: Vec<CommandMark>,
+    pub cwd: Option<PathBuf>,
+    pub primary_screen: Option<PrimaryScreen>,
     parser: Parser,
     current_fg: TermColor, current_bg: TermColor, current_attrs: Attrs,
 }
 impl TerminalState {
     pub fn new(rows: usize, cols: usize, max_scrollback: usize) -> Self {
         Self {
-            grid: Grid::new(rows, cols, max_scrollback), title: "Spiltixal".into(),
+            grid: Grid::new(rows, cols, max_scrollback), title: "Spiltixal".into(), last_exit_code: None,
+            command_marks: Vec::new(), cwd: None, primary_screen: None,
             parser: Parser::new(), current_fg: TermColor::Default,
             current_bg: TermColor::Default, current_attrs: Attrs::default(),
         }
@@ -618,13 +2061,74 @@ impl TerminalState {
             grid: &mut self.grid, current_fg: self.current_fg,
             current_bg: self.current_bg, current_attrs: self.current_attrs,
             title: &mut self.title,
+            last_exit_code: &mut self.last_exit_code,
+            command_marks: &mut self.command_marks,
+            primary_screen: &mut self.primary_screen,
+            cwd: &mut self.cwd,
         };
         for &byte in bytes { self.parser.advance(&mut perf, byte); }
         self.current_fg    = perf.current_fg;
         self.current_bg    = perf.current_bg;
         self.current_attrs = perf.current_attrs;
     }
-    pub fn resize(&mut self, rows: usize, cols: usize) { self.grid.resize(rows, cols); }
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        if cols == self.grid.cols || self.primary_screen.is_some() {
+            self.grid.resize(rows, cols);
+        } else {
+            let base = self.grid.evicted_lines;
+            let remap = self.grid.reflow(rows, cols);
+            let map = |line: usize| match line.checked_sub(base) {
+                Some(idx) => base + remap[idx.min(remap.len() - 1)],
+                None => line,
+            };
+            for mark in &mut self.command_marks {
+                mark.prompt_line  = map(mark.prompt_line);
+                mark.command_line = mark.command_line.map(map);
+                mark.output_line  = mark.output_line.map(map);
+                mark.end_line     = mark.end_line.map(map);
+            }
+        }
+        if let Some(primary) = &mut self.primary_screen {
+            Grid::resize_buffer(&mut primary.cells, &mut primary.stamps, &mut primary.wrapped, &mut primary.sizes, rows, cols);
+        }
+    }
+    pub fn alt_screen_active(&self) -> bool { self.primary_screen.is_some() }
+    pub fn at_shell_prompt(&self) -> bool {
+        self.command_marks.last().is_some_and(|mark| mark.command_line.is_some() && mark.output_line.is_none())
+    }
+    pub fn prompt_line_command(&self, prompt_regex: &str) -> Option<String> {
+        let pattern = prompt_regex.trim();
+        if pattern.is_empty() { return None; }
+        let re = Regex::new(pattern).ok()?;
+        let row = self.grid.cells.get(self.grid.cursor_y)?;
+        let line: String = row.iter().filter(|c| c.width != 0).map(|c| c.ch).collect();
+        let m = re.find(&line)?;
+        Some(line[m.end()..].trim().to_string())
+    }
+    pub fn prompt_command(&self, prompt_regex: &str, input_buf: &str) -> Option<String> {
+        self.prompt_line_command(prompt_regex)
+            .or_else(|| self.at_shell_prompt().then(|| input_buf.trim_end_matches('\n').to_string()))
+    }
+    pub fn mark_command_text(&self, mark: &CommandMark) -> String {
+        let Some(start) = mark.command_line else { return String::new() };
+        let end = mark.output_line.unwrap_or(start).max(start + 1);
+        (start..end)
+            .filter_map(|line| self.grid.line_text(line, if line == start { mark.command_col } else { 0 }))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim()
+            .to_string()
+    }
+    pub fn mark_output_text(&self, mark: &CommandMark) -> Option<(String, bool)> {
+        let output = mark.output_line?;
+        let end = mark.end_line?;
+        let start = if mark.command_line == Some(output) { output + 1 } else { output };
+        let mut lines: Vec<String> = (start..end).filter_map(|line| self.grid.line_text(line, 0)).collect();
+        while lines.last().is_some_and(|l| l.is_empty()) { lines.pop(); }
+        let truncated = lines.len() > MAX_CONTEXT_OUTPUT_LINES || start < self.grid.evicted_lines;
+        if lines.len() > MAX_CONTEXT_OUTPUT_LINES { lines.drain(..lines.len() - MAX_CONTEXT_OUTPUT_LINES); }
+        Some((lines.join("\n"), truncated))
+    }
 }
 
 pub struct PtyHandle {
@@ -632,17 +2136,73 @@ pub struct PtyHandle {
     pub writer: Arc<Mutex<Box<dyn Write + Send>>>,
     pub child:  Box<dyn Child + Send + Sync>,
     pub rx:     Receiver<Vec<u8>>,
+    pub term:   String,
+}
+#[derive(Clone, Debug, Default)]
+pub struct ShellSpec {
+    pub shell:   String,
+    pub login:   bool,
+    pub args:    Vec<String>,
+    pub command: Option<String>,
+    pub cwd:     Option<PathBuf>,
+    pub env:     Vec<(String, String)>,
+}
+impl ShellSpec {
+    pub fn check_cwd(&mut self) -> Option<String> {
+        let dir = self.cwd.as_ref()?;
+        if dir.is_dir() { return None; }
+        let home = dirs::home_dir();
+        let note = format!(
+            "{} doesn't exist, so the shell started in {}.",
+            dir.display(),
+            home.as_ref().map_or_else(|| "the launch directory".to_string(), |h| h.display().to_string()),
+        );
+        self.cwd = home;
+        Some(note)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LaunchOptions {
+    pub shell:             Option<String>,
+    pub command:           Option<String>,
+    pub working_directory: Option<PathBuf>,
+}
+impl LaunchOptions {
+    pub fn shell_spec(&self, config: &Config) -> ShellSpec {
+        ShellSpec {
+            shell:   self.shell.clone().unwrap_or_else(|| config.shell.clone()),
+            login:   config.login_shell,
+            args:    config.shell_args.clone(),
+            command: self.command.clone(),
+            cwd:     self.working_directory.clone().or_else(|| {
+                let dir = config.working_directory.trim();
+                (!dir.is_empty()).then(|| expand_tilde(dir))
+            }),
+            env:     config.env.clone(),
+        }
+    }
 }
+
+pub const INJECTED_ENV: [(&str, &str); 2] = [
+    ("COLORTERM", "truecolor"),
+    ("SPILTIXAL", "1"),
+];
+
 impl PtyHandle {
-    pub fn spawn(shell: &str, rows: u16, cols: u16) -> Result<Self> {
+    pub fn spawn(spec: &ShellSpec, term: &str, rows: u16, cols: u16) -> Result<Self> {
         let pty_system = native_pty_system();
         let pair = pty_system.openpty(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })
             .context("Failed to open PTY")?;
         let master = pair.master;
-        let mut cmd = CommandBuilder::new(shell);
-        cmd.env("TERM", "xterm-256color");
-        cmd.env("COLORTERM", "truecolor");
-        cmd.env("SPILTIXAL", "1");
+        let mut cmd = CommandBuilder::new(&spec.shell);
+        if spec.login { cmd.arg("-l"); }
+        cmd.args(&spec.args);
+        if let Some(command) = &spec.command { cmd.args(["-c", command]); }
+        if let Some(dir) = &spec.cwd { cmd.cwd(dir); }
+        for (key, value) in &spec.env { cmd.env(key, value); }
+        cmd.env("TERM", term);
+        for (key, value) in INJECTED_ENV { cmd.env(key, value); }
         let child  = pair.slave.spawn_command(cmd).context("Failed to spawn shell")?;
         let writer = Arc::new(Mutex::new(master.take_writer().context("PTY writer")?));
         let mut reader = master.try_clone_reader().context("PTY reader")?;
@@ -657,10 +2217,11 @@ impl PtyHandle {
                 }
             }
         });
-        Ok(Self { master, writer, child, rx })
+        Ok(Self { master, writer, child, rx, term: term.to_string() })
     }
-    pub fn write_str(&self, s: &str) -> Result<()> {
-        self.writer.lock().map_err(|_| anyhow::anyhow!("lock"))?.write_all(s.as_bytes()).context("write")
+    pub fn write_str(&self, s: &str) -> Result<()> { self.write_bytes(s.as_bytes()) }
+    pub fn write_bytes(&self, bytes: &[u8]) -> Result<()> {
+        self.writer.lock().map_err(|_| anyhow::anyhow!("lock"))?.write_all(bytes).context("write")
     }
     pub fn resize(&self, rows: u16, cols: u16) -> Result<()> {
         self.master.lock().map_err(|_| anyhow::anyhow!("lock"))?
@@ -688,45 +2249,345 @@ impl PtyHandle {
         Ok(())
     }
     pub fn is_alive(&mut self) -> bool { matches!(self.child.try_wait(), Ok(None)) }
+    pub fn foreground_job(&self) -> Option<String> {
+        let pgrp = self.master.lock().ok()?.process_group_leader()?;
+        if self.child.process_id().is_some_and(|pid| pid as i32 == pgrp) { return None; }
+        let name = std::fs::read_to_string(format!("/proc/{pgrp}/comm")).ok()
+            .map(|comm| comm.trim().to_string())
+            .filter(|comm| !comm.is_empty())
+            .unwrap_or_else(|| format!("process {pgrp}"));
+        Some(name)
+    }
+    pub fn exit_code(&mut self) -> Option<u32> {
+        self.child.try_wait().ok().flatten().map(|status| status.exit_code())
+    }
+    pub fn shell_cwd(&self) -> Option<PathBuf> {
+        let pid = self.child.process_id()?;
+        std::fs::read_link(format!("/proc/{pid}/cwd")).ok()
+    }
+    pub fn shell_environ(&self) -> Result<Vec<(String, String)>> {
+        let pid = self.child.process_id().context("shell has no pid")?;
+        let raw = std::fs::read(format!("/proc/{pid}/environ"))
+            .with_context(|| format!("Failed to read /proc/{pid}/environ"))?;
+        let mut vars: Vec<(String, String)> = raw.split(|&b| b == 0)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                match entry.split_once('=') {
+                    Some((k, v)) => (k.to_string(), v.to_string()),
+                    None => (entry.to_string(), String::new()),
+                }
+            })
+            .collect();
+        vars.sort();
+        Ok(vars)
+    }
+}
+impl Drop for PtyHandle {
+    fn drop(&mut self) {
+        if !self.is_alive() { return; }
+        let _ = self.signal_foreground("HUP");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct CastRecorder {
+    pub path: PathBuf,
+    file:     std::io::BufWriter<std::fs::File>,
+    started:  Instant,
+    pending:  Vec<u8>,
+}
+impl CastRecorder {
+    pub fn start(path: PathBuf, rows: usize, cols: usize, shell: &str, term: &str) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file = std::fs::File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut rec = Self { path, file: std::io::BufWriter::new(file), started: Instant::now(), pending: Vec::new() };
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": Local::now().timestamp(),
+            "env": { "SHELL": shell, "TERM": term },
+        });
+        writeln!(rec.file, "{header}").context("write cast header")?;
+        Ok(rec)
+    }
+    fn event(&mut self, kind: &str, data: &str) -> Result<()> {
+        let line = serde_json::json!([self.started.elapsed().as_secs_f64(), kind, data]);
+        writeln!(self.file, "{line}").context("write cast event")
+    }
+    pub fn output(&mut self, bytes: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        if valid == 0 { return Ok(()); }
+        let chunk: Vec<u8> = self.pending.drain(..valid).collect();
+        self.event("o", &String::from_utf8_lossy(&chunk))
+    }
+    pub fn resize(&mut self, rows: usize, cols: usize) -> Result<()> {
+        self.event("r", &format!("{cols}x{rows}"))
+    }
+    pub fn finish(mut self) -> Result<PathBuf> {
+        if !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            self.event("o", &String::from_utf8_lossy(&rest))?;
+        }
+        self.file.flush().context("flush cast")?;
+        Ok(self.path)
+    }
+}
+
+const OUTPUT_LOG_ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+const OUTPUT_LOG_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct OutputLog {
+    pub path:   PathBuf,
+    tx:         Sender<Vec<u8>>,
+    buf:        Vec<u8>,
+    last_flush: Instant,
+}
+impl OutputLog {
+    pub fn open(path: PathBuf) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let open = |p: &Path| std::fs::OpenOptions::new().create(true).append(true).open(p);
+        let mut file = open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let (tx, rx) = unbounded::<Vec<u8>>();
+        let log_path = path.clone();
+        thread::spawn(move || {
+            for chunk in rx {
+                if written > 0 && written + chunk.len() as u64 > OUTPUT_LOG_ROTATE_BYTES {
+                    let mut rotated = log_path.clone().into_os_string();
+                    rotated.push(".1");
+                    if let Err(e) = std::fs::rename(&log_path, &rotated) { log::warn!("rotating {}: {e}", log_path.display()); }
+                    match open(&log_path) {
+                        Ok(f) => { file = f; written = 0; }
+                        Err(e) => { log::warn!("reopening {}: {e}", log_path.display()); break; }
+                    }
+                }
+                if let Err(e) = file.write_all(&chunk) {
+                    log::warn!("writing {}: {e}", log_path.display());
+                    break;
+                }
+                written += chunk.len() as u64;
+            }
+        });
+        Ok(Self { path, tx, buf: Vec::new(), last_flush: Instant::now() })
+    }
+    pub fn write(&mut self, bytes: &[u8]) { self.buf.extend_from_slice(bytes); }
+    pub fn flush_if_due(&mut self) -> bool {
+        if self.buf.is_empty() || self.last_flush.elapsed() < OUTPUT_LOG_FLUSH_INTERVAL { return true; }
+        self.last_flush = Instant::now();
+        self.tx.send(std::mem::take(&mut self.buf)).is_ok()
+    }
+}
+impl Drop for OutputLog {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() { let _ = self.tx.send(std::mem::take(&mut self.buf)); }
+    }
+}
+
+const WORD_DELIMITERS: &str = "\"'`()[]{}<>|;,│";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SelectionPoint { pub line: usize, pub col: usize }
+
+#[derive(Clone, Copy, Debug)]
+pub struct Selection { pub anchor: SelectionPoint, pub head: SelectionPoint, pub block: bool }
+
+impl Selection {
+    pub fn ordered(&self) -> (SelectionPoint, SelectionPoint) {
+        if self.anchor <= self.head { (self.anchor, self.head) } else { (self.head, self.anchor) }
+    }
+    pub fn is_empty(&self) -> bool { self.anchor == self.head }
+    pub fn contains(&self, line: usize, col: usize) -> bool {
+        if self.block {
+            let (lo, hi) = (self.anchor.line.min(self.head.line), self.anchor.line.max(self.head.line));
+            let (left, right) = (self.anchor.col.min(self.head.col), self.anchor.col.max(self.head.col));
+            (lo..=hi).contains(&line) && (left..=right).contains(&col)
+        } else {
+            let (start, end) = self.ordered();
+            let p = SelectionPoint { line, col };
+            p >= start && p <= end
+        }
+    }
+    pub fn text(&self, grid: &Grid) -> String {
+        let (start, end) = self.ordered();
+        let (left, right) = (self.anchor.col.min(self.head.col), self.anchor.col.max(self.head.col));
+        let mut lines = Vec::new();
+        for line in start.line..=end.line {
+            let Some(row) = grid.line_at(line) else { continue };
+            let (from, to) = if self.block {
+                (left, right)
+            } else {
+                (if line == start.line { start.col } else { 0 }, if line == end.line { end.col } else { usize::MAX })
+            };
+            let text: String = row.iter().enumerate()
+                .filter(|(i, c)| *i >= from && *i <= to && c.width != 0)
+                .map(|(_, c)| c.ch)
+                .collect();
+            lines.push(text.trim_end().to_string());
+        }
+        lines.join("\n")
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct SearchState {
     pub query: String, pub matches: Vec<SearchMatch>,
     pub current_idx: usize, pub active: bool,
+    pub current_only: bool, pub wrapped_at: Option<Instant>,
+    pub history: SearchHistory, pub history_idx: Option<usize>,
+    pub use_regex: bool, pub case_sensitive: bool,
+    pub error: Option<String>,
+    pattern: Option<Regex>,
+    scanned_rows: usize, scanned_evicted: usize,
 }
 #[derive(Debug, Clone)]
 pub struct SearchMatch { pub row: usize, pub col: usize, pub len: usize }
 
 impl SearchState {
-    pub fn search(&mut self, scrollback: &[Vec<Cell>], grid: &[Vec<Cell>]) {
+    pub fn search(&mut self, grid: &Grid) {
         self.matches.clear(); self.current_idx = 0;
+        self.scanned_rows = grid.scrollback.len(); self.scanned_evicted = grid.evicted_lines;
+        self.pattern = None; self.error = None;
         if self.query.is_empty() { return; }
-        let q = self.query.to_lowercase();
-        for (r, row) in scrollback.iter().chain(grid.iter()).enumerate() {
-            let line: String = row.iter().map(|c| c.ch).collect();
-            let lower = line.to_lowercase();
-            let mut start = 0;
-            while let Some(pos) = lower[start..].find(&q) {
-                let abs = start + pos;
-                self.matches.push(SearchMatch { row: r, col: abs, len: q.len() });
-                start = abs + 1;
-            }
+        let source = if self.use_regex { self.query.clone() } else { regex::escape(&self.query) };
+        match RegexBuilder::new(&source).case_insensitive(!self.case_sensitive).build() {
+            Ok(re) => self.pattern = Some(re),
+            Err(e) => { self.error = Some(e.to_string().lines().last().unwrap_or("invalid pattern").trim().to_string()); return; }
+        }
+        let Some(re) = self.pattern.clone() else { return };
+        for (r, row) in grid.scrollback.iter().chain(grid.cells.iter()).enumerate() { self.scan_row(r, row, &re); }
+    }
+    pub fn refresh(&mut self, grid: &Grid) {
+        let Some(re) = self.pattern.clone() else { return };
+        let shift = grid.evicted_lines.saturating_sub(self.scanned_evicted);
+        let stable = self.scanned_rows.saturating_sub(shift);
+        if grid.evicted_lines < self.scanned_evicted || stable > grid.scrollback.len() {
+            self.search(grid);
+            return;
+        }
+        let current = self.current_match().map(|m| (m.row, m.col));
+        self.matches.retain(|m| m.row >= shift && m.row - shift < stable);
+        for m in &mut self.matches { m.row -= shift; }
+        for (r, row) in grid.scrollback.iter().chain(grid.cells.iter()).enumerate().skip(stable) { self.scan_row(r, row, &re); }
+        self.scanned_rows = grid.scrollback.len(); self.scanned_evicted = grid.evicted_lines;
+        self.current_idx = current
+            .and_then(|(row, col)| row.checked_sub(shift).map(|row| (row, col)))
+            .and_then(|(row, col)| self.matches.iter().position(|m| m.row == row && m.col == col))
+            .unwrap_or_else(|| self.current_idx.min(self.matches.len().saturating_sub(1)));
+    }
+    fn scan_row(&mut self, r: usize, row: &[Cell], re: &Regex) {
+        let line: String = row.iter().map(|c| c.ch).collect();
+        for m in re.find_iter(&line).filter(|m| !m.is_empty()) {
+            let col = line[..m.start()].chars().count();
+            self.matches.push(SearchMatch { row: r, col, len: m.as_str().chars().count() });
         }
     }
     pub fn next(&mut self) {
-        if !self.matches.is_empty() { self.current_idx = (self.current_idx + 1) % self.matches.len(); }
+        if self.matches.is_empty() { return; }
+        self.current_idx = (self.current_idx + 1) % self.matches.len();
+        if self.current_idx == 0 { self.wrapped_at = Some(Instant::now()); }
     }
     pub fn prev(&mut self) {
         if self.matches.is_empty() { return; }
-        if self.current_idx == 0 { self.current_idx = self.matches.len() - 1; } else { self.current_idx -= 1; }
+        if self.current_idx == 0 {
+            self.current_idx = self.matches.len() - 1;
+            self.wrapped_at = Some(Instant::now());
+        } else { self.current_idx -= 1; }
+    }
+    pub fn recently_wrapped(&self) -> bool {
+        self.wrapped_at.is_some_and(|t| t.elapsed() < Duration::from_millis(1200))
     }
     pub fn current_match(&self) -> Option<&SearchMatch> { self.matches.get(self.current_idx) }
     pub fn is_match_at(&self, row: usize, col: usize) -> bool {
-        self.matches.iter().any(|m| m.row == row && col >= m.col && col < m.col + m.len)
+        !self.current_only && self.matches.iter().any(|m| m.row == row && col >= m.col && col < m.col + m.len)
     }
     pub fn is_current_at(&self, row: usize, col: usize) -> bool {
-        self.current_match().map_or(false, |m| m.row == row && col >= m.col && col < m.col + m.len)
+        self.current_match().is_some_and(|m| m.row == row && col >= m.col && col < m.col + m.len)
+    }
+    pub fn close(&mut self) {
+        self.history.record(&self.query);
+        self.history_idx = None;
+        self.query.clear(); self.matches.clear();
+        self.pattern = None; self.error = None;
+    }
+    pub fn history_older(&mut self) -> bool {
+        let len = self.history.queries.len();
+        if len == 0 { return false; }
+        let idx = match self.history_idx { Some(i) => (i + 1).min(len - 1), None => 0 };
+        self.history_idx = Some(idx);
+        self.query = self.history.queries[idx].clone();
+        true
+    }
+    pub fn history_newer(&mut self) -> bool {
+        match self.history_idx {
+            None => false,
+            Some(0) => { self.history_idx = None; self.query.clear(); true }
+            Some(i) => { self.history_idx = Some(i - 1); self.query = self.history.queries[i - 1].clone(); true }
+        }
+    }
+}
+
+const MAX_SEARCH_HISTORY: usize = 50;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchHistory { pub queries: Vec<String> }
+impl SearchHistory {
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path()).ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+    pub fn save_to_disk(&self) {
+        if let Some(dir) = Self::path().parent() { let _ = std::fs::create_dir_all(dir); }
+        if let Ok(json) = serde_json::to_string_pretty(self) { let _ = std::fs::write(Self::path(), json); }
+    }
+    fn path() -> PathBuf {
+        profile_dir(&active_profile()).unwrap_or_else(app_data_dir).join("search_history.json")
+    }
+    pub fn record(&mut self, query: &str) {
+        let query = query.trim();
+        if query.is_empty() { return; }
+        self.queries.retain(|q| q != query);
+        self.queries.insert(0, query.to_string());
+        self.queries.truncate(MAX_SEARCH_HISTORY);
+        self.save_to_disk();
+    }
+}
+
+const MAX_COMMAND_HISTORY: usize = 1000;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CommandHistory { pub entries: Vec<String> }
+impl CommandHistory {
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path()).ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+    pub fn save_to_disk(&self) {
+        if let Some(dir) = Self::path().parent() { let _ = std::fs::create_dir_all(dir); }
+        if let Ok(json) = serde_json::to_string(self) { let _ = std::fs::write(Self::path(), json); }
+    }
+    fn path() -> PathBuf {
+        profile_dir(&active_profile()).unwrap_or_else(app_data_dir).join("command_history.json")
+    }
+    pub fn record(&mut self, command: &str) {
+        let command = command.trim();
+        if command.is_empty() || self.entries.last().is_some_and(|last| last == command) { return; }
+        self.entries.push(command.to_string());
+        if self.entries.len() > MAX_COMMAND_HISTORY { self.entries.drain(..self.entries.len() - MAX_COMMAND_HISTORY); }
+        self.save_to_disk();
     }
 }
 
@@ -734,33 +2595,106 @@ impl SearchState {
 pub struct SavedCommand {
     pub id: u64, pub label: String, pub command: String,
     pub description: String, pub created_at: DateTime<Local>, pub use_count: u32,
+    #[serde(default)]
+    pub hotkey: Option<char>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub last_values: HashMap<String, String>,
 }
 impl SavedCommand {
     pub fn new(id: u64, command: impl Into<String>, description: impl Into<String>) -> Self {
         let cmd = command.into();
         let label = cmd.chars().take(40).collect();
-        Self { id, label, command: cmd, description: description.into(), created_at: Local::now(), use_count: 0 }
+        Self { id, label, command: cmd, description: description.into(), created_at: Local::now(), use_count: 0, hotkey: None, last_values: HashMap::new() }
+    }
+    fn placeholder_spans(&self) -> Vec<(std::ops::Range<usize>, &str)> {
+        let mut spans = Vec::new();
+        let mut rest = 0;
+        while let Some(open) = self.command[rest..].find('{').map(|i| rest + i) {
+            let Some(close) = self.command[open..].find('}').map(|i| open + i) else { break };
+            let name = &self.command[open + 1..close];
+            let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if valid && !self.command[..open].ends_with('$') {
+                spans.push((open..close + 1, name));
+                rest = close + 1;
+            } else {
+                rest = open + 1;
+            }
+        }
+        spans
+    }
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (_, name) in self.placeholder_spans() {
+            if !names.iter().any(|n| n == name) { names.push(name.to_string()); }
+        }
+        names
+    }
+    pub fn fill(&self, values: &HashMap<String, String>) -> String {
+        let mut out = String::with_capacity(self.command.len());
+        let mut last = 0;
+        for (span, name) in self.placeholder_spans() {
+            out.push_str(&self.command[last..span.start]);
+            let quote = quote_context(&self.command[..span.start]);
+            out.push_str(&shell_quote(values.get(name).map(String::as_str).unwrap_or(""), quote));
+            last = span.end;
+        }
+        out.push_str(&self.command[last..]);
+        out
+    }
+}
+
+fn quote_context(prefix: &str) -> Option<char> {
+    let mut quote = None;
+    let mut chars = prefix.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') | (Some('$'), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => { chars.next(); }
+            (None, '$') if chars.peek() == Some(&'\'') => { chars.next(); quote = Some('$'); }
+            (None, '\'' | '"') => quote = Some(c),
+            _ => {}
+        }
+    }
+    quote
+}
+
+fn shell_quote(value: &str, quote: Option<char>) -> String {
+    let plain = value.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:@%+=,".contains(c));
+    match quote {
+        Some('\'') => Spiltixal::shell_escape_single(value),
+        Some('$') => value.replace('\\', "\\\\").replace('\'', "\\'"),
+        _ if plain && (quote.is_some() || !value.is_empty()) => value.to_string(),
+        Some(_) => format!("\"'{}'\"", Spiltixal::shell_escape_single(value)),
+        None => format!("'{}'", Spiltixal::shell_escape_single(value)),
     }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
-pub struct SavedCommandStore { pub commands: Vec<SavedCommand>, next_id: u64 }
+pub struct SavedCommandStore {
+    pub commands: Vec<SavedCommand>, next_id: u64,
+    #[serde(skip)]
+    path: PathBuf,
+}
 impl SavedCommandStore {
-    pub fn load() -> Self {
-        let p = Self::path();
-        if p.exists() {
-            if let Ok(data) = std::fs::read_to_string(&p) {
-                if let Ok(s) = serde_json::from_str::<SavedCommandStore>(&data) { return s; }
+    pub fn load() -> Self { Self::load_from(Self::default_path()) }
+    pub fn load_from(path: PathBuf) -> Self {
+        let mut store = Self::default();
+        if path.exists() {
+            if let Ok(data) = std::fs::read_to_string(&path) {
+                if let Ok(s) = serde_json::from_str::<SavedCommandStore>(&data) { store = s; }
             }
         }
-        Self::default()
+        store.path = path;
+        store
     }
     pub fn save_to_disk(&self) {
-        if let Some(dir) = Self::path().parent() { let _ = std::fs::create_dir_all(dir); }
-        if let Ok(json) = serde_json::to_string_pretty(self) { let _ = std::fs::write(Self::path(), json); }
+        if let Some(dir) = self.path.parent() { let _ = std::fs::create_dir_all(dir); }
+        if let Ok(json) = serde_json::to_string_pretty(self) { let _ = std::fs::write(&self.path, json); }
     }
-    fn path() -> PathBuf {
-        dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("spiltixal").join("saved_commands.json")
+    fn default_path() -> PathBuf {
+        profile_dir(&active_profile()).unwrap_or_else(app_data_dir).join("saved_commands.json")
     }
     pub fn add(&mut self, command: impl Into<String>, description: impl Into<String>) -> u64 {
         let id = self.next_id; self.next_id += 1;
@@ -768,9 +2702,28 @@ impl SavedCommandStore {
         self.save_to_disk(); id
     }
     pub fn remove(&mut self, id: u64) { self.commands.retain(|c| c.id != id); self.save_to_disk(); }
+    pub fn update(&mut self, id: u64, command: impl Into<String>, description: impl Into<String>) -> bool {
+        let Some(c) = self.commands.iter_mut().find(|c| c.id == id) else { return false };
+        c.command = command.into();
+        c.label = c.command.chars().take(40).collect();
+        c.description = description.into();
+        self.save_to_disk(); true
+    }
     pub fn increment_use(&mut self, id: u64) {
         if let Some(c) = self.commands.iter_mut().find(|c| c.id == id) { c.use_count += 1; self.save_to_disk(); }
     }
+    pub fn remember_values(&mut self, id: u64, values: HashMap<String, String>) {
+        if let Some(c) = self.commands.iter_mut().find(|c| c.id == id) { c.last_values = values; self.save_to_disk(); }
+    }
+    pub fn set_hotkey(&mut self, id: u64, hotkey: Option<char>) {
+        for c in &mut self.commands {
+            if c.id == id { c.hotkey = hotkey; } else if hotkey.is_some() && c.hotkey == hotkey { c.hotkey = None; }
+        }
+        self.save_to_disk();
+    }
+    pub fn by_hotkey(&self, key: char) -> Option<&SavedCommand> {
+        self.commands.iter().find(|c| c.hotkey == Some(key))
+    }
     pub fn search(&self, q: &str) -> Vec<&SavedCommand> {
         if q.is_empty() { return self.commands.iter().collect(); }
         let q = q.to_lowercase();
@@ -780,109 +2733,357 @@ impl SavedCommandStore {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct ChatMessage { pub role: String, pub content: String }
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String, pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(skip)]
+    pub image_paths: Vec<PathBuf>,
+}
+impl ChatMessage {
+    fn context_chars(&self) -> usize {
+        self.content.len() + (self.images.len() + self.image_paths.len()) * AI_IMAGE_CONTEXT_CHARS
+    }
+}
+
+#[derive(Serialize)]
+struct OllamaReq<'a> {
+    model: &'a str, prompt: &'a str, stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
 
 #[derive(Serialize)]
-struct OllamaReq<'a> { model: &'a str, prompt: &'a str, stream: bool }
+struct ChatReq<'a> { model: &'a str, messages: Vec<serde_json::Value>, stream: bool }
 
 #[derive(Deserialize)]
-struct OllamaResp { response: String }
+struct OllamaResp {
+    #[serde(default)] response: String,
+    #[serde(default)] message: Option<ChatMessage>,
+    #[serde(default)] done: bool,
+    #[serde(default)] error: Option<String>,
+}
 
 pub enum AiEvent { Token(String), Done, Error(String) }
 
-#[derive(Clone)]
-pub struct AiClient { pub endpoint: String, pub model: String, pub system_prompt: String }
-impl AiClient {
-    pub fn new(endpoint: impl Into<String>, model: impl Into<String>, system_prompt: impl Into<String>) -> Self {
-        Self { endpoint: endpoint.into(), model: model.into(), system_prompt: system_prompt.into() }
-    }
-    pub fn send_async(&self, history: Vec<ChatMessage>, tx: Sender<AiEvent>) {
-        let endpoint = self.endpoint.clone();
-        let model    = self.model.clone();
-        let sys      = self.system_prompt.clone();
-        thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build();
-            match rt {
-                Err(e) => { let _ = tx.send(AiEvent::Error(e.to_string())); }
-                Ok(rt) => rt.block_on(async move {
-                    match Self::call(&endpoint, &model, &sys, &history).await {
-                        Ok(reply) => { let _ = tx.send(AiEvent::Token(reply)); let _ = tx.send(AiEvent::Done); }
-                        Err(e)    => {
-                            let msg = if e.to_string().contains("404") {
-                                format!("Model not found. Run: ollama pull {}", model)
-                            } else if e.to_string().contains("Connection refused") || e.to_string().contains("error sending request") {
-                                "Ollama not running. Start it: ollama serve".into()
-                            } else {
-                                e.to_string()
-                            };
-                            let _ = tx.send(AiEvent::Error(msg));
-                        }
-                    }
-                }),
-            }
-        });
+const AI_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const AI_WATCHDOG_MARGIN: Duration = Duration::from_secs(15);
+const AI_IMAGE_CONTEXT_CHARS: usize = 4096;
+
+fn parse_ai_endpoint(raw: &str) -> Result<reqwest::Url> {
+    let raw = raw.trim();
+    let mut url = reqwest::Url::parse(raw).with_context(|| format!("Invalid AI endpoint {raw:?}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("Invalid AI endpoint {raw:?}: scheme must be http or https, not {}", url.scheme());
     }
-    async fn call(endpoint: &str, model: &str, sys: &str, history: &[ChatMessage]) -> Result<String> {
-        let client = reqwest::Client::builder().timeout(Duration::from_secs(60)).build()?;
-        let prompt = format!("{}\n\n{}",
-            sys,
-            history.iter().map(|m| format!("{}: {}", m.role, m.content)).collect::<Vec<_>>().join("\n")
-        );
-        let generate_url = if endpoint.ends_with("/api/chat") {
-            endpoint.replace("/api/chat", "/api/generate")
-        } else if endpoint.ends_with("/api/generate") {
-            endpoint.to_string()
-        } else {
-            format!("{}/api/generate", endpoint.trim_end_matches('/'))
-        };
-        let resp = client.post(&generate_url)
-            .json(&OllamaReq { model, prompt: &prompt, stream: false })
-            .send().await?.error_for_status()?.json::<OllamaResp>().await?;
-        Ok(resp.response.trim().to_string())
+    if url.host_str().is_none() { anyhow::bail!("Invalid AI endpoint {raw:?}: missing host"); }
+    if let Ok(mut segments) = url.path_segments_mut() { segments.pop_if_empty(); }
+    url.set_fragment(None);
+    Ok(url)
+}
+
+fn endpoint_is_loopback(url: &reqwest::Url) -> bool {
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host.trim_matches(|c| c == '[' || c == ']').parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback()),
+        None => false,
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum Emotion { Happy, Neutral, Thinking, Curious, Worried, Excited, Confused }
+fn ai_endpoint_warning(url: &reqwest::Url) -> Option<String> {
+    (url.scheme() == "http" && !endpoint_is_loopback(url))
+        .then(|| format!("{} is remote but uses plain http; prompts and terminal output are sent unencrypted.", url.host_str().unwrap_or_default()))
+}
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum MateView { Chat, SavedCommands }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AiBackend {
+    #[default]
+    Ollama,
+    OllamaGenerate,
+    OpenAiCompatible,
+}
 
-pub struct Mate {
-    pub name:           String,
+#[derive(Deserialize)]
+struct OpenAiChunk { #[serde(default)] choices: Vec<OpenAiChoice> }
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    #[serde(default)] delta: OpenAiDelta,
+    #[serde(default)] finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct OpenAiDelta { #[serde(default)] content: Option<String> }
+
+#[derive(Clone)]
+pub struct AiClient {
+    pub endpoint: reqwest::Url, pub model: String, pub system_prompt: String,
+    pub backend: AiBackend, pub api_key: Option<String>,
+    pub context_chars: usize,
+}
+impl AiClient {
+    pub fn new(endpoint: &str, model: impl Into<String>, system_prompt: impl Into<String>) -> Result<Self> {
+        let endpoint = parse_ai_endpoint(endpoint)?;
+        if let Some(warning) = ai_endpoint_warning(&endpoint) { log::warn!("{warning}"); }
+        Ok(Self { endpoint, model: model.into(), system_prompt: system_prompt.into(), backend: AiBackend::Ollama, api_key: None, context_chars: default_ai_context_chars() })
+    }
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let api_key = Some(config.ai_api_key.trim().to_string()).filter(|k| !k.is_empty());
+        Ok(Self { backend: config.ai_backend, api_key, context_chars: config.ai_context_chars, ..Self::new(&config.ai_endpoint, &config.ai_model, &config.ai_system_prompt)? })
+    }
+    fn request_url(&self) -> reqwest::Url {
+        let mut url = self.endpoint.clone();
+        let mut segments: Vec<String> = url.path_segments()
+            .map(|s| s.filter(|s| !s.is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+        let tail: Vec<&str> = segments.iter().rev().take(2).map(String::as_str).collect();
+        match (self.backend, tail.as_slice()) {
+            (AiBackend::Ollama, ["chat", "api"]) => {}
+            (AiBackend::Ollama, ["generate", "api"]) => { segments.pop(); segments.push("chat".into()); }
+            (AiBackend::Ollama, _) => segments.extend(["api".into(), "chat".into()]),
+            (AiBackend::OllamaGenerate, ["chat", "api"]) => { segments.pop(); segments.push("generate".into()); }
+            (AiBackend::OllamaGenerate, ["generate", "api"]) => {}
+            (AiBackend::OllamaGenerate, _) => segments.extend(["api".into(), "generate".into()]),
+            (AiBackend::OpenAiCompatible, ["completions", "chat"]) => {}
+            (AiBackend::OpenAiCompatible, ["v1", ..]) => segments.extend(["chat".into(), "completions".into()]),
+            (AiBackend::OpenAiCompatible, _) => segments.extend(["v1".into(), "chat".into(), "completions".into()]),
+        }
+        if let Ok(mut path) = url.path_segments_mut() { path.clear().extend(&segments); }
+        url
+    }
+    pub fn fit_context(&self, history: &mut Vec<ChatMessage>) -> usize {
+        let mut budget = self.context_chars.saturating_sub(self.system_prompt.len());
+        let mut keep = 0;
+        for (i, msg) in history.iter().enumerate().rev() {
+            if i + 1 < history.len() && msg.context_chars() > budget { break; }
+            budget = budget.saturating_sub(msg.context_chars());
+            keep += 1;
+        }
+        let dropped = history.len() - keep;
+        if dropped > 0 {
+            history.drain(..dropped);
+            log::info!("trimmed {dropped} older chat message(s) to stay within {} context chars", self.context_chars);
+        }
+        dropped
+    }
+    pub fn send_async(&self, mut history: Vec<ChatMessage>, tx: Sender<AiEvent>) {
+        let client = self.clone();
+        thread::spawn(move || {
+            let panic_tx = tx.clone();
+            let worker = std::panic::AssertUnwindSafe(move || {
+                for msg in &mut history {
+                    let paths = std::mem::take(&mut msg.image_paths);
+                    msg.images.extend(paths.iter().filter_map(encode_image_for_ai));
+                }
+                client.run_request(history, tx)
+            });
+            if let Err(panic) = std::panic::catch_unwind(worker) {
+                let what = panic.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".into());
+                let _ = panic_tx.send(AiEvent::Error(format!("AI worker crashed: {what}")));
+            }
+        });
+    }
+    fn run_request(&self, history: Vec<ChatMessage>, tx: Sender<AiEvent>) {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build();
+        match rt {
+            Err(e) => { let _ = tx.send(AiEvent::Error(e.to_string())); }
+            Ok(rt) => rt.block_on(async move {
+                match self.call(&history, &tx).await {
+                    Ok(())  => { let _ = tx.send(AiEvent::Done); }
+                    Err(e)  => {
+                        let text = e.to_string();
+                        let unreachable = text.contains("Connection refused") || text.contains("error sending request");
+                        let ollama = matches!(self.backend, AiBackend::Ollama | AiBackend::OllamaGenerate);
+                        let msg = match self.backend {
+                            _ if ollama && text.contains("404") => format!("Model not found. Run: ollama pull {}", self.model),
+                            _ if ollama && unreachable => "Ollama not running. Start it: ollama serve".into(),
+                            AiBackend::OpenAiCompatible if text.contains("404") => format!("Endpoint or model {} not found (404).", self.model),
+                            AiBackend::OpenAiCompatible if text.contains("401") => "The AI server rejected the API key (401).".into(),
+                            AiBackend::OpenAiCompatible if unreachable => format!("Couldn't reach the AI server at {}.", self.endpoint),
+                            _ => text,
+                        };
+                        let _ = tx.send(AiEvent::Error(msg));
+                    }
+                }
+            }),
+        }
+    }
+    async fn call(&self, history: &[ChatMessage], tx: &Sender<AiEvent>) -> Result<()> {
+        let client = reqwest::Client::builder().timeout(AI_REQUEST_TIMEOUT).build()?;
+        let mut req = client.post(self.request_url());
+        if let Some(key) = &self.api_key { req = req.bearer_auth(key); }
+        let req = match self.backend {
+            AiBackend::OllamaGenerate => {
+                let prompt = format!("{}\n\n{}",
+                    self.system_prompt,
+                    history.iter().map(|m| format!("{}: {}", m.role, m.content)).collect::<Vec<_>>().join("\n")
+                );
+                let images = history.last().map(|m| m.images.clone()).unwrap_or_default();
+                req.json(&OllamaReq { model: &self.model, prompt: &prompt, stream: true, images })
+            }
+            AiBackend::Ollama | AiBackend::OpenAiCompatible => {
+                let system = ChatMessage { role: "system".into(), content: self.system_prompt.clone(), ..Default::default() };
+                let messages = std::iter::once(&system).chain(history).map(|m| self.wire_message(m)).collect();
+                req.json(&ChatReq { model: &self.model, messages, stream: true })
+            }
+        };
+        let mut resp = req.send().await?.error_for_status()?;
+        let mut pending: Vec<u8> = Vec::new();
+        let mut started = false;
+        while let Some(chunk) = resp.chunk().await? {
+            pending.extend_from_slice(&chunk);
+            while let Some(nl) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=nl).collect();
+                if self.emit_chunk(&line, &mut started, tx)? { return Ok(()); }
+            }
+        }
+        self.emit_chunk(&pending, &mut started, tx)?;
+        Ok(())
+    }
+    fn wire_message(&self, msg: &ChatMessage) -> serde_json::Value {
+        if self.backend != AiBackend::OpenAiCompatible || msg.images.is_empty() {
+            return serde_json::to_value(msg).unwrap_or_default();
+        }
+        let mut parts = vec![serde_json::json!({ "type": "text", "text": msg.content })];
+        parts.extend(msg.images.iter().map(|b64| serde_json::json!({
+            "type": "image_url", "image_url": { "url": format!("data:image/png;base64,{b64}") }
+        })));
+        serde_json::json!({ "role": msg.role, "content": parts })
+    }
+    fn parse_chunk(&self, line: &str) -> Result<Option<(String, bool)>> {
+        match self.backend {
+            AiBackend::Ollama | AiBackend::OllamaGenerate => {
+                let Ok(chunk) = serde_json::from_str::<OllamaResp>(line) else { return Ok(None) };
+                if let Some(err) = chunk.error { anyhow::bail!(err); }
+                let text = chunk.message.map(|m| m.content).unwrap_or(chunk.response);
+                Ok(Some((text, chunk.done)))
+            }
+            AiBackend::OpenAiCompatible => {
+                let Some(data) = line.strip_prefix("data:").map(str::trim) else { return Ok(Some((String::new(), false))) };
+                if data == "[DONE]" { return Ok(Some((String::new(), true))); }
+                let Ok(chunk) = serde_json::from_str::<OpenAiChunk>(data) else { return Ok(None) };
+                let Some(choice) = chunk.choices.into_iter().next() else { return Ok(Some((String::new(), false))) };
+                Ok(Some((choice.delta.content.unwrap_or_default(), choice.finish_reason.is_some())))
+            }
+        }
+    }
+    fn emit_chunk(&self, line: &[u8], started: &mut bool, tx: &Sender<AiEvent>) -> Result<bool> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        if line.is_empty() { return Ok(false); }
+        let Some((response, done)) = self.parse_chunk(line)? else {
+            log::warn!("skipping malformed AI stream line: {line}");
+            return Ok(false);
+        };
+        let text = if *started { response.as_str() } else { response.trim_start() };
+        if !text.is_empty() {
+            *started = true;
+            let _ = tx.send(AiEvent::Token(text.to_string()));
+        }
+        Ok(done)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emotion { Happy, Neutral, Thinking, Curious, Worried, Excited, Confused }
+
+const EMOTION_MIN_DWELL: Duration = Duration::from_millis(1000);
+const EMOTION_RESET_AFTER: Duration = Duration::from_secs(30);
+const WORD_TYPING_MAX_INTERVAL: Duration = Duration::from_millis(8);
+const MAX_CHAT_HISTORY: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MateView { Chat, SavedCommands }
+
+pub struct Mate {
+    pub name:           String,
     pub emotion:        Emotion,
     pub chat_history:   Vec<ChatMessage>,
     pub input_text:     String,
     pub save_box_text:  String,
     pub save_desc_text: String,
     pub reply_pending:  bool,
+    pub streaming:      bool,
     pub last_message:   String,
     pub view:           MateView,
     pub commands:       SavedCommandStore,
     pub ai_client:      Option<AiClient>,
     pub event_rx:       Option<Receiver<AiEvent>>,
+    pub pending_since:  Option<Instant>,
     pub emotion_timer:  Option<Instant>,
+    pub emotion_since:  Instant,
+    pub queued_emotion: Option<Emotion>,
+    pub typing_interval: Option<Duration>,
     pub customize_mode: bool,
     pub typing_target:  String,
     pub typing_chars:   usize,
     pub typing_tick:    Instant,
     pub attach_path:    String,
+    pub pending_images: Vec<PathBuf>,
+    pub pending_context: Option<PendingContext>,
+    pub focus_input:    bool,
+    pub editing_saved:  Option<SavedCommandEdit>,
+    pub chat_history_path: PathBuf,
+}
+
+pub struct SavedCommandEdit {
+    pub id:          u64,
+    pub command:     String,
+    pub description: String,
+}
+
+pub struct PendingContext {
+    pub label: String,
+    pub body:  String,
 }
 impl Mate {
     pub fn new(name: String, ai_client: Option<AiClient>) -> Self {
+        Self::with_storage(name, ai_client, SavedCommandStore::load(), Self::default_chat_history_path())
+    }
+    pub fn with_storage(name: String, ai_client: Option<AiClient>, commands: SavedCommandStore, chat_history_path: PathBuf) -> Self {
         let greeting = format!("{name} here. I am connected to your terminal. Send a file path or ask me to run a command.");
         Self {
-            name, emotion: Emotion::Happy, chat_history: Vec::new(),
+            name, emotion: Emotion::Happy, chat_history: Self::load_chat_history(&chat_history_path),
             input_text: String::new(), save_box_text: String::new(), save_desc_text: String::new(),
-            reply_pending: false, last_message: greeting.clone(), view: MateView::Chat,
-            commands: SavedCommandStore::load(), ai_client, event_rx: None,
-            emotion_timer: None, customize_mode: false,
+            reply_pending: false, streaming: false, last_message: greeting.clone(), view: MateView::Chat,
+            commands, ai_client, event_rx: None, pending_since: None,
+            emotion_timer: None, emotion_since: Instant::now(), queued_emotion: None, customize_mode: false,
+            typing_interval: Some(Duration::from_millis(18)),
             typing_target: greeting, typing_chars: usize::MAX, typing_tick: Instant::now(),
-            attach_path: String::new(),
+            attach_path: String::new(), pending_images: Vec::new(),
+            pending_context: None, focus_input: false, editing_saved: None, chat_history_path,
         }
     }
 
+    fn default_chat_history_path() -> PathBuf {
+        profile_dir(&active_profile()).unwrap_or_else(app_data_dir).join("chat_history.json")
+    }
+    pub fn load_chat_history(path: &Path) -> Vec<ChatMessage> {
+        std::fs::read_to_string(path).ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+    pub fn save_chat_history(&mut self) {
+        if self.chat_history.len() > MAX_CHAT_HISTORY { self.chat_history.drain(..self.chat_history.len() - MAX_CHAT_HISTORY); }
+        let path = &self.chat_history_path;
+        if let Some(dir) = path.parent() { let _ = std::fs::create_dir_all(dir); }
+        let stored: Vec<ChatMessage> = self.chat_history.iter()
+            .map(|m| ChatMessage { role: m.role.clone(), content: m.content.clone(), ..Default::default() })
+            .collect();
+        if let Ok(json) = serde_json::to_string_pretty(&stored) { let _ = std::fs::write(path, json); }
+    }
+    pub fn clear_conversation(&mut self) {
+        self.chat_history.clear();
+        let _ = std::fs::remove_file(&self.chat_history_path);
+        self.last_message = "conversation cleared.".into();
+        self.typing_target = self.last_message.clone();
+        self.typing_chars = 0;
+        self.typing_tick = Instant::now();
+    }
+
     pub fn emotion_from_text(text: &str) -> Emotion {
         let t = text.to_lowercase();
         if t.contains("error") || t.contains("fail") || t.contains("crash") || t.contains("kill") || t.contains("rm -rf") {
@@ -902,9 +3103,21 @@ impl Mate {
 
     pub fn tick_typing(&mut self) {
         if self.typing_chars >= self.typing_target.len() { return; }
-        if self.typing_tick.elapsed() >= Duration::from_millis(18) {
+        let Some(interval) = self.typing_interval else {
+            self.typing_chars = usize::MAX;
+            return;
+        };
+        if self.typing_tick.elapsed() >= interval {
             let remaining = &self.typing_target[self.typing_chars..];
-            let next = remaining.char_indices().nth(1).map(|(i, _)| self.typing_chars + i).unwrap_or(self.typing_target.len());
+            let step = if interval <= WORD_TYPING_MAX_INTERVAL {
+                remaining.char_indices()
+                    .skip_while(|(_, c)| c.is_whitespace())
+                    .find(|(_, c)| c.is_whitespace())
+                    .map(|(i, _)| i)
+            } else {
+                remaining.char_indices().nth(1).map(|(i, _)| i)
+            };
+            let next = step.map(|i| self.typing_chars + i).unwrap_or(self.typing_target.len());
             self.typing_chars = next;
             self.typing_tick = Instant::now();
         }
@@ -914,34 +3127,102 @@ impl Mate {
         if self.typing_chars >= self.typing_target.len() {
             &self.typing_target
         } else {
-            &self.typing_target[..self.typing_chars]
+            let mut end = self.typing_chars;
+            while !self.typing_target.is_char_boundary(end) { end -= 1; }
+            &self.typing_target[..end]
         }
     }
 
     pub fn is_typing(&self) -> bool {
         self.typing_chars < self.typing_target.len()
     }
+    pub fn set_emotion(&mut self, emotion: Emotion) {
+        let thinking_locked = self.reply_pending && self.emotion == Emotion::Thinking;
+        if emotion == self.emotion {
+            self.queued_emotion = None;
+        } else if thinking_locked || self.emotion_since.elapsed() < EMOTION_MIN_DWELL {
+            self.queued_emotion = Some(emotion);
+        } else {
+            self.emotion = emotion;
+            self.emotion_since = Instant::now();
+            self.queued_emotion = None;
+        }
+    }
+
+    fn tick_emotion(&mut self) {
+        if let Some(emotion) = self.queued_emotion.take() { self.set_emotion(emotion); }
+        if self.reply_pending { return; }
+        if self.emotion_timer.is_some_and(|t| t.elapsed() > EMOTION_RESET_AFTER) {
+            self.set_emotion(Emotion::Neutral);
+            self.emotion_timer = None;
+        }
+    }
+
     pub fn poll_ai(&mut self) {
-        if self.event_rx.is_none() { return; }
-        let mut reply = String::new(); let mut done = false;
-        while let Ok(ev) = self.event_rx.as_ref().unwrap().try_recv() {
-            match ev {
-                AiEvent::Token(t) => reply.push_str(&t),
-                AiEvent::Done     => done = true,
-                AiEvent::Error(e) => { reply = e; done = true; }
+        let mut reply = String::new(); let mut error = None; let mut done = false; let mut disconnected = false;
+        if let Some(rx) = &self.event_rx {
+            loop {
+                match rx.try_recv() {
+                    Ok(AiEvent::Token(t)) => reply.push_str(&t),
+                    Ok(AiEvent::Done)     => done = true,
+                    Ok(AiEvent::Error(e)) => { error = Some(e); done = true; }
+                    Err(crossbeam_channel::TryRecvError::Empty) => break,
+                    Err(crossbeam_channel::TryRecvError::Disconnected) => { disconnected = true; break; }
+                }
             }
+            if !reply.is_empty() || done { self.pending_since = Some(Instant::now()); }
         }
         if !reply.is_empty() {
-            self.last_message = reply.clone();
-            self.typing_target = reply.clone();
-            self.typing_chars = 0;
-            self.typing_tick = Instant::now();
-            self.chat_history.push(ChatMessage { role: "assistant".into(), content: reply });
+            if self.streaming {
+                if let Some(last) = self.chat_history.last_mut() { last.content.push_str(&reply); }
+                self.typing_target.push_str(&reply);
+                self.last_message = self.typing_target.clone();
+            } else {
+                self.streaming = true;
+                self.last_message = reply.clone();
+                self.typing_target = reply.clone();
+                self.typing_chars = if self.typing_interval.is_some() { 0 } else { usize::MAX };
+                self.typing_tick = Instant::now();
+                self.chat_history.push(ChatMessage { role: "assistant".into(), content: reply, ..Default::default() });
+            }
+        }
+        if let Some(e) = error {
+            if self.streaming { self.typing_target.push_str(&format!("\n\n[{e}]")); } else {
+                self.typing_target = e;
+                self.typing_chars = 0;
+                self.typing_tick = Instant::now();
+            }
+            self.last_message = self.typing_target.clone();
         }
-        if done { self.reply_pending = false; self.emotion = Emotion::Happy; self.event_rx = None; }
-        if let Some(t) = self.emotion_timer {
-            if t.elapsed() > Duration::from_secs(30) { self.emotion = Emotion::Neutral; self.emotion_timer = None; }
+        if done {
+            self.streaming = false;
+            if let Some(last) = self.chat_history.last_mut().filter(|m| m.role == "assistant") {
+                last.content = last.content.trim_end().to_string();
+            }
+            self.save_chat_history();
+            self.reply_pending = false;
+            self.event_rx = None;
+            self.pending_since = None;
+            self.set_emotion(Emotion::Happy);
+            self.emotion_timer = Some(Instant::now());
+        } else if self.reply_pending && disconnected {
+            self.abandon_reply("The AI worker stopped without replying. Try again.");
+        } else if self.reply_pending && self.pending_since.is_some_and(|t| t.elapsed() > AI_REQUEST_TIMEOUT + AI_WATCHDOG_MARGIN) {
+            self.abandon_reply(&format!("No reply after {}s; gave up waiting.", (AI_REQUEST_TIMEOUT + AI_WATCHDOG_MARGIN).as_secs()));
         }
+        self.tick_emotion();
+    }
+    pub fn abandon_reply(&mut self, reason: &str) {
+        self.reply_pending = false;
+        self.streaming = false;
+        self.event_rx = None;
+        self.pending_since = None;
+        self.last_message = reason.to_string();
+        self.typing_target = self.last_message.clone();
+        self.typing_chars = 0;
+        self.typing_tick = Instant::now();
+        self.emotion_timer = None;
+        self.set_emotion(Emotion::Neutral);
     }
     pub fn send_message(&mut self, msg: String) {
         if msg.trim().eq_ignore_ascii_case("customize") {
@@ -951,18 +3232,27 @@ impl Mate {
             self.typing_tick = Instant::now();
             self.customize_mode = true; return;
         }
-        self.emotion = Self::emotion_from_text(&msg);
-        self.chat_history.push(ChatMessage { role: "user".into(), content: msg.clone() });
-        let thinking_msg = "Working...".to_string();
-        self.last_message = thinking_msg.clone();
-        self.typing_target = thinking_msg;
-        self.typing_chars = usize::MAX;
+        self.emotion_timer = Some(Instant::now());
+        let images = std::mem::take(&mut self.pending_images);
+        self.chat_history.push(ChatMessage { role: "user".into(), content: msg.clone(), ..Default::default() });
+        self.save_chat_history();
+        let mut thinking_msg = "Working...".to_string();
         if let Some(client) = &self.ai_client {
+            let mut history = self.chat_history.clone();
+            if let Some(last) = history.last_mut() { last.image_paths = images; }
+            let dropped = client.fit_context(&mut history);
+            if dropped > 0 { thinking_msg = format!("Working... (left out {dropped} older message(s) to fit the context budget)"); }
             let (tx, rx) = unbounded::<AiEvent>();
-            client.send_async(self.chat_history.clone(), tx);
-            self.event_rx = Some(rx); self.reply_pending = true;
-            self.emotion = Emotion::Thinking; self.emotion_timer = Some(Instant::now());
+            client.send_async(history, tx);
+            self.event_rx = Some(rx);
+            self.set_emotion(Emotion::Thinking);
+            self.reply_pending = true;
+            self.pending_since = Some(Instant::now());
+            self.last_message = thinking_msg.clone();
+            self.typing_target = thinking_msg;
+            self.typing_chars = usize::MAX;
         } else {
+            self.set_emotion(Self::emotion_from_text(&msg));
             let offline = "AI is disabled. Toggle AI to enable it.".to_string();
             self.last_message = offline.clone();
             self.typing_target = offline;
@@ -970,7 +3260,28 @@ impl Mate {
             self.typing_tick = Instant::now();
         }
     }
-    pub fn delete_saved(&mut self, id: u64) { self.commands.remove(id); }
+    pub fn delete_saved(&mut self, id: u64) {
+        self.commands.remove(id);
+        if self.editing_saved.as_ref().is_some_and(|e| e.id == id) { self.editing_saved = None; }
+    }
+    pub fn edit_saved(&mut self, id: u64) {
+        let Some(c) = self.commands.commands.iter().find(|c| c.id == id) else { return };
+        self.editing_saved = Some(SavedCommandEdit { id, command: c.command.clone(), description: c.description.clone() });
+    }
+    pub fn finish_edit(&mut self) {
+        let Some(edit) = &self.editing_saved else { return };
+        let cmd = edit.command.trim().to_string();
+        if cmd.is_empty() { return; }
+        let (id, desc) = (edit.id, edit.description.trim().to_string());
+        self.editing_saved = None;
+        if self.commands.update(id, cmd, desc) {
+            let msg = "updated it.".to_string();
+            self.last_message = msg.clone();
+            self.typing_target = msg;
+            self.typing_chars = 0;
+            self.typing_tick = Instant::now();
+        }
+    }
     pub fn save_command(&mut self) {
         let cmd  = self.save_box_text.trim().to_string();
         let desc = self.save_desc_text.trim().to_string();
@@ -982,7 +3293,8 @@ impl Mate {
             self.typing_target = msg;
             self.typing_chars = 0;
             self.typing_tick = Instant::now();
-            self.emotion = Emotion::Happy;
+            self.set_emotion(Emotion::Happy);
+            self.emotion_timer = Some(Instant::now());
         }
     }
 }
@@ -992,7 +3304,7 @@ pub struct CustomizeState {
     pub open: bool,
     pub fg_color: [u8; 4], pub bg_solid: [u8; 4],
     pub use_gradient: bool, pub grad_a: [u8; 4], pub grad_b: [u8; 4], pub grad_angle: f32,
-    pub font_size: f32, pub bg_opacity: f32,
+    pub font_size: f32, pub scrollback_lines: usize, pub font_family: String, pub mono_fonts: Vec<MonoFont>, pub bg_opacity: f32, pub terminal_padding: f32, pub line_height_factor: f32,
     pub bg_image: Option<PathBuf>, pub bg_video: Option<PathBuf>,
     pub happy_path: Option<PathBuf>, pub neutral_path: Option<PathBuf>, pub thinking_path: Option<PathBuf>,
     pub bg_image_input: String, pub bg_video_input: String,
@@ -1005,12 +3317,79 @@ pub struct CustomizeState {
     pub selected_layer: Option<usize>,
     pub drawing: Vec<DrawStroke>,
     pub active_stroke: Vec<Pos2>,
+    pub draw_tool: DrawTool,
+    pub stroke_color: [u8; 4],
     pub stroke_width: f32,
     pub draw_opacity: u8,
     pub drag_layer: Option<usize>,
     pub drag_offset: Vec2,
+    pub resize_anchor: Option<Pos2>,
+    pub resize_aspect: f32,
     pub save_message: String,
     pub reset_confirm_step: usize,
+    pub settings_open: bool,
+    pub prompt_regex: String,
+    pub login_shell: bool,
+    pub shell_args: String,
+    pub working_directory: String,
+    pub env: String,
+    pub on_shell_exit: ShellExitAction,
+    pub confirm_close: bool,
+    pub log_output_path: String,
+    pub term: String,
+    pub terminfo_feedback: String,
+    pub typing_instant: bool,
+    pub typing_speed_ms: u64,
+    pub reduce_motion: bool,
+    pub animate_video_background: bool,
+    pub bob_dock: BobDock,
+    pub dim_on_unfocus: bool,
+    pub unfocused_opacity: f32,
+    pub show_timestamps: bool,
+    pub scroll_on_input: bool,
+    pub blink_rate_hz: f32,
+    pub cursor_shape: CursorShape,
+    pub palette: [[u8; 4]; 16],
+    pub cursor_color: [u8; 4],
+    pub selection_color: [u8; 4],
+    pub theme_seed: String,
+    pub theme_backup: Option<Theme>,
+    pub theme_files: Vec<(String, PathBuf)>,
+    pub theme_path_input: String,
+    pub theme_browse: bool,
+    pub theme_export_input: String,
+    pub theme_export_browse: bool,
+    pub ai_endpoint: String,
+    pub ai_model: String,
+    pub ai_backend: AiBackend,
+    pub ai_api_key: String,
+    pub ai_context_chars: usize,
+    pub ai_vision: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum PickerTarget {
+    #[default]
+    LayerPath,
+    ThemeImport,
+    ThemeExport,
+    LayoutImport,
+    LayoutExport,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Text,
+    Html,
+}
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Html => "html",
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Default)]
@@ -1024,6 +3403,59 @@ pub enum CustomizeTool {
     Theme,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum DrawTool {
+    #[default]
+    Pen,
+    Line,
+    Rectangle,
+    Ellipse,
+    Eraser,
+}
+
+impl DrawTool {
+    pub const ALL: [DrawTool; 5] = [DrawTool::Pen, DrawTool::Line, DrawTool::Rectangle, DrawTool::Ellipse, DrawTool::Eraser];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DrawTool::Pen => "Pen",
+            DrawTool::Line => "Line",
+            DrawTool::Rectangle => "Rect",
+            DrawTool::Ellipse => "Ellipse",
+            DrawTool::Eraser => "Eraser",
+        }
+    }
+
+    pub fn is_shape(self) -> bool {
+        matches!(self, DrawTool::Line | DrawTool::Rectangle | DrawTool::Ellipse)
+    }
+
+    pub fn shape_points(self, a: Pos2, b: Pos2) -> Vec<Pos2> {
+        match self {
+            DrawTool::Line => vec![a, b],
+            DrawTool::Rectangle => vec![a, pos2(b.x, a.y), b, pos2(a.x, b.y), a],
+            DrawTool::Ellipse => {
+                let center = a.lerp(b, 0.5);
+                let radius = (b - a).abs() * 0.5;
+                (0..=64)
+                    .map(|i| {
+                        let t = i as f32 / 64.0 * std::f32::consts::TAU;
+                        center + vec2(t.cos() * radius.x, t.sin() * radius.y)
+                    })
+                    .collect()
+            }
+            DrawTool::Pen | DrawTool::Eraser => Vec::new(),
+        }
+    }
+}
+
+fn distance_to_segment(p: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let len_sq = ab.length_sq();
+    let t = if len_sq > 0.0 { ((p - a).dot(ab) / len_sq).clamp(0.0, 1.0) } else { 0.0 };
+    p.distance(a + ab * t)
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum OverlayAnimation {
     #[default]
@@ -1032,6 +3464,9 @@ pub enum OverlayAnimation {
     Floating,
 }
 
+const LAYER_SIZE_RANGE: std::ops::RangeInclusive<f32> = 0.05..=0.9;
+const LAYER_HANDLE_SIZE: f32 = 9.0;
+
 #[derive(Clone)]
 pub struct OverlayLayer {
     pub path: PathBuf,
@@ -1042,6 +3477,8 @@ pub struct OverlayLayer {
     pub tint: [u8; 4],
     pub animation: OverlayAnimation,
     pub texture: Option<TextureHandle>,
+    pub frames: Vec<(TextureHandle, Duration)>,
+    pub frames_decode: Option<GifDecode>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -1099,7 +3536,8 @@ impl CustomizeState {
         };
         Self {
             open: true, fg_color: c.theme.foreground, bg_solid, use_gradient, grad_a, grad_b, grad_angle,
-            font_size: c.theme.font_size, bg_opacity: c.opacity,
+            font_size: c.theme.font_size, scrollback_lines: c.scrollback_lines, font_family: c.theme.font_family.clone(), mono_fonts: find_mono_fonts(), bg_opacity: c.opacity, terminal_padding: c.terminal_padding,
+            line_height_factor: c.line_height_factor,
             bg_image: initial_bg_image,
             bg_video: initial_bg_video,
             happy_path: c.custom_mate_happy.clone(), neutral_path: c.custom_mate_neutral.clone(),
@@ -1117,23 +3555,144 @@ impl CustomizeState {
             selected_layer: None,
             drawing: Vec::new(),
             active_stroke: Vec::new(),
+            draw_tool: DrawTool::Pen,
+            stroke_color: c.theme.foreground,
             stroke_width: 2.0,
             draw_opacity: 220,
             drag_layer: None,
             drag_offset: Vec2::ZERO,
+            resize_anchor: None,
+            resize_aspect: 1.0,
             save_message: String::new(),
             reset_confirm_step: 0,
-            ..Default::default()
+            settings_open: false,
+            prompt_regex: c.prompt_regex.clone(),
+            login_shell: c.login_shell,
+            shell_args: c.shell_args.iter().map(|a| format!("{a}\n")).collect(),
+            working_directory: c.working_directory.clone(),
+            env: c.env.iter().map(|(k, v)| format!("{k}={v}\n")).collect(),
+            on_shell_exit: c.on_shell_exit,
+            confirm_close: c.confirm_close,
+            log_output_path: c.log_output_path.clone(),
+            term: c.term.clone(),
+            typing_instant: c.typing_speed_ms.unwrap_or(0) == 0,
+            typing_speed_ms: c.typing_speed_ms.filter(|ms| *ms > 0).unwrap_or(18),
+            reduce_motion: c.reduce_motion,
+            animate_video_background: c.animate_video_background,
+            bob_dock: c.bob_dock,
+            dim_on_unfocus: c.dim_on_unfocus,
+            unfocused_opacity: c.unfocused_opacity,
+            show_timestamps: c.show_timestamps,
+            scroll_on_input: c.scroll_on_input,
+            blink_rate_hz: c.blink_rate_hz,
+            cursor_shape: c.cursor_shape,
+            terminfo_feedback: String::new(),
+            palette: c.theme.ansi_palette(),
+            cursor_color: c.theme.cursor_color,
+            selection_color: c.theme.selection_color,
+            theme_seed: String::new(),
+            theme_backup: None,
+            theme_files: Theme::discover(),
+            theme_path_input: String::new(),
+            theme_browse: false,
+            theme_export_input: String::new(),
+            theme_export_browse: false,
+            ai_endpoint: c.ai_endpoint.clone(),
+            ai_backend: c.ai_backend,
+            ai_api_key: c.ai_api_key.clone(),
+            ai_context_chars: c.ai_context_chars,
+            ai_vision: c.ai_vision,
+            ai_model: c.ai_model.clone(),
+        }
+    }
+    pub fn preview_theme(&mut self, config: &mut Config, mut theme: Theme) {
+        theme.font_size = config.theme.font_size;
+        theme.font_family = config.theme.font_family.clone();
+        self.palette = theme.ansi_palette();
+        self.fg_color = theme.foreground;
+        self.cursor_color = theme.cursor_color;
+        self.selection_color = theme.selection_color;
+        self.use_gradient = false;
+        self.bg_image = None;
+        self.bg_video = None;
+        self.bg_image_input.clear();
+        self.bg_video_input.clear();
+        match &theme.background {
+            Background::Solid(bg) => self.bg_solid = *bg,
+            Background::Gradient { stops, angle } => {
+                self.use_gradient = true;
+                if let Some(first) = stops.first() { self.grad_a = first.color; }
+                if let Some(last) = stops.last() { self.grad_b = last.color; }
+                self.grad_angle = *angle;
+            }
+            Background::Image { path, opacity } => {
+                self.bg_image = Some(path.clone());
+                self.bg_image_input = path.display().to_string();
+                self.bg_opacity = *opacity;
+            }
+            Background::Video { path, opacity } => {
+                self.bg_video = Some(path.clone());
+                self.bg_video_input = path.display().to_string();
+                self.bg_opacity = *opacity;
+            }
         }
+        let previous = std::mem::replace(&mut config.theme, theme);
+        self.theme_backup.get_or_insert(previous);
+    }
+    pub fn export_theme(&self, config: &Config, path: &str) -> Result<PathBuf> {
+        let path = path.trim();
+        if path.is_empty() { anyhow::bail!("Choose where to save the theme first."); }
+        let mut preview = config.clone();
+        self.apply_to(&mut preview);
+        let path = expand_config_path(path);
+        let path = if path.extension().is_none() { path.with_extension("json") } else { path };
+        preview.theme.save_to_file(&path)?;
+        Ok(path)
     }
     pub fn apply_to(&self, config: &mut Config) {
         config.theme.foreground          = self.fg_color;
         config.theme.font_size           = self.font_size;
+        config.scrollback_lines          = self.scrollback_lines.clamp(*SCROLLBACK_RANGE.start(), *SCROLLBACK_RANGE.end());
+        config.theme.font_family         = self.font_family.trim().to_string();
         config.opacity                   = self.bg_opacity;
+        config.terminal_padding          = self.terminal_padding;
+        config.line_height_factor        = self.line_height_factor;
         config.custom_mate_happy         = self.happy_path.clone();
         config.custom_mate_neutral       = self.neutral_path.clone();
         config.custom_mate_thinking      = self.thinking_path.clone();
         config.theme_preset              = self.theme_preset.clone();
+        config.prompt_regex              = self.prompt_regex.trim().to_string();
+        config.login_shell               = self.login_shell;
+        config.shell_args                = self.shell_args.lines().filter(|a| !a.is_empty()).map(str::to_string).collect();
+        config.working_directory         = self.working_directory.trim().to_string();
+        config.env                       = self.env.lines()
+            .filter_map(|l| l.trim().split_once('='))
+            .filter(|(k, _)| !k.trim().is_empty())
+            .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+            .collect();
+        config.on_shell_exit             = self.on_shell_exit;
+        config.confirm_close             = self.confirm_close;
+        config.log_output_path           = self.log_output_path.trim().to_string();
+        config.term                      = self.term.trim().to_string();
+        config.typing_speed_ms           = (!self.typing_instant).then_some(self.typing_speed_ms);
+        config.reduce_motion             = self.reduce_motion;
+        config.animate_video_background  = self.animate_video_background;
+        config.bob_dock                  = self.bob_dock;
+        config.dim_on_unfocus            = self.dim_on_unfocus;
+        config.unfocused_opacity         = self.unfocused_opacity;
+        config.show_timestamps           = self.show_timestamps;
+        config.scroll_on_input           = self.scroll_on_input;
+        config.blink_rate_hz             = self.blink_rate_hz;
+        config.cursor_shape              = self.cursor_shape;
+        config.theme.cursor_color        = self.cursor_color;
+        config.theme.selection_color     = self.selection_color;
+        config.theme.set_ansi_palette(&self.palette);
+        config.ai_model                  = self.ai_model.trim().to_string();
+        config.ai_backend                = self.ai_backend;
+        config.ai_api_key                = self.ai_api_key.trim().to_string();
+        config.ai_context_chars          = self.ai_context_chars;
+        config.ai_vision                 = self.ai_vision;
+        if let Ok(url) = parse_ai_endpoint(&self.ai_endpoint) { config.ai_endpoint = url.to_string(); }
         config.theme.background = if let Some(p) = &self.bg_image {
             Background::Image { path: p.clone(), opacity: self.bg_opacity }
         } else if let Some(p) = &self.bg_video {
@@ -1205,6 +3764,53 @@ fn show_customize_window(ctx: &Context, state: &mut CustomizeState, config: &mut
                     state.bg_video_input.clear();
                 }
             });
+            let mut chosen = None;
+            ui.horizontal_wrapped(|ui| {
+                for (name, path) in &state.theme_files {
+                    if ui.selectable_label(state.theme_preset == *name, name).on_hover_text(path.display().to_string()).clicked() {
+                        chosen = Some((name.clone(), path.clone()));
+                    }
+                }
+            });
+            if let Some((name, path)) = chosen {
+                match Theme::from_file(&path) {
+                    Ok(theme) => {
+                        state.preview_theme(config, theme);
+                        state.theme_preset = name;
+                        state.path_error.clear();
+                    }
+                    Err(e) => state.path_error = format!("{e:#}"),
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.label("Import theme:");
+                ui.add(egui::TextEdit::singleline(&mut state.theme_path_input).desired_width(200.0).hint_text("/path/to/theme.json"));
+                if ui.small_button("Browse...").clicked() { state.theme_browse = true; }
+                if ui.small_button("Import").clicked() {
+                    match Theme::import(Path::new(state.theme_path_input.trim())) {
+                        Ok((name, theme)) => {
+                            state.theme_files = Theme::discover();
+                            state.preview_theme(config, theme);
+                            state.theme_preset = name;
+                            state.theme_path_input.clear();
+                            state.path_error.clear();
+                        }
+                        Err(e) => state.path_error = format!("{e:#}"),
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Export theme:");
+                ui.add(egui::TextEdit::singleline(&mut state.theme_export_input).desired_width(200.0).hint_text("~/my-theme.json"));
+                if ui.small_button("Browse...").clicked() { state.theme_export_browse = true; }
+                if ui.small_button("Export").clicked() {
+                    state.path_error = match state.export_theme(config, &state.theme_export_input) {
+                        Ok(path) => format!("Saved theme to {}", path.display()),
+                        Err(e) => format!("{e:#}"),
+                    };
+                }
+            });
+            ui.label(RichText::new(format!("Theme files live in {}", themes_dir().display())).size(10.0).color(Color32::from_gray(140)));
         });
 
         egui::CollapsingHeader::new("Background").default_open(true).show(ui, |ui| {
@@ -1245,15 +3851,212 @@ fn show_customize_window(ctx: &Context, state: &mut CustomizeState, config: &mut
             });
             if let Some(p) = &state.bg_image { ui.label(format!("Using image: {}", p.display())); }
             if let Some(p) = &state.bg_video { ui.label(format!("Using video: {}", p.display())); }
+            ui.checkbox(&mut state.animate_video_background, "Play background video")
+                .on_hover_text("Decode the video with ffmpeg and loop it. Uses noticeably more CPU than a still poster frame.");
             ui.horizontal(|ui| { ui.label("Opacity:"); ui.add(egui::Slider::new(&mut state.bg_opacity, 0.2..=1.0)); });
         });
 
         egui::CollapsingHeader::new("Text & Font").show(ui, |ui| {
             ui.horizontal(|ui| { ui.label("Foreground:"); show_color_picker(ui, &mut state.fg_color); });
+            ui.horizontal(|ui| {
+                ui.label("Font:");
+                let selected = if wants_custom_font(&state.font_family) { state.font_family.clone() } else { "Built-in monospace".to_string() };
+                egui::ComboBox::from_id_source("font_family").selected_text(selected).width(200.0).show_ui(ui, |ui| {
+                    ui.selectable_value(&mut state.font_family, "monospace".to_string(), "Built-in monospace");
+                    for font in &state.mono_fonts {
+                        ui.selectable_value(&mut state.font_family, font.family.clone(), &font.family);
+                    }
+                });
+            });
+            if wants_custom_font(&state.font_family) && !state.mono_fonts.iter().any(|f| font_name_key(&f.family) == font_name_key(&state.font_family)) {
+                ui.colored_label(Color32::from_rgb(240, 180, 90), format!("{} isn't installed; the built-in font will be used.", state.font_family));
+            }
             ui.horizontal(|ui| { ui.label("Font size:");  ui.add(egui::Slider::new(&mut state.font_size, 8.0..=32.0).suffix("px")); });
+            ui.horizontal(|ui| { ui.label("Padding:");    ui.add(egui::Slider::new(&mut state.terminal_padding, 0.0..=32.0).suffix("px")); });
+            ui.horizontal(|ui| { ui.label("Line height:"); ui.add(egui::Slider::new(&mut state.line_height_factor, 0.8..=2.0).suffix("x")); });
+            ui.horizontal(|ui| {
+                ui.label("Scrollback:");
+                ui.add(egui::DragValue::new(&mut state.scrollback_lines).clamp_range(SCROLLBACK_RANGE).speed(100.0).suffix(" lines"));
+            });
+            if state.scrollback_lines > SCROLLBACK_WARN_LINES {
+                let mb = state.scrollback_lines * 120 * std::mem::size_of::<Cell>() / (1024 * 1024);
+                ui.colored_label(Color32::from_rgb(240, 180, 90), format!("Large scrollback uses memory: about {mb} MB at 120 columns."));
+            }
+            ui.checkbox(&mut state.show_timestamps, "Show line timestamps");
+            ui.checkbox(&mut state.scroll_on_input, "Jump to the bottom when typing");
+            ui.horizontal(|ui| {
+                ui.label("Text blink:");
+                ui.add(egui::Slider::new(&mut state.blink_rate_hz, 0.0..=4.0).suffix("Hz")).on_hover_text("0 keeps blinking text steady");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Cursor shape:");
+                ui.selectable_value(&mut state.cursor_shape, CursorShape::Block, "Block");
+                ui.selectable_value(&mut state.cursor_shape, CursorShape::Underline, "Underline");
+                ui.selectable_value(&mut state.cursor_shape, CursorShape::Bar, "Bar");
+            });
+            ui.add_enabled_ui(is_hyprland(), |ui| {
+                ui.checkbox(&mut state.dim_on_unfocus, "Dim when the window loses focus");
+                ui.horizontal(|ui| {
+                    ui.label("Unfocused:");
+                    ui.add_enabled(state.dim_on_unfocus, egui::Slider::new(&mut state.unfocused_opacity, 0.1..=1.0));
+                });
+            }).response.on_disabled_hover_text("Needs a transparent window (Hyprland)");
+        });
+
+        egui::CollapsingHeader::new("ANSI palette").show(ui, |ui| {
+            let swatch = 18.0;
+            let (rect, _) = ui.allocate_exact_size(vec2(swatch * 8.0 + 8.0, swatch * 2.0 + 8.0), Sense::hover());
+            let bg = if state.use_gradient { state.grad_a } else { state.bg_solid };
+            ui.painter().rect_filled(rect, 4.0, Color32::from_rgba_unmultiplied(bg[0], bg[1], bg[2], 255));
+            for (i, c) in state.palette.iter().enumerate() {
+                let min = rect.min + vec2(4.0 + (i % 8) as f32 * swatch, 4.0 + (i / 8) as f32 * swatch);
+                let cell = Rect::from_min_size(min, vec2(swatch, swatch)).shrink(2.0);
+                ui.painter().rect_filled(cell, 2.0, Color32::from_rgba_unmultiplied(c[0], c[1], c[2], c[3]));
+            }
+            ui.add_space(4.0);
+            egui::Grid::new("ansi_palette_grid").num_columns(4).spacing([10.0, 4.0]).show(ui, |ui| {
+                for (i, name) in ANSI_COLOR_NAMES.iter().enumerate() {
+                    ui.label(*name);
+                    show_color_picker(ui, &mut state.palette[i]);
+                    ui.label(format!("Bright {}", name.to_lowercase()));
+                    show_color_picker(ui, &mut state.palette[i + 8]);
+                    ui.end_row();
+                }
+            });
+            ui.add_space(4.0);
+            ui.horizontal(|ui| { ui.label("Cursor:");    show_color_picker(ui, &mut state.cursor_color); });
+            ui.horizontal(|ui| { ui.label("Selection:"); show_color_picker(ui, &mut state.selection_color); });
+            ui.add_space(4.0);
+            ui.horizontal(|ui| {
+                let mut seed = None;
+                if ui.button("Surprise me").clicked() {
+                    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+                    seed = Some(nanos);
+                }
+                ui.label("Seed:");
+                ui.add(egui::TextEdit::singleline(&mut state.theme_seed).desired_width(140.0).hint_text("hex seed"));
+                if ui.small_button("Use seed").clicked() {
+                    match u64::from_str_radix(state.theme_seed.trim(), 16) {
+                        Ok(s) => { seed = Some(s); state.path_error.clear(); }
+                        Err(_) => state.path_error = format!("Invalid seed: {}", state.theme_seed.trim()),
+                    }
+                }
+                if let Some(seed) = seed {
+                    state.theme_seed = format!("{seed:016x}");
+                    state.preview_theme(config, Theme::generate(seed));
+                }
+            });
+        });
+
+        egui::CollapsingHeader::new("Shell").show(ui, |ui| {
+            ui.label("Prompt regex (matches your PS1 on the cursor line):");
+            ui.add(egui::TextEdit::singleline(&mut state.prompt_regex).desired_width(f32::INFINITY).hint_text(r"^\S+@\S+:.*?\$ "));
+            if !state.prompt_regex.trim().is_empty() {
+                if let Err(e) = Regex::new(state.prompt_regex.trim()) {
+                    ui.colored_label(Color32::from_rgb(240, 110, 110), format!("Invalid regex: {e}"));
+                }
+            }
+            ui.checkbox(&mut state.login_shell, "Start the shell as a login shell (-l)");
+            ui.label("Shell arguments (one per line):");
+            ui.add(egui::TextEdit::multiline(&mut state.shell_args).desired_rows(2).desired_width(f32::INFINITY).font(egui::TextStyle::Monospace).hint_text("--rcfile\n~/.bashrc.alt"));
+            ui.horizontal(|ui| {
+                ui.label("Start in:");
+                ui.add(egui::TextEdit::singleline(&mut state.working_directory).desired_width(220.0).hint_text("launch directory"));
+            });
+            ui.label("Extra environment (KEY=value per line):");
+            ui.add(egui::TextEdit::multiline(&mut state.env).desired_rows(3).desired_width(f32::INFINITY).font(egui::TextStyle::Monospace));
+            ui.label(RichText::new("Shell settings apply the next time the shell starts.").size(10.0).color(Color32::from_gray(140)));
+            ui.horizontal(|ui| {
+                ui.label("When the shell exits:");
+                ui.selectable_value(&mut state.on_shell_exit, ShellExitAction::Close, "Close");
+                ui.selectable_value(&mut state.on_shell_exit, ShellExitAction::Restart, "Restart");
+                ui.selectable_value(&mut state.on_shell_exit, ShellExitAction::Hold, "Hold");
+            });
+            ui.checkbox(&mut state.confirm_close, "Ask before closing while a program is running");
+            ui.horizontal(|ui| {
+                ui.label("Output log:");
+                ui.add(egui::TextEdit::singleline(&mut state.log_output_path).desired_width(220.0).hint_text("off"))
+                    .on_hover_text("Append everything the shell prints to this file (rotated at 10 MB)");
+            });
+            ui.horizontal(|ui| {
+                ui.label("TERM:");
+                ui.add(egui::TextEdit::singleline(&mut state.term).desired_width(160.0).hint_text("auto"));
+            });
+            let term = state.term.trim();
+            if term.is_empty() {
+                let auto = if terminfo_installed(SPILTIXAL_TERM) { SPILTIXAL_TERM } else { FALLBACK_TERM };
+                ui.label(RichText::new(format!("auto: {auto}")).color(Color32::from_gray(150)).size(11.0));
+            } else if !terminfo_installed(term) {
+                ui.colored_label(Color32::from_rgb(240, 180, 90), format!("No terminfo entry found for '{term}'; programs may misbehave."));
+            }
+            if term == FALLBACK_TERM || (term.is_empty() && !terminfo_installed(SPILTIXAL_TERM)) {
+                ui.label(RichText::new("xterm-256color advertises features Spiltixal does not implement yet.").color(Color32::from_gray(150)).size(11.0));
+            }
+            if ui.button("Install spiltixal terminfo").clicked() {
+                state.terminfo_feedback = match install_terminfo() {
+                    Ok(dir) => format!("Installed into {}", dir.display()),
+                    Err(e) => e.to_string(),
+                };
+            }
+            if !state.terminfo_feedback.is_empty() {
+                ui.label(RichText::new(&state.terminfo_feedback).color(Color32::from_gray(170)).size(11.0));
+            }
+            ui.label(RichText::new("Applies to newly spawned shells.").color(Color32::from_gray(150)).size(11.0));
+        });
+
+        egui::CollapsingHeader::new("AI").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Backend:");
+                ui.selectable_value(&mut state.ai_backend, AiBackend::Ollama, "Ollama");
+                ui.selectable_value(&mut state.ai_backend, AiBackend::OllamaGenerate, "Ollama (legacy generate)");
+                ui.selectable_value(&mut state.ai_backend, AiBackend::OpenAiCompatible, "OpenAI-compatible");
+            });
+            let endpoint_hint = match state.ai_backend {
+                AiBackend::Ollama => "http://localhost:11434/api/chat",
+                AiBackend::OllamaGenerate => "http://localhost:11434/api/generate",
+                AiBackend::OpenAiCompatible => "http://localhost:8080/v1/chat/completions",
+            };
+            ui.horizontal(|ui| {
+                ui.label("Endpoint:");
+                ui.add(egui::TextEdit::singleline(&mut state.ai_endpoint).desired_width(f32::INFINITY).hint_text(endpoint_hint));
+            });
+            match parse_ai_endpoint(&state.ai_endpoint) {
+                Ok(url) => {
+                    if let Some(warning) = ai_endpoint_warning(&url) {
+                        ui.colored_label(Color32::from_rgb(255, 200, 0), warning);
+                    }
+                }
+                Err(e) => { ui.colored_label(Color32::from_rgb(240, 110, 110), format!("{e:#}")); }
+            }
+            ui.horizontal(|ui| {
+                ui.label("Model:");
+                ui.add(egui::TextEdit::singleline(&mut state.ai_model).desired_width(f32::INFINITY).hint_text("qwen2.5:0.5b"));
+            });
+            ui.horizontal(|ui| {
+                ui.label("API key:");
+                ui.add(egui::TextEdit::singleline(&mut state.ai_api_key).password(true).desired_width(f32::INFINITY).hint_text("optional bearer token"));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Context budget:");
+                ui.add(egui::DragValue::new(&mut state.ai_context_chars).clamp_range(1_000..=200_000).speed(500.0).suffix(" chars"))
+                    .on_hover_text("Older chat turns are left out once the conversation exceeds this size");
+            });
+            ui.checkbox(&mut state.ai_vision, "Model accepts images").on_hover_text("Send attached images to the model instead of just their file name");
         });
 
         egui::CollapsingHeader::new("Bob (Mate images)").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut state.typing_instant, "Instant replies");
+                ui.add_enabled(!state.typing_instant, egui::Slider::new(&mut state.typing_speed_ms, 1..=80).suffix("ms/char"));
+            });
+            ui.checkbox(&mut state.reduce_motion, "Reduce motion");
+            ui.horizontal(|ui| {
+                ui.label("Placement:");
+                ui.selectable_value(&mut state.bob_dock, BobDock::Floating, "Floating");
+                ui.selectable_value(&mut state.bob_dock, BobDock::Left, "Dock left");
+                ui.selectable_value(&mut state.bob_dock, BobDock::Right, "Dock right");
+            });
+            ui.add_space(4.0);
             ui.label("Set a file path for each emotion image:");
             for (label, input, path_opt) in [
                 ("1. Happy", &mut state.happy_input, &mut state.happy_path),
@@ -1281,64 +4084,412 @@ fn show_customize_window(ctx: &Context, state: &mut CustomizeState, config: &mut
         ui.add_space(8.0);
         ui.separator();
         ui.horizontal(|ui| {
-            if ui.button("Submit").clicked() { state.apply_to(config); config.save(); submitted = true; close = true; }
-            if ui.button("Cancel").clicked() { close = true; }
+            let endpoint_ok = parse_ai_endpoint(&state.ai_endpoint).is_ok();
+            if ui.add_enabled(endpoint_ok, egui::Button::new("Submit")).on_disabled_hover_text("Fix the invalid AI endpoint first").clicked() {
+                state.apply_to(config); config.save(); state.theme_backup = None; submitted = true; close = true;
+            }
+            if ui.button("Cancel").clicked() {
+                if let Some(theme) = state.theme_backup.take() { config.theme = theme; }
+                close = true;
+            }
         });
     });
-    if close { state.open = false; }
+    if close { state.settings_open = false; }
     submitted
 }
 
-struct DangerPrompt { command: String, reason: &'static str }
+struct DangerPrompt { command: String, reason: String, run: bool }
+impl DangerPrompt {
+    fn check(rules: &DangerRules, command: String) -> Result<String, Self> {
+        match rules.check(&command) {
+            Some(reason) => Err(Self { command, reason, run: true }),
+            None => Ok(command),
+        }
+    }
+}
 
-pub struct Spiltixal {
-    config:             Config,
-    term:               TerminalState,
-    pty:                Option<PtyHandle>,
-    input_buf:          String,
-    command_history:    Vec<String>,
-    history_idx:        Option<usize>,
-    danger_prompt:      Option<DangerPrompt>,
-    search:             SearchState,
-    search_open:        bool,
-    mate:               Mate,
-    mate_open_target:   bool,
-    mate_open_anim:     f32,
-    mate_input_focused: bool,
-    mate_textures:      HashMap<String, TextureHandle>,
-    bg_texture:         Option<TextureHandle>,
-    bg_texture_path:    Option<PathBuf>,
-    customize:          Option<CustomizeState>,
-    cursor_blink_timer: Instant,
-    cursor_visible:     bool,
-    cell_w:             f32,
-    cell_h:             f32,
-    nerd_font_loaded:   bool,
-    anim_t:             f32,
-    terminal_has_focus: bool,
-    terminal_rect:      Option<Rect>,
-    mate_rect:          Option<Rect>,
-    install_prompt_open: bool,
-    install_feedback:    String,
-    install_in_progress: bool,
-    install_rx:          Option<Receiver<String>>,
-    last_ram_check:      Instant,
-    ai_enable_prompt_open: bool,
-    ai_enable_feedback:    String,
-    ollama_child:         Option<OsChild>,
-    term_rows:            usize,
-    term_cols:            usize,
-    last_metrics_update:  Instant,
-    applied_layers:       Vec<OverlayLayer>,
-    applied_drawing:      Vec<DrawStroke>,
-    picker_in_progress:   bool,
-    picker_rx:            Option<Receiver<Result<String, String>>>,
+struct TemplateForm { id: u64, label: String, fields: Vec<(String, String)>, focus_first: bool }
+
+pub struct PausedView {
+    pub rows:   Vec<Vec<Cell>>,
+    pub stamps: Vec<Option<DateTime<Local>>>,
+    pub wrapped: Vec<bool>,
+    pub sizes:  Vec<LineSize>,
+    pub since:  Instant,
 }
 
-impl Drop for Spiltixal {
-    fn drop(&mut self) {
-        self.picker_rx = None;
-        if let Some(mut child) = self.ollama_child.take() {
+const DROPDOWN_HEIGHT_FRACTION: f32 = 0.45;
+const DROPDOWN_PEEK: f32 = 6.0;
+
+pub struct DropdownState {
+    pub shown:   bool,
+    pub anim:    f32,
+    pub applied: Option<Vec2>,
+}
+impl Default for DropdownState {
+    fn default() -> Self { Self { shown: true, anim: 0.0, applied: None } }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    CommandPalette,
+    ToggleSearch,
+    ToggleBob,
+    FocusBob,
+    ToggleBobDock,
+    ToggleAi,
+    OpenCustomize,
+    OpenSettings,
+    Profiles,
+    Environment,
+    ToggleBroadcast,
+    ToggleDropdown,
+    TogglePause,
+    CopySelection,
+    PreviousPrompt,
+    NextPrompt,
+    ExportScrollback,
+    ToggleRecording,
+    NewTab,
+    CloseTab,
+    NextTab,
+    SplitSideBySide,
+    SplitStacked,
+    FocusNextPane,
+    ClearScrollback,
+}
+impl Action {
+    pub const ALL: [Action; 25] = [
+        Action::CommandPalette,
+        Action::ToggleSearch,
+        Action::ToggleBob,
+        Action::FocusBob,
+        Action::ToggleBobDock,
+        Action::ToggleAi,
+        Action::OpenCustomize,
+        Action::OpenSettings,
+        Action::Profiles,
+        Action::Environment,
+        Action::ToggleBroadcast,
+        Action::ToggleDropdown,
+        Action::TogglePause,
+        Action::CopySelection,
+        Action::PreviousPrompt,
+        Action::NextPrompt,
+        Action::ExportScrollback,
+        Action::ToggleRecording,
+        Action::NewTab,
+        Action::CloseTab,
+        Action::NextTab,
+        Action::SplitSideBySide,
+        Action::SplitStacked,
+        Action::FocusNextPane,
+        Action::ClearScrollback,
+    ];
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::CommandPalette => "Command palette",
+            Action::ToggleSearch   => "Toggle search",
+            Action::ToggleBob      => "Toggle Bob",
+            Action::FocusBob       => "Focus Bob's input",
+            Action::ToggleBobDock  => "Dock / float Bob",
+            Action::ToggleAi       => "Toggle AI",
+            Action::OpenCustomize  => "Open customizer",
+            Action::OpenSettings   => "Open settings",
+            Action::Profiles       => "Switch profile",
+            Action::Environment    => "Inspect environment",
+            Action::ToggleBroadcast => "Broadcast input to all sessions",
+            Action::ToggleDropdown  => "Show / hide drop-down",
+            Action::TogglePause     => "Pause / resume output",
+            Action::CopySelection   => "Copy selection",
+            Action::PreviousPrompt  => "Jump to previous prompt",
+            Action::NextPrompt      => "Jump to next prompt",
+            Action::ExportScrollback => "Export scrollback",
+            Action::ToggleRecording  => "Start / stop recording",
+            Action::NewTab           => "New tab",
+            Action::CloseTab         => "Close tab / pane",
+            Action::NextTab          => "Next tab",
+            Action::SplitSideBySide  => "Split pane side by side",
+            Action::SplitStacked     => "Split pane top / bottom",
+            Action::FocusNextPane    => "Focus other pane",
+            Action::ClearScrollback  => "Clear screen and scrollback",
+        }
+    }
+    pub fn keybinding(self) -> Option<&'static str> {
+        match self {
+            Action::CommandPalette => Some("Ctrl+Shift+P"),
+            Action::ToggleSearch   => Some("Alt+F"),
+            Action::ToggleBob      => Some("Alt+M"),
+            Action::FocusBob       => Some("Alt+Enter"),
+            Action::ToggleDropdown => Some("F12"),
+            Action::TogglePause    => Some("Ctrl+Shift+S"),
+            Action::CopySelection  => Some("Ctrl+Shift+C"),
+            Action::PreviousPrompt => Some("Ctrl+Shift+Up"),
+            Action::NextPrompt     => Some("Ctrl+Shift+Down"),
+            Action::ExportScrollback => Some("Ctrl+Shift+E"),
+            Action::ToggleRecording  => Some("Ctrl+Shift+R"),
+            Action::NewTab           => Some("Ctrl+Shift+T"),
+            Action::CloseTab         => Some("Ctrl+Shift+W"),
+            Action::NextTab          => Some("Ctrl+Tab"),
+            Action::SplitSideBySide  => Some("Ctrl+Shift+D"),
+            Action::SplitStacked     => Some("Ctrl+Shift+O"),
+            Action::FocusNextPane    => Some("Ctrl+Shift+N"),
+            Action::ClearScrollback  => Some("Ctrl+Shift+K"),
+            _ => None,
+        }
+    }
+}
+
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = (pos..candidate.len()).find(|&i| candidate[i] == q)?;
+        score += 1;
+        if last_match.is_some_and(|l| l + 1 == found) { score += 3; }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() { score += 2; }
+        last_match = Some(found);
+        pos = found + 1;
+    }
+    Some(score - candidate.len() as i32 / 8)
+}
+
+pub struct TerminalTab {
+    term:          TerminalState,
+    pty:           Option<PtyHandle>,
+    launch:        LaunchOptions,
+    shell_exited:  Option<Option<u32>>,
+    shell_started: Instant,
+    input_buf:     String,
+    history_idx:   Option<usize>,
+    paused_view:   Option<PausedView>,
+    selection:     Option<Selection>,
+    recorder:      Option<CastRecorder>,
+    split:         Option<Box<PaneSplit>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CloseScope {
+    Window,
+    Tab,
+    Pane,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SplitAxis {
+    SideBySide,
+    Stacked,
+}
+
+pub struct PaneSplit {
+    axis:       SplitAxis,
+    pane:       TerminalTab,
+    pane_first: bool,
+}
+
+impl TerminalTab {
+    fn open(launch: LaunchOptions, config: &Config, rows: usize, cols: usize) -> (Self, Option<String>) {
+        let mut spec = launch.shell_spec(config);
+        let cwd_note = spec.check_cwd();
+        let pty = PtyHandle::spawn(&spec, &resolve_term(config), rows as u16, cols as u16)
+            .map_err(|e| log::warn!("{e:#}"))
+            .ok();
+        let tab = Self {
+            term: TerminalState::new(rows, cols, config.scrollback_lines),
+            pty,
+            launch,
+            shell_exited: None,
+            shell_started: Instant::now(),
+            input_buf: String::new(),
+            history_idx: None,
+            paused_view: None,
+            selection: None,
+            recorder: None,
+            split: None,
+        };
+        (tab, cwd_note)
+    }
+
+    fn label(&self) -> String {
+        let title = self.term.title.trim();
+        let label = if !title.is_empty() {
+            title.to_string()
+        } else if let Some(name) = self.term.cwd.as_ref().and_then(|d| d.file_name()) {
+            name.to_string_lossy().into_owned()
+        } else {
+            "shell".to_string()
+        };
+        match label.char_indices().nth(TAB_LABEL_MAX_CHARS) {
+            Some((cut, _)) => format!("{}…", &label[..cut]),
+            None => label,
+        }
+    }
+
+    fn for_each_pane(&mut self, mut f: impl FnMut(&mut TerminalTab)) {
+        f(self);
+        if let Some(split) = &mut self.split { f(&mut split.pane); }
+    }
+
+    fn drain_output(&mut self, output_log: &mut Option<OutputLog>) -> bool {
+        if let Some(split) = &mut self.split { split.pane.drain_output(output_log); }
+        let Some(pty) = &self.pty else { return false };
+        let mut received = false;
+        while let Ok(bytes) = pty.rx.try_recv() {
+            if let Some(log) = output_log { log.write(&bytes); }
+            if let Some(rec) = &mut self.recorder {
+                if let Err(e) = rec.output(&bytes) {
+                    log::warn!("{e:#}");
+                    self.recorder = None;
+                }
+            }
+            self.term.process_bytes(&bytes);
+            received = true;
+        }
+        received
+    }
+
+    fn resize(&mut self, rows: usize, cols: usize) {
+        self.term.resize(rows, cols);
+        if let Some(rec) = &mut self.recorder {
+            if let Err(e) = rec.resize(rows, cols) {
+                log::warn!("{e:#}");
+                self.recorder = None;
+            }
+        }
+        if let Some(pty) = &self.pty {
+            let _ = pty.resize(rows as u16, cols as u16);
+        }
+    }
+
+    fn hold(&mut self, code: Option<u32>) {
+        self.pty = None;
+        self.shell_exited = Some(code);
+        let status = code.map_or_else(|| "process exited".to_string(), |c| format!("process exited with code {c}"));
+        self.term.process_bytes(format!("\r\n\x1b[0;7m[{status}, press Enter to restart]\x1b[0m\r\n").as_bytes());
+        self.term.grid.scroll_offset = 0;
+    }
+
+    fn reap_split_pane(&mut self, config: &Config) {
+        let Some(split) = &mut self.split else { return };
+        let Some(pty) = &mut split.pane.pty else { return };
+        if pty.is_alive() { return; }
+        let code = pty.exit_code();
+        if let Some(rec) = split.pane.recorder.take() {
+            if let Err(e) = rec.finish() { log::warn!("{e:#}"); }
+        }
+        match config.on_shell_exit {
+            ShellExitAction::Close => self.split = None,
+            ShellExitAction::Restart if split.pane.shell_started.elapsed() >= Duration::from_secs(1) => {
+                let (rows, cols) = (split.pane.term.grid.rows, split.pane.term.grid.cols);
+                split.pane = TerminalTab::open(split.pane.launch.clone(), config, rows, cols).0;
+            }
+            ShellExitAction::Restart | ShellExitAction::Hold => split.pane.hold(code),
+        }
+    }
+}
+
+const TAB_LABEL_MAX_CHARS: usize = 24;
+const PANE_GAP: f32 = 4.0;
+
+pub struct Spiltixal {
+    config:             Config,
+    tab:                TerminalTab,
+    inactive_tabs:      Vec<TerminalTab>,
+    active_tab:         usize,
+    close_requested:    bool,
+    command_history:    CommandHistory,
+    danger_prompt:      Option<DangerPrompt>,
+    close_confirm:      Option<(CloseScope, Vec<String>)>,
+    close_confirmed:    bool,
+    danger_rules:       DangerRules,
+    search:             SearchState,
+    search_open:        bool,
+    mate:               Mate,
+    mate_open_target:   bool,
+    mate_open_anim:     f32,
+    mate_input_focused: bool,
+    term_events:        Vec<Event>,
+    term_modifiers:     Modifiers,
+    mate_textures:      HashMap<String, TextureHandle>,
+    bg_texture:         Option<TextureHandle>,
+    bg_texture_path:    Option<PathBuf>,
+    bg_frames:          Vec<(TextureHandle, Duration)>,
+    bg_frames_decode:   Option<GifDecode>,
+    bg_video:           Option<VideoPlayer>,
+    customize:          Option<CustomizeState>,
+    cursor_blink_timer: Instant,
+    cursor_visible:     bool,
+    cell_w:             f32,
+    cell_h:             f32,
+    nerd_font_loaded:   bool,
+    styled_fonts:       [bool; 3],
+    fonts_family:       String,
+    config_mtime:       Option<std::time::SystemTime>,
+    config_checked:     Instant,
+    blink_seen:         bool,
+    anim_t:             f32,
+    terminal_has_focus: bool,
+    terminal_rect:      Option<Rect>,
+    mate_rect:          Option<Rect>,
+    install_prompt_open: bool,
+    install_feedback:    String,
+    install_in_progress: bool,
+    install_rx:          Option<Receiver<String>>,
+    last_ram_check:      Instant,
+    ai_enable_prompt_open: bool,
+    ai_enable_feedback:    String,
+    ollama_child:         Option<OsChild>,
+    term_rows:            usize,
+    term_cols:            usize,
+    last_metrics_update:  Instant,
+    last_wheel_scroll:    Instant,
+    applied_layers:       Vec<OverlayLayer>,
+    applied_drawing:      Vec<DrawStroke>,
+    picker_in_progress:   bool,
+    picker_rx:            Option<Receiver<Result<String, String>>>,
+    picker_target:        PickerTarget,
+    profile_window_open:  bool,
+    profile_name_input:   String,
+    profile_feedback:     String,
+    hovered_link:         Option<FileLink>,
+    hovered_url:          Option<UrlLink>,
+    hovered_mark:         Option<(usize, Rect)>,
+    mouse_button_down:    Option<u8>,
+    mouse_last_cell:      Option<(usize, usize)>,
+    mouse_wheel_lines:    f32,
+    env_window_open:      bool,
+    template_form:        Option<TemplateForm>,
+    export_window_open:   bool,
+    export_format:        ExportFormat,
+    export_path:          String,
+    export_feedback:      String,
+    output_log:           Option<OutputLog>,
+    env_filter:           String,
+    env_snapshot:         Option<Result<Vec<(String, String)>, String>>,
+    palette_open:         bool,
+    palette_query:        String,
+    palette_selected:     usize,
+    broadcast_input:      bool,
+    broadcast_confirm:    bool,
+    pub dropdown:         Option<DropdownState>,
+    unfocus_dim:          f32,
+    copy_request:         Option<String>,
+    window_title:         String,
+    window_title_since:   Option<Instant>,
+}
+
+const WINDOW_TITLE_DEBOUNCE: Duration = Duration::from_millis(300);
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const SCROLLBACK_RANGE: std::ops::RangeInclusive<usize> = 100..=200_000;
+const SCROLLBACK_WARN_LINES: usize = 50_000;
+const SCROLL_SNAP_GRACE: Duration = Duration::from_millis(400);
+
+impl Drop for Spiltixal {
+    fn drop(&mut self) {
+        self.picker_rx = None;
+        if let Some(mut child) = self.ollama_child.take() {
             let _ = child.kill();
             let _ = child.wait();
         }
@@ -1372,6 +4523,21 @@ impl Spiltixal {
         }
     }
 
+    fn saved_to_layer(saved: SavedOverlayLayer) -> OverlayLayer {
+        OverlayLayer {
+            path: PathBuf::from(saved.path),
+            is_video: saved.is_video,
+            pos: vec2(saved.pos[0], saved.pos[1]),
+            size: vec2(saved.size[0], saved.size[1]),
+            rotation_deg: saved.rotation_deg,
+            tint: saved.tint,
+            animation: saved.animation,
+            texture: None,
+            frames: Vec::new(),
+            frames_decode: None,
+        }
+    }
+
     fn draw_rotated_texture(
         painter: &Painter,
         tex: TextureId,
@@ -1387,7 +4553,7 @@ impl Spiltixal {
         let corners = [vec2(-hw, -hh), vec2(hw, -hh), vec2(hw, hh), vec2(-hw, hh)];
         let mut mesh = egui::epaint::Mesh::with_texture(tex);
         let uvs = [pos2(0.0, 0.0), pos2(1.0, 0.0), pos2(1.0, 1.0), pos2(0.0, 1.0)];
-        for (local, uv) in corners.into_iter().zip(uvs.into_iter()) {
+        for (local, uv) in corners.into_iter().zip(uvs) {
             let rot = vec2(local.x * c - local.y * s, local.x * s + local.y * c);
             mesh.vertices.push(egui::epaint::Vertex { pos: center + rot, uv, color: tint });
         }
@@ -1407,6 +4573,11 @@ impl Spiltixal {
         c
     }
 
+    fn layer_corners(rect: Rect, layer: &OverlayLayer, t: f32, phase: f32) -> [Pos2; 4] {
+        let r = Rect::from_center_size(Self::layer_center(rect, layer, t, phase), Self::layer_size_px(rect, layer));
+        [r.left_top(), r.right_top(), r.right_bottom(), r.left_bottom()]
+    }
+
     fn layer_size_px(rect: Rect, layer: &OverlayLayer) -> Vec2 {
         let base = rect.width().min(rect.height());
         vec2(
@@ -1416,6 +4587,10 @@ impl Spiltixal {
     }
 
     fn ensure_layer_texture(layer: &mut OverlayLayer, ctx: &Context) {
+        let name = format!("overlay-{}", layer.path.display());
+        if let Some(frames) = poll_gif_decode(ctx, &name, &mut layer.frames_decode) {
+            layer.frames = frames;
+        }
         if layer.texture.is_some() {
             return;
         }
@@ -1425,7 +4600,9 @@ impl Spiltixal {
             image_from_path(&layer.path)
         };
         if let Some(ci) = img {
-            let name = format!("overlay-{}", layer.path.display());
+            if !layer.is_video {
+                layer.frames_decode = spawn_gif_decode(&layer.path);
+            }
             layer.texture = Some(ctx.load_texture(name, ci, TextureOptions::LINEAR));
         }
     }
@@ -1440,7 +4617,11 @@ impl Spiltixal {
                 layer.rotation_deg
             };
             let tint = Color32::from_rgba_unmultiplied(layer.tint[0], layer.tint[1], layer.tint[2], layer.tint[3]);
-            if let Some(tex) = &layer.texture {
+            let frame = animation_frame(&layer.frames, self.anim_t).map(|(tex, next)| {
+                painter.ctx().request_repaint_after(next);
+                tex
+            });
+            if let Some(tex) = frame.or(layer.texture.as_ref()) {
                 Self::draw_rotated_texture(painter, tex.id(), center, size, rot, tint);
             }
             if selected == Some(i) {
@@ -1449,6 +4630,11 @@ impl Spiltixal {
                     2.0,
                     Stroke::new(1.3, Color32::from_rgb(245, 190, 90)),
                 );
+                for corner in Self::layer_corners(rect, layer, self.anim_t, i as f32 * 0.73) {
+                    let handle = Rect::from_center_size(corner, vec2(LAYER_HANDLE_SIZE, LAYER_HANDLE_SIZE));
+                    painter.rect_filled(handle, 1.5, Color32::from_rgb(245, 190, 90));
+                    painter.rect_stroke(handle, 1.5, Stroke::new(1.0, Color32::from_rgb(40, 30, 10)));
+                }
             }
         }
     }
@@ -1467,20 +4653,22 @@ impl Spiltixal {
         }
     }
 
-    fn save_customize_layout(&mut self, state: &mut CustomizeState) {
-        let Some(home) = dirs::home_dir() else { return; };
-        let dir = home.join(".config").join("spiltixal");
-        let path = dir.join("layout.json");
-        let _ = std::fs::create_dir_all(&dir);
-        let layout = SavedCustomizeLayout {
+    fn layout_from_state(state: &CustomizeState) -> SavedCustomizeLayout {
+        SavedCustomizeLayout {
             saved_at: Local::now().to_rfc3339(),
             text_color: state.fg_color,
             background_color: state.bg_solid,
             theme_preset: state.theme_preset.clone(),
             layers: state.layers.iter().map(Self::layer_to_saved).collect(),
             drawing: state.drawing.clone(),
-        };
-        if let Ok(json) = serde_json::to_string_pretty(&layout) {
+        }
+    }
+
+    fn save_customize_layout(&mut self, state: &mut CustomizeState) {
+        let dir = Config::dir();
+        let path = dir.join("layout.json");
+        let _ = std::fs::create_dir_all(&dir);
+        if let Ok(json) = serde_json::to_string_pretty(&Self::layout_from_state(state)) {
             if std::fs::write(&path, json).is_ok() {
                 state.save_message = format!("Saved at {}", path.display());
                 self.mate.last_message = state.save_message.clone();
@@ -1499,12 +4687,46 @@ impl Spiltixal {
     }
 
     fn load_customize_layout() -> Option<SavedCustomizeLayout> {
-        let home = dirs::home_dir()?;
-        let path = home.join(".config").join("spiltixal").join("layout.json");
+        let path = Config::dir().join("layout.json");
         let data = std::fs::read_to_string(path).ok()?;
         serde_json::from_str::<SavedCustomizeLayout>(&data).ok()
     }
 
+    fn load_applied_layout() -> (Vec<OverlayLayer>, Vec<DrawStroke>) {
+        let Some(layout) = Self::load_customize_layout() else { return (Vec::new(), Vec::new()) };
+        let layers = layout.layers.into_iter().map(Self::saved_to_layer).collect::<Vec<_>>();
+        (layers, layout.drawing)
+    }
+
+    fn export_customize_layout(state: &CustomizeState, path: &str) -> Result<PathBuf> {
+        let path = path.trim();
+        if path.is_empty() { anyhow::bail!("Choose where to save the layout first."); }
+        let path = expand_config_path(path);
+        let path = if path.extension().is_none() { path.with_extension("json") } else { path };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let json = serde_json::to_string_pretty(&Self::layout_from_state(state))?;
+        std::fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn import_customize_layout(state: &mut CustomizeState, path: &Path) -> Result<Vec<String>> {
+        let data = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let layout: SavedCustomizeLayout = serde_json::from_str(&data)
+            .with_context(|| format!("{} is not a Spiltixal layout", path.display()))?;
+        let (layers, missing): (Vec<_>, Vec<_>) = layout.layers.into_iter().partition(|l| Path::new(&l.path).exists());
+        state.fg_color = layout.text_color;
+        state.bg_solid = layout.background_color;
+        state.theme_preset = layout.theme_preset;
+        state.layers = layers.into_iter().map(Self::saved_to_layer).collect();
+        state.drawing = layout.drawing;
+        state.selected_layer = None;
+        state.drag_layer = None;
+        state.active_stroke.clear();
+        Ok(missing.into_iter().map(|l| l.path).collect())
+    }
+
     fn point_to_norm(rect: Rect, p: Pos2) -> Vec2 {
         vec2(
             ((p.x - rect.left()) / rect.width()).clamp(0.0, 1.0),
@@ -1512,6 +4734,15 @@ impl Spiltixal {
         )
     }
 
+    fn hit_layer_handle(rect: Rect, layers: &[OverlayLayer], selected: Option<usize>, p: Pos2, t: f32) -> Option<(usize, usize)> {
+        let idx = selected?;
+        let corners = Self::layer_corners(rect, layers.get(idx)?, t, idx as f32 * 0.73);
+        corners
+            .iter()
+            .position(|c| Rect::from_center_size(*c, vec2(LAYER_HANDLE_SIZE, LAYER_HANDLE_SIZE)).expand(3.0).contains(p))
+            .map(|corner| (idx, corner))
+    }
+
     fn hit_layer_index(rect: Rect, layers: &[OverlayLayer], p: Pos2, t: f32) -> Option<usize> {
         for i in (0..layers.len()).rev() {
             let layer = &layers[i];
@@ -1537,8 +4768,35 @@ impl Spiltixal {
                 if let Ok(result) = rx.try_recv() {
                     match result {
                         Ok(path) => {
-                            state.layer_path_input = path;
                             state.path_error.clear();
+                            match self.picker_target {
+                                PickerTarget::LayerPath => state.layer_path_input = path,
+                                PickerTarget::ThemeImport => state.theme_path_input = path,
+                                PickerTarget::ThemeExport => {
+                                    state.path_error = match state.export_theme(&self.config, &path) {
+                                        Ok(saved) => format!("Saved theme to {}", saved.display()),
+                                        Err(e) => format!("{e:#}"),
+                                    };
+                                    state.theme_export_input = path;
+                                }
+                                PickerTarget::LayoutExport => {
+                                    state.path_error = match Self::export_customize_layout(&state, &path) {
+                                        Ok(saved) => format!("Saved layout to {}", saved.display()),
+                                        Err(e) => format!("{e:#}"),
+                                    };
+                                }
+                                PickerTarget::LayoutImport => {
+                                    state.path_error = match Self::import_customize_layout(&mut state, Path::new(&path)) {
+                                        Ok(missing) if missing.is_empty() => String::new(),
+                                        Ok(missing) => format!(
+                                            "Imported layout, but skipped {} layer(s) with missing files:\n{}",
+                                            missing.len(),
+                                            missing.join("\n"),
+                                        ),
+                                        Err(e) => format!("{e:#}"),
+                                    };
+                                }
+                            }
                         }
                         Err(err) => {
                             state.path_error = err;
@@ -1557,11 +4815,14 @@ impl Spiltixal {
         let term_painter = ctx.layer_painter(LayerId::new(egui::Order::Foreground, Id::new("customize_overlay")));
         self.render_overlay_layers(&term_painter, term_rect, &state.layers, state.selected_layer);
         self.render_drawing(&term_painter, term_rect, &state.drawing);
-        if state.active_stroke.len() > 1 {
-            let stroke_color = Color32::from_rgba_unmultiplied(state.fg_color[0], state.fg_color[1], state.fg_color[2], state.fg_color[3]);
-            for pts in state.active_stroke.windows(2) {
-                term_painter.line_segment([pts[0], pts[1]], Stroke::new(state.stroke_width, stroke_color));
-            }
+        let stroke_color = Color32::from_rgba_unmultiplied(state.stroke_color[0], state.stroke_color[1], state.stroke_color[2], state.draw_opacity);
+        let preview = match (state.draw_tool, state.active_stroke.first(), state.active_stroke.last()) {
+            (tool, Some(a), Some(b)) if tool.is_shape() => tool.shape_points(*a, *b),
+            (DrawTool::Pen, _, _) => state.active_stroke.clone(),
+            _ => Vec::new(),
+        };
+        for pts in preview.windows(2) {
+            term_painter.line_segment([pts[0], pts[1]], Stroke::new(state.stroke_width, stroke_color));
         }
 
         let pointer = ctx.input(|i| {
@@ -1573,7 +4834,60 @@ impl Spiltixal {
             )
         });
 
-        if state.tool == CustomizeTool::Draw {
+        if state.tool == CustomizeTool::Draw && state.draw_tool == DrawTool::Eraser {
+            let radius = (state.stroke_width * 3.0).max(8.0);
+            if let Some(p) = pointer.0.filter(|p| term_rect.contains(*p)) {
+                term_painter.circle_stroke(p, radius, Stroke::new(1.0, Color32::from_gray(220)));
+                if pointer.1 {
+                    let from = state.active_stroke.last().copied().unwrap_or(p);
+                    let steps = (from.distance(p) / (radius * 0.5)).ceil().max(1.0) as usize;
+                    let samples = (0..=steps).map(|i| from.lerp(p, i as f32 / steps as f32)).collect::<Vec<_>>();
+                    state.drawing.retain(|stroke| {
+                        let pts = stroke.points
+                            .iter()
+                            .map(|n| pos2(term_rect.left() + n[0] * term_rect.width(), term_rect.top() + n[1] * term_rect.height()))
+                            .collect::<Vec<_>>();
+                        let reach = radius + stroke.width * 0.5;
+                        !samples.iter().any(|s| match pts.as_slice() {
+                            [only] => only.distance(*s) <= reach,
+                            _ => pts.windows(2).any(|w| distance_to_segment(*s, w[0], w[1]) <= reach),
+                        })
+                    });
+                    state.active_stroke = vec![p];
+                }
+            }
+            if pointer.3 {
+                state.active_stroke.clear();
+            }
+        } else if state.tool == CustomizeTool::Draw && state.draw_tool.is_shape() {
+            if let Some(p) = pointer.0 {
+                if pointer.2 && term_rect.contains(p) {
+                    state.active_stroke = vec![p, p];
+                } else if pointer.1 && !state.active_stroke.is_empty() {
+                    let clamped = term_rect.clamp(p);
+                    state.active_stroke.truncate(1);
+                    state.active_stroke.push(clamped);
+                }
+            }
+            if pointer.3 && state.active_stroke.len() > 1 {
+                let (a, b) = (state.active_stroke[0], state.active_stroke[1]);
+                if a.distance(b) > 2.0 {
+                    let points = state.draw_tool
+                        .shape_points(Self::point_to_norm(term_rect, a).to_pos2(), Self::point_to_norm(term_rect, b).to_pos2())
+                        .into_iter()
+                        .map(|n| [n.x, n.y])
+                        .collect();
+                    let mut color = state.stroke_color;
+                    color[3] = state.draw_opacity;
+                    state.drawing.push(DrawStroke {
+                        points,
+                        color,
+                        width: state.stroke_width,
+                    });
+                }
+                state.active_stroke.clear();
+            }
+        } else if state.tool == CustomizeTool::Draw {
             if let Some(p) = pointer.0 {
                 if term_rect.contains(p) && pointer.1 {
                     state.active_stroke.push(p);
@@ -1597,7 +4911,7 @@ impl Spiltixal {
                     })
                     .collect::<Vec<_>>();
                 if points.len() > 1 {
-                    let mut color = state.fg_color;
+                    let mut color = state.stroke_color;
                     color[3] = state.draw_opacity;
                     state.drawing.push(DrawStroke {
                         points,
@@ -1608,7 +4922,15 @@ impl Spiltixal {
                 state.active_stroke.clear();
             }
         } else if let Some(p) = pointer.0 {
-            if pointer.2 && term_rect.contains(p) {
+            let handle = Self::hit_layer_handle(term_rect, &state.layers, state.selected_layer, p, self.anim_t);
+            if let (true, Some((idx, corner))) = (pointer.2, handle) {
+                let corners = Self::layer_corners(term_rect, &state.layers[idx], self.anim_t, idx as f32 * 0.73);
+                let layer = &state.layers[idx];
+                state.drag_layer = Some(idx);
+                state.resize_anchor = Some(corners[(corner + 2) % 4]);
+                state.resize_aspect = layer.size.x / layer.size.y.max(0.001);
+            } else if pointer.2 && term_rect.contains(p) {
+                state.resize_anchor = None;
                 if let Some(idx) = Self::hit_layer_index(term_rect, &state.layers, p, self.anim_t) {
                     state.selected_layer = Some(idx);
                     let center = Self::layer_center(term_rect, &state.layers[idx], self.anim_t, idx as f32 * 0.73);
@@ -1616,7 +4938,25 @@ impl Spiltixal {
                     state.drag_offset = p - center;
                 }
             } else if pointer.1 {
-                if let Some(idx) = state.drag_layer {
+                if let (Some(idx), Some(anchor)) = (state.drag_layer, state.resize_anchor) {
+                    let keep_aspect = ctx.input(|i| i.modifiers.shift);
+                    let base = term_rect.width().min(term_rect.height());
+                    let d = p - anchor;
+                    let mut size = d.abs() / base;
+                    if keep_aspect {
+                        if size.x / size.y.max(0.001) > state.resize_aspect {
+                            size.y = size.x / state.resize_aspect;
+                        } else {
+                            size.x = size.y * state.resize_aspect;
+                        }
+                    }
+                    let size = size.clamp(Vec2::splat(*LAYER_SIZE_RANGE.start()), Vec2::splat(*LAYER_SIZE_RANGE.end()));
+                    let signed = vec2(size.x.copysign(d.x), size.y.copysign(d.y)) * base;
+                    if let Some(layer) = state.layers.get_mut(idx) {
+                        layer.size = size;
+                        layer.pos = Self::point_to_norm(term_rect, anchor + signed * 0.5);
+                    }
+                } else if let Some(idx) = state.drag_layer {
                     let target = p - state.drag_offset;
                     let n = Self::point_to_norm(term_rect, target);
                     if let Some(layer) = state.layers.get_mut(idx) {
@@ -1625,6 +4965,31 @@ impl Spiltixal {
                 }
             } else if pointer.3 {
                 state.drag_layer = None;
+                state.resize_anchor = None;
+            }
+        }
+
+        if state.tool != CustomizeTool::Draw {
+            if let Some(layer) = state.selected_layer.and_then(|idx| state.layers.get_mut(idx)) {
+                self.term_events.retain(|event| {
+                    let Event::Key { key, pressed, modifiers, .. } = event else { return true };
+                    let (dx, dy) = match key {
+                        Key::ArrowLeft => (-1.0, 0.0),
+                        Key::ArrowRight => (1.0, 0.0),
+                        Key::ArrowUp => (0.0, -1.0),
+                        Key::ArrowDown => (0.0, 1.0),
+                        _ => return true,
+                    };
+                    if !pressed { return false; }
+                    if modifiers.ctrl || modifiers.command {
+                        let step = if modifiers.shift { 15.0 } else { 1.0 };
+                        layer.rotation_deg = (layer.rotation_deg + (dx - dy) * step + 180.0).rem_euclid(360.0) - 180.0;
+                    } else {
+                        let step = if modifiers.shift { 0.02 } else { 0.002 };
+                        layer.pos = (layer.pos + vec2(dx, dy) * step).clamp(Vec2::ZERO, Vec2::splat(1.0));
+                    }
+                    false
+                });
             }
         }
 
@@ -1655,6 +5020,19 @@ impl Spiltixal {
                                 state.tool = tool;
                             }
                         }
+                        ui.separator();
+                        if ui.selectable_label(state.settings_open, "7. Settings").clicked() {
+                            state.settings_open = !state.settings_open;
+                        }
+                        ui.separator();
+                        if ui.button("Export layout...").clicked() {
+                            self.start_picker(false, PickerTarget::LayoutExport);
+                            state.path_error = "Opening file picker...".into();
+                        }
+                        if ui.button("Import layout...").clicked() {
+                            self.start_picker(false, PickerTarget::LayoutImport);
+                            state.path_error = "Opening file picker...".into();
+                        }
                     });
             });
 
@@ -1679,7 +5057,7 @@ impl Spiltixal {
                                 ui.add(egui::TextEdit::singleline(&mut state.layer_path_input).hint_text("/path/to/file"));
                                 let pick_label = if !is_video_tool { "Select Image" } else { "Select Video" };
                                 if ui.button(pick_label).clicked() {
-                                    self.start_picker(is_video_tool);
+                                    self.start_picker(is_video_tool, PickerTarget::LayerPath);
                                     state.path_error = "Opening file picker...".into();
                                 }
                                 if ui.button("Add Layer").clicked() {
@@ -1694,6 +5072,8 @@ impl Spiltixal {
                                             tint: [255, 255, 255, 230],
                                             animation: OverlayAnimation::None,
                                             texture: None,
+                                            frames: Vec::new(),
+                                            frames_decode: None,
                                         };
                                         Self::ensure_layer_texture(&mut layer, ctx);
                                         if layer.texture.is_none() {
@@ -1715,6 +5095,20 @@ impl Spiltixal {
                             }
                             CustomizeTool::Draw => {
                                 ui.label("Draw over terminal");
+                                ui.horizontal_wrapped(|ui| {
+                                    for tool in DrawTool::ALL {
+                                        if ui.selectable_label(state.draw_tool == tool, tool.label()).clicked() {
+                                            state.draw_tool = tool;
+                                            state.active_stroke.clear();
+                                        }
+                                    }
+                                });
+                                if state.draw_tool != DrawTool::Eraser {
+                                    ui.horizontal(|ui| {
+                                        ui.label("Color");
+                                        show_color_picker(ui, &mut state.stroke_color);
+                                    });
+                                }
                                 ui.horizontal(|ui| {
                                     ui.label("Width");
                                     ui.add(egui::Slider::new(&mut state.stroke_width, 1.0..=10.0));
@@ -1754,8 +5148,8 @@ impl Spiltixal {
                                 ui.label("Selected Layer");
                                 ui.horizontal(|ui| {
                                     ui.label("Size");
-                                    ui.add(egui::Slider::new(&mut layer.size.x, 0.05..=0.9).show_value(false));
-                                    ui.add(egui::Slider::new(&mut layer.size.y, 0.05..=0.9).show_value(false));
+                                    ui.add(egui::Slider::new(&mut layer.size.x, LAYER_SIZE_RANGE).show_value(false));
+                                    ui.add(egui::Slider::new(&mut layer.size.y, LAYER_SIZE_RANGE).show_value(false));
                                 });
                                 ui.horizontal(|ui| {
                                     ui.label("Rotation");
@@ -1775,6 +5169,22 @@ impl Spiltixal {
                                     ui.selectable_value(&mut layer.animation, OverlayAnimation::Spin, "Spin");
                                     ui.selectable_value(&mut layer.animation, OverlayAnimation::Floating, "Floating");
                                 });
+                                ui.horizontal(|ui| {
+                                    if ui.add_enabled(idx + 1 < state.layers.len(), egui::Button::new("Bring forward")).clicked() {
+                                        state.layers.swap(idx, idx + 1);
+                                        state.selected_layer = Some(idx + 1);
+                                    }
+                                    if ui.add_enabled(idx > 0, egui::Button::new("Send backward")).clicked() {
+                                        state.layers.swap(idx, idx - 1);
+                                        state.selected_layer = Some(idx - 1);
+                                    }
+                                    if ui.button("Duplicate").clicked() {
+                                        let mut copy = state.layers[idx].clone();
+                                        copy.pos = (copy.pos + vec2(0.03, 0.03)).min(vec2(1.0, 1.0));
+                                        state.layers.insert(idx + 1, copy);
+                                        state.selected_layer = Some(idx + 1);
+                                    }
+                                });
                                 if ui.button("Remove Layer").clicked() {
                                     state.layers.remove(idx);
                                     state.selected_layer = None;
@@ -1823,6 +5233,7 @@ impl Spiltixal {
                                         state.active_stroke.clear();
                                         state.drawing.clear();
                                         state.draw_opacity = 220;
+                                        state.stroke_color = defaults.foreground;
                                         state.path_error.clear();
                                         state.reset_confirm_step = 0;
                                     }
@@ -1840,27 +5251,40 @@ impl Spiltixal {
             .order(egui::Order::Foreground)
             .show(ctx, |ui| {
                 if ui.add(egui::Button::new(RichText::new("Apply").strong()).fill(Color32::from_rgb(55, 125, 220))).clicked() {
+                    let log_path = self.config.log_output_path.clone();
+                    let animate_video = self.config.animate_video_background;
                     state.apply_to(&mut self.config);
+                    if animate_video != self.config.animate_video_background { self.bg_texture_path = None; }
                     self.config.save();
-                    self.applied_layers = state.layers
-                        .iter()
-                        .map(|l| OverlayLayer {
-                            path: l.path.clone(),
-                            is_video: l.is_video,
-                            pos: l.pos,
-                            size: l.size,
-                            rotation_deg: l.rotation_deg,
-                            tint: l.tint,
-                            animation: l.animation,
-                            texture: l.texture.clone(),
-                        })
-                        .collect();
+                    let max_scrollback = self.config.scrollback_lines;
+                    for tab in self.all_tabs_mut() { tab.for_each_pane(|pane| pane.term.grid.set_max_scrollback(max_scrollback)); }
+                    if self.search_open { self.search.refresh(&self.tab.term.grid); }
+                    if log_path != self.config.log_output_path { self.reopen_output_log(); }
+                    self.applied_layers = state.layers.clone();
                     self.applied_drawing = state.drawing.clone();
                     self.save_customize_layout(&mut state);
                     state.open = false;
                 }
             });
 
+        let settings_submitted = state.settings_open && show_customize_window(ctx, &mut state, &mut self.config);
+        if std::mem::take(&mut state.theme_browse) {
+            self.start_picker(false, PickerTarget::ThemeImport);
+            state.path_error = "Opening file picker...".into();
+        }
+        if std::mem::take(&mut state.theme_export_browse) {
+            self.start_picker(false, PickerTarget::ThemeExport);
+            state.path_error = "Opening file picker...".into();
+        }
+        if settings_submitted {
+            self.mate_textures.clear();
+            if self.config.ai_enabled {
+                if let Err(e) = self.rebuild_ai_client() {
+                    self.mate.last_message = format!("AI settings saved, but couldn't start: {:#}", e);
+                }
+            }
+        }
+
         if !state.save_message.is_empty() {
             egui::Area::new("customize_saved_msg".into())
                 .anchor(Align2::CENTER_BOTTOM, vec2(0.0, -12.0))
@@ -1896,21 +5320,12 @@ impl Spiltixal {
         }
     }
 
-    pub fn new(cc: &eframe::CreationContext) -> Self {
+    pub fn new(cc: &eframe::CreationContext, launch: LaunchOptions) -> Self {
         egui_extras::install_image_loaders(&cc.egui_ctx);
+        cc.egui_ctx.options_mut(|o| o.zoom_with_keyboard = false);
 
-        let mut fonts = egui::FontDefinitions::default();
-        let mut nerd_loaded = false;
-        for (idx, font_path) in find_icon_fonts().into_iter().enumerate() {
-            if let Ok(bytes) = std::fs::read(&font_path) {
-                let key = format!("IconFont{idx}");
-                if idx == 0 { nerd_loaded = true; }
-                fonts.font_data.insert(key.clone(), egui::FontData::from_owned(bytes));
-                fonts.families.entry(FontFamily::Monospace).or_default().insert(0, key.clone());
-                fonts.families.entry(FontFamily::Proportional).or_default().insert(0, key);
-            }
-        }
-        cc.egui_ctx.set_fonts(fonts);
+        let mut config = Config::load();
+        let font_setup = install_fonts(&cc.egui_ctx, &config.theme.font_family);
 
         let mut style = (*cc.egui_ctx.style()).clone();
         style.visuals.window_rounding            = Rounding::same(10.0);
@@ -1922,40 +5337,52 @@ impl Spiltixal {
         style.visuals.window_fill                = Color32::from_rgba_unmultiplied(18, 18, 28, 240);
         cc.egui_ctx.set_style(style);
 
-        let mut config = Config::load();
         if !config.ai_system_prompt.contains("attached to the live PTY terminal") {
             config.ai_system_prompt.push_str(" You are attached to the live PTY terminal and allowed to run commands through user-approved actions. Supported direct actions are /run <command>, /ctrl c, /ctrl z, /ctrl \\\\, and /signal <INT|TSTP|QUIT>.");
             config.save();
         }
+        if !config.ai_system_prompt.contains("/cd <path>") {
+            config.ai_system_prompt.push_str(" Use /cd <path> to change the shell's working directory.");
+            config.save();
+        }
         let ai_client = if config.ai_enabled {
-            Some(AiClient::new(&config.ai_endpoint, &config.ai_model, &config.ai_system_prompt))
+            AiClient::from_config(&config)
+                .map_err(|e| log::warn!("{e:#}"))
+                .ok()
         } else { None };
-        let mate = Mate::new(config.mate_name.clone(), ai_client);
-        let pty  = PtyHandle::spawn(&config.shell, 24, 80).ok();
-        let (applied_layers, applied_drawing) = if let Some(layout) = Self::load_customize_layout() {
-            let layers = layout.layers.into_iter().map(|l| OverlayLayer {
-                path: PathBuf::from(l.path),
-                is_video: l.is_video,
-                pos: vec2(l.pos[0], l.pos[1]),
-                size: vec2(l.size[0], l.size[1]),
-                rotation_deg: l.rotation_deg,
-                tint: l.tint,
-                animation: l.animation,
-                texture: None,
-            }).collect::<Vec<_>>();
-            (layers, layout.drawing)
-        } else {
-            (Vec::new(), Vec::new())
-        };
+        let mut mate = Mate::new(config.mate_name.clone(), ai_client);
+        let (danger_rules, rule_errors) = DangerRules::compile(&config);
+        if !rule_errors.is_empty() {
+            mate.last_message = danger_rule_errors_message(&rule_errors);
+            mate.typing_target = mate.last_message.clone();
+        }
+        if !font_setup.family_found && wants_custom_font(&config.theme.font_family) {
+            mate.last_message = format!("couldn't find the font {}, using the built-in monospace font.", config.theme.font_family);
+            mate.typing_target = mate.last_message.clone();
+        }
+        let term_name = resolve_term(&config);
+        if !terminfo_installed(&term_name) {
+            log::warn!("no terminfo entry found for TERM={term_name}");
+        }
+        let (tab, cwd_note) = TerminalTab::open(launch, &config, 24, 80);
+        if let Some(note) = cwd_note {
+            mate.last_message = note;
+            mate.typing_target = mate.last_message.clone();
+        }
+        let output_log = Some(config.log_output_path.trim())
+            .filter(|p| !p.is_empty())
+            .and_then(|p| OutputLog::open(expand_config_path(p)).map_err(|e| log::warn!("{e:#}")).ok());
+        let (applied_layers, applied_drawing) = Self::load_applied_layout();
 
         Self {
-            term: TerminalState::new(24, 80, config.scrollback_lines),
-            pty, input_buf: String::new(), command_history: Vec::new(), history_idx: None,
-            danger_prompt: None, search: SearchState::default(), search_open: false,
+            tab, inactive_tabs: Vec::new(), active_tab: 0, close_requested: false, command_history: CommandHistory::load(),
+            danger_prompt: None, close_confirm: None, close_confirmed: false, danger_rules, search: SearchState { history: SearchHistory::load(), ..Default::default() }, search_open: false,
             mate, mate_open_target: true, mate_open_anim: 1.0, mate_input_focused: false,
-            mate_textures: HashMap::new(), bg_texture: None, bg_texture_path: None, customize: None,
+            term_events: Vec::new(), term_modifiers: Modifiers::NONE,
+            mate_textures: HashMap::new(), bg_texture: None, bg_texture_path: None, bg_frames: Vec::new(), bg_frames_decode: None, bg_video: None, customize: None,
             cursor_blink_timer: Instant::now(), cursor_visible: true,
-            cell_w: 8.5, cell_h: 17.0, nerd_font_loaded: nerd_loaded, anim_t: 0.0,
+            cell_w: 8.5, cell_h: 17.0, nerd_font_loaded: font_setup.nerd_loaded, styled_fonts: font_setup.styled, fonts_family: config.theme.font_family.clone(),
+            config_mtime: Config::modified(), config_checked: Instant::now(), blink_seen: false, anim_t: 0.0,
             terminal_has_focus: true, terminal_rect: None, mate_rect: None,
             install_prompt_open: !Self::launched_from_usr_bin(), install_feedback: String::new(),
             install_in_progress: false,
@@ -1967,37 +5394,422 @@ impl Spiltixal {
             term_rows: 24,
             term_cols: 80,
             last_metrics_update: Instant::now(),
+            last_wheel_scroll: Instant::now(),
             applied_layers,
             applied_drawing,
             picker_in_progress: false,
             picker_rx: None,
+            picker_target: PickerTarget::LayerPath,
+            profile_window_open: false,
+            profile_name_input: String::new(),
+            profile_feedback: String::new(),
+            hovered_link: None,
+            hovered_url: None,
+            hovered_mark: None,
+            mouse_button_down: None,
+            mouse_last_cell: None,
+            mouse_wheel_lines: 0.0,
+            env_window_open: false,
+            template_form: None,
+            export_window_open: false,
+            export_format: ExportFormat::Text,
+            export_path: String::new(),
+            export_feedback: String::new(),
+            output_log,
+            env_filter: String::new(),
+            env_snapshot: None,
+            palette_open: false,
+            palette_query: String::new(),
+            palette_selected: 0,
+            broadcast_input: false,
+            broadcast_confirm: false,
+            dropdown: None,
+            unfocus_dim: 0.0,
+            copy_request: None,
+            window_title: "Spiltixal".into(),
+            window_title_since: None,
             config,
         }
     }
 
     fn poll_pty(&mut self) {
-        if let Some(pty) = &self.pty {
-            while let Ok(bytes) = pty.rx.try_recv() { self.term.process_bytes(&bytes); }
+        let cursor_was_hidden = !self.tab.term.grid.cursor_visible_app;
+        let received = self.tab.drain_output(&mut self.output_log);
+        for tab in &mut self.inactive_tabs {
+            tab.drain_output(&mut self.output_log);
         }
+        if cursor_was_hidden && self.tab.term.grid.cursor_visible_app {
+            self.cursor_visible = true;
+            self.cursor_blink_timer = Instant::now();
+        }
+        if received && self.search_open { self.search.refresh(&self.tab.term.grid); }
+        if self.output_log.as_mut().is_some_and(|log| !log.flush_if_due()) {
+            let path = self.output_log.take().map(|log| log.path.display().to_string()).unwrap_or_default();
+            self.mate.last_message = format!("stopped logging output, writing {path} failed.");
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+        }
+    }
+
+    fn restart_shell(&mut self, code: Option<u32>) {
+        let (tab, cwd_note) = TerminalTab::open(self.tab.launch.clone(), &self.config, self.tab.term.grid.rows, self.tab.term.grid.cols);
+        let split = self.tab.split.take();
+        self.tab = tab;
+        self.tab.split = split;
+        if self.search_open { self.search.refresh(&self.tab.term.grid); }
+        self.mate.last_message = match (&self.tab.pty, code) {
+            (None, _) => "couldn't restart the shell, check the shell setting.".to_string(),
+            (Some(_), Some(c)) => format!("shell exited with code {c}, started a fresh one."),
+            (Some(_), None) => "shell exited, started a fresh one.".to_string(),
+        };
+        if let Some(note) = cwd_note { self.mate.last_message = format!("{} {note}", self.mate.last_message); }
+        self.mate.typing_target = self.mate.last_message.clone();
+        self.mate.typing_chars = 0;
+        self.mate.typing_tick = Instant::now();
     }
 
     fn send_input(&self, data: &str) {
-        if let Some(pty) = &self.pty { let _ = pty.write_str(data); }
+        self.send_bytes(data.as_bytes());
+    }
+
+    fn send_bytes(&self, data: &[u8]) {
+        if let Some(pty) = &self.tab.pty { let _ = pty.write_bytes(data); }
+    }
+
+    fn send_typed(&self, data: &str) {
+        self.send_typed_with(|_| data.to_string());
+    }
+
+    fn send_typed_with(&self, encode: impl Fn(&Grid) -> String) {
+        self.send_input(&encode(&self.tab.term.grid));
+        if !self.broadcast_input { return; }
+        let split_pane = self.tab.split.as_ref().map(|s| &s.pane);
+        let background = self.inactive_tabs.iter().flat_map(|t| std::iter::once(t).chain(t.split.as_ref().map(|s| &s.pane)));
+        for tab in split_pane.into_iter().chain(background) {
+            if let Some(pty) = &tab.pty { let _ = pty.write_bytes(encode(&tab.term.grid).as_bytes()); }
+        }
+    }
+
+    fn tab_count(&self) -> usize {
+        self.inactive_tabs.len() + 1
+    }
+
+    fn tabs_in_order(&self) -> impl Iterator<Item = &TerminalTab> {
+        let (before, after) = self.inactive_tabs.split_at(self.active_tab);
+        before.iter().chain(std::iter::once(&self.tab)).chain(after)
+    }
+
+    fn all_tabs_mut(&mut self) -> impl Iterator<Item = &mut TerminalTab> {
+        std::iter::once(&mut self.tab).chain(self.inactive_tabs.iter_mut())
+    }
+
+    fn new_tab(&mut self) {
+        let launch = LaunchOptions {
+            shell: self.tab.launch.shell.clone(),
+            command: None,
+            working_directory: self.current_cwd(),
+        };
+        let (tab, cwd_note) = TerminalTab::open(launch, &self.config, self.term_rows, self.term_cols);
+        let previous = std::mem::replace(&mut self.tab, tab);
+        self.inactive_tabs.insert(self.active_tab, previous);
+        self.active_tab += 1;
+        self.after_tab_switch();
+        let note = match (&self.tab.pty, cwd_note) {
+            (None, _) => Some("couldn't start a shell for the new tab, check the shell setting.".to_string()),
+            (Some(_), note) => note,
+        };
+        if let Some(note) = note {
+            self.mate.last_message = note;
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+        }
+    }
+
+    fn close_tab(&mut self) {
+        if let Some(split) = self.tab.split.take() {
+            if self.tab.recorder.is_some() { self.stop_recording(); }
+            self.tab = split.pane;
+            self.after_tab_switch();
+            return;
+        }
+        if self.inactive_tabs.is_empty() {
+            self.close_requested = true;
+            return;
+        }
+        if self.tab.recorder.is_some() { self.stop_recording(); }
+        let next = if self.active_tab < self.inactive_tabs.len() {
+            self.inactive_tabs.remove(self.active_tab)
+        } else {
+            self.active_tab -= 1;
+            self.inactive_tabs.remove(self.active_tab)
+        };
+        self.tab = next;
+        self.after_tab_switch();
+    }
+
+    fn request_close_tab(&mut self) {
+        let last = self.tab.split.is_none() && self.inactive_tabs.is_empty();
+        if !last && self.config.confirm_close {
+            if let Some(job) = self.tab.pty.as_ref().and_then(|pty| pty.foreground_job()) {
+                let scope = if self.tab.split.is_some() { CloseScope::Pane } else { CloseScope::Tab };
+                self.close_confirm = Some((scope, vec![job]));
+                return;
+            }
+        }
+        self.close_tab();
+    }
+
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.active_tab || index >= self.tab_count() { return; }
+        let slot = if index < self.active_tab { index } else { index - 1 };
+        let next = self.inactive_tabs.remove(slot);
+        let previous = std::mem::replace(&mut self.tab, next);
+        let insert_at = if index < self.active_tab { self.active_tab - 1 } else { self.active_tab };
+        self.inactive_tabs.insert(insert_at, previous);
+        self.active_tab = index;
+        self.after_tab_switch();
+    }
+
+    fn cycle_tab(&mut self, forward: bool) {
+        let count = self.tab_count();
+        let next = if forward { (self.active_tab + 1) % count } else { (self.active_tab + count - 1) % count };
+        self.switch_tab(next);
+    }
+
+    fn after_tab_switch(&mut self) {
+        self.hovered_link = None;
+        self.hovered_url = None;
+        self.hovered_mark = None;
+        self.mouse_button_down = None;
+        self.mouse_last_cell = None;
+        self.mouse_wheel_lines = 0.0;
+        if self.search_open { self.search.search(&self.tab.term.grid); }
+    }
+
+    fn split_pane(&mut self, axis: SplitAxis) {
+        if self.tab.split.is_some() {
+            self.mate.last_message = "this tab is already split, close a pane first.".into();
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+            return;
+        }
+        let launch = LaunchOptions {
+            shell: self.tab.launch.shell.clone(),
+            command: None,
+            working_directory: self.current_cwd(),
+        };
+        let (pane, _) = TerminalTab::open(launch, &self.config, self.term_rows, self.term_cols);
+        let previous = std::mem::replace(&mut self.tab, pane);
+        self.tab.split = Some(Box::new(PaneSplit { axis, pane: previous, pane_first: true }));
+        self.after_tab_switch();
+    }
+
+    fn focus_other_pane(&mut self) {
+        let Some(mut split) = self.tab.split.take() else { return };
+        std::mem::swap(&mut self.tab, &mut split.pane);
+        split.pane_first = !split.pane_first;
+        self.tab.split = Some(split);
+        self.after_tab_switch();
+    }
+
+    fn pane_rects(&self, rect: Rect) -> (Rect, Option<Rect>) {
+        let Some(split) = &self.tab.split else { return (rect, None) };
+        let half = PANE_GAP * 0.5;
+        let (first, second) = match split.axis {
+            SplitAxis::SideBySide => {
+                let x = rect.center().x;
+                (Rect::from_min_max(rect.min, pos2(x - half, rect.max.y)), Rect::from_min_max(pos2(x + half, rect.min.y), rect.max))
+            }
+            SplitAxis::Stacked => {
+                let y = rect.center().y;
+                (Rect::from_min_max(rect.min, pos2(rect.max.x, y - half)), Rect::from_min_max(pos2(rect.min.x, y + half), rect.max))
+            }
+        };
+        if split.pane_first { (second, Some(first)) } else { (first, Some(second)) }
+    }
+
+    fn draw_split_pane(&mut self, ui: &mut Ui, rect: Rect) {
+        let Some(mut split) = self.tab.split.take() else { return };
+        let (rows, cols) = self.grid_size_for(rect);
+        if split.pane.term.grid.rows != rows || split.pane.term.grid.cols != cols {
+            split.pane.resize(rows, cols);
+        }
+        std::mem::swap(&mut self.tab, &mut split.pane);
+        self.draw_terminal(ui, rect, false);
+        std::mem::swap(&mut self.tab, &mut split.pane);
+        self.tab.split = Some(split);
+    }
+
+    fn reap_exited_shells(&mut self) {
+        let config = &self.config;
+        self.tab.reap_split_pane(config);
+        for tab in &mut self.inactive_tabs {
+            tab.reap_split_pane(config);
+        }
+        let exited = self.inactive_tabs
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, tab)| {
+                let pty = tab.pty.as_mut()?;
+                (!pty.is_alive()).then(|| (i, pty.exit_code()))
+            })
+            .collect::<Vec<_>>();
+        for (i, code) in exited.into_iter().rev() {
+            if let Some(rec) = self.inactive_tabs[i].recorder.take() {
+                if let Err(e) = rec.finish() { log::warn!("{e:#}"); }
+            }
+            match self.config.on_shell_exit {
+                ShellExitAction::Close => match self.inactive_tabs[i].split.take() {
+                    Some(split) => self.inactive_tabs[i] = split.pane,
+                    None => {
+                        self.inactive_tabs.remove(i);
+                        if i < self.active_tab { self.active_tab -= 1; }
+                    }
+                },
+                ShellExitAction::Restart if self.inactive_tabs[i].shell_started.elapsed() >= Duration::from_secs(1) => {
+                    let tab = &mut self.inactive_tabs[i];
+                    let (rows, cols) = (tab.term.grid.rows, tab.term.grid.cols);
+                    let split = tab.split.take();
+                    *tab = TerminalTab::open(tab.launch.clone(), &self.config, rows, cols).0;
+                    tab.split = split;
+                }
+                ShellExitAction::Restart | ShellExitAction::Hold => self.inactive_tabs[i].hold(code),
+            }
+        }
     }
 
     fn send_signal(&self, signal_name: &str) {
-        if let Some(pty) = &self.pty {
+        if let Some(pty) = &self.tab.pty {
             let _ = pty.signal_foreground(signal_name);
         }
     }
 
-    fn execute_command(&mut self, cmd: String) {
-        self.command_history.push(cmd.clone());
-        self.history_idx = None;
-        self.input_buf.clear();
+    fn execute_command(&mut self, cmd: String) -> bool {
+        match DangerPrompt::check(&self.danger_rules, cmd) {
+            Ok(cmd) => { self.send_command(cmd); true }
+            Err(prompt) => { self.danger_prompt = Some(prompt); false }
+        }
+    }
+
+    fn send_command(&mut self, cmd: String) {
+        self.command_history.record(&cmd);
+        self.tab.history_idx = None;
+        self.tab.input_buf.clear();
         self.send_input(&format!("{}\n", cmd));
     }
 
+    fn run_saved_command(&mut self, id: u64) {
+        let Some(saved) = self.mate.commands.commands.iter().find(|c| c.id == id) else { return };
+        let names = saved.placeholders();
+        if !names.is_empty() {
+            let fields = names.into_iter()
+                .map(|name| { let value = saved.last_values.get(&name).cloned().unwrap_or_default(); (name, value) })
+                .collect();
+            self.template_form = Some(TemplateForm { id, label: saved.label.clone(), fields, focus_first: true });
+            return;
+        }
+        let cmd = saved.command.clone();
+        self.mate.commands.increment_use(id);
+        self.execute_command(cmd);
+    }
+
+    fn draw_template_form(&mut self, ctx: &Context) {
+        let Some(form) = &mut self.template_form else { return };
+        let Some(saved) = self.mate.commands.commands.iter().find(|c| c.id == form.id) else {
+            self.template_form = None;
+            return;
+        };
+        let mut open = true;
+        let mut run = false;
+        egui::Window::new(format!("Run {}", form.label))
+            .id(Id::new("template_form"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                egui::Grid::new("template_fields").num_columns(2).spacing([8.0, 6.0]).show(ui, |ui| {
+                    let count = form.fields.len();
+                    for (i, (name, value)) in form.fields.iter_mut().enumerate() {
+                        ui.label(format!("{name}:"));
+                        let r = ui.add(egui::TextEdit::singleline(value).desired_width(220.0));
+                        if i == 0 && std::mem::take(&mut form.focus_first) { r.request_focus(); }
+                        if i + 1 == count && r.lost_focus() && ui.input(|inp| inp.key_pressed(Key::Enter)) { run = true; }
+                        ui.end_row();
+                    }
+                });
+                let values: HashMap<String, String> = form.fields.iter().cloned().collect();
+                ui.add_space(6.0);
+                ui.label(RichText::new(saved.fill(&values)).code().color(Color32::from_rgb(165, 220, 125)).size(11.0));
+                ui.add_space(6.0);
+                if ui.button("Run").clicked() { run = true; }
+            });
+        if run {
+            let values: HashMap<String, String> = form.fields.iter().cloned().collect();
+            let (id, cmd) = (form.id, saved.fill(&values));
+            self.template_form = None;
+            self.mate.commands.remember_values(id, values);
+            self.mate.commands.increment_use(id);
+            self.execute_command(cmd);
+        } else if !open {
+            self.template_form = None;
+        }
+    }
+
+    fn digit_for_key(key: Key) -> Option<char> {
+        match key {
+            Key::Num0 => Some('0'), Key::Num1 => Some('1'), Key::Num2 => Some('2'), Key::Num3 => Some('3'),
+            Key::Num4 => Some('4'), Key::Num5 => Some('5'), Key::Num6 => Some('6'), Key::Num7 => Some('7'),
+            Key::Num8 => Some('8'), Key::Num9 => Some('9'),
+            _ => None,
+        }
+    }
+
+    fn saved_command_for_key(&self, key: Key) -> Option<u64> {
+        Self::digit_for_key(key).and_then(|d| self.mate.commands.by_hotkey(d)).map(|c| c.id)
+    }
+
+    fn current_cwd(&self) -> Option<PathBuf> {
+        self.tab.term.cwd.clone().or_else(|| self.tab.pty.as_ref().and_then(|p| p.shell_cwd()))
+    }
+
+    fn resolve_user_path(&self, input: &str) -> Result<PathBuf> {
+        let expanded = expand_tilde(input);
+        if expanded.is_absolute() { return Ok(expanded); }
+        Ok(self.current_cwd().context("Unknown working directory; use an absolute path")?.join(expanded))
+    }
+
+    fn change_directory(&mut self, input: &str) -> Result<PathBuf> {
+        let target = self.resolve_user_path(input)?;
+        let target = target.canonicalize().with_context(|| format!("No such directory: {}", target.display()))?;
+        if !target.is_dir() { anyhow::bail!("Not a directory: {}", target.display()); }
+        let quoted = format!("'{}'", Self::shell_escape_single(&target.display().to_string()));
+        self.send_input(&format!("cd {quoted}\n"));
+        Ok(target)
+    }
+
+    fn history_recall_enabled(&self) -> bool {
+        !self.tab.term.grid.app_cursor_keys && !self.tab.term.alt_screen_active() && self.tab.term.at_shell_prompt()
+            && !self.command_history.entries.is_empty()
+    }
+
+    fn recall_history(&mut self, older: bool) {
+        let len = self.command_history.entries.len();
+        if len == 0 { return; }
+        self.tab.history_idx = match (self.tab.history_idx, older) {
+            (None, true)     => Some(len - 1),
+            (None, false)    => return,
+            (Some(i), true)  => Some(i.saturating_sub(1)),
+            (Some(i), false) => (i + 1 < len).then_some(i + 1),
+        };
+        let line = self.tab.history_idx.map(|i| self.command_history.entries[i].clone()).unwrap_or_default();
+        self.replace_terminal_input_line(&line);
+        self.tab.input_buf = line;
+    }
+
     fn replace_terminal_input_line(&self, new_line: &str) {
         self.send_input("\x15");
         if !new_line.is_empty() { self.send_input(new_line); }
@@ -2020,38 +5832,72 @@ impl Spiltixal {
         else { format!("{fixed} {tail}") }
     }
 
+    fn prompt_command(&self) -> Option<String> {
+        self.tab.term.prompt_command(&self.config.prompt_regex, &self.tab.input_buf)
+    }
+
     fn finalize_typed_command(&mut self) {
-        let cmd = self.input_buf.trim_end_matches('\n').to_string();
+        let prompt_cmd = self.prompt_command();
+        let at_prompt = prompt_cmd.is_some();
+        let cmd = prompt_cmd.unwrap_or_else(|| self.tab.input_buf.trim_end_matches('\n').to_string());
         if cmd.is_empty() {
-            self.send_input("\n");
-            self.input_buf.clear();
+            self.send_typed("\r");
+            self.tab.input_buf.clear();
             return;
         }
+        self.tab.input_buf = cmd.clone();
         let corrected = self.autocorrect_command(&cmd);
         if corrected != cmd {
             self.replace_terminal_input_line(&corrected);
-            self.input_buf = corrected.clone();
+            self.tab.input_buf = corrected.clone();
             self.mate.last_message = format!("autocorrected: {cmd} → {corrected}");
         }
-        if let Some(reason) = check_dangerous(&self.input_buf) {
-            self.danger_prompt = Some(DangerPrompt { command: self.input_buf.clone(), reason });
+        if let Some(reason) = self.danger_rules.check(&self.tab.input_buf) {
+            self.danger_prompt = Some(DangerPrompt { command: self.tab.input_buf.clone(), reason, run: false });
             return;
         }
-        self.command_history.push(self.input_buf.clone());
-        self.history_idx = None;
-        self.input_buf.clear();
-        self.send_input("\n");
+        if at_prompt { self.command_history.record(&self.tab.input_buf); }
+        self.tab.history_idx = None;
+        self.tab.input_buf.clear();
+        self.send_typed("\r");
+    }
+
+    fn scrollback_text(&self, max_chars: usize) -> (String, bool) {
+        let grid = &self.tab.term.grid;
+        let first = grid.evicted_lines;
+        let last = grid.absolute_line(grid.rows);
+        let mut lines: Vec<String> = (first..last).filter_map(|l| grid.line_text(l, 0)).collect();
+        while lines.last().is_some_and(|l| l.is_empty()) { lines.pop(); }
+        let mut used = 0;
+        let keep = lines.iter().rev().take_while(|l| { used += l.len() + 1; used <= max_chars }).count();
+        let truncated = keep < lines.len() || first > 0;
+        (lines.split_off(lines.len() - keep).join("\n"), truncated)
+    }
+
+    fn last_error_context(&self) -> Option<(String, String)> {
+        if let Some(idx) = self.tab.term.command_marks.iter().rposition(|m| m.exit_code.is_some_and(|c| c != 0)) {
+            return self.mark_context(idx);
+        }
+        let grid = &self.tab.term.grid;
+        let last = grid.absolute_line(grid.rows);
+        let first = last.saturating_sub(500).max(grid.evicted_lines);
+        let hit = (first..last).rev().find(|&l| grid.line_text(l, 0).is_some_and(|t| ERROR_LINE_RE.is_match(&t)))?;
+        let body = (hit.saturating_sub(30).max(first)..(hit + 10).min(last))
+            .filter_map(|l| grid.line_text(l, 0))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(("recent error output".into(), body.trim_end().to_string()))
     }
 
     fn terminal_context(&self) -> String {
-        let total = self.term.grid.scrollback.len() + self.term.grid.rows;
+        let total = self.tab.term.grid.scrollback.len() + self.tab.term.grid.rows;
         let start = total.saturating_sub(12);
         let mut lines = Vec::new();
         for idx in start..total {
-            let row = if idx < self.term.grid.scrollback.len() {
-                &self.term.grid.scrollback[idx]
+            let row = if idx < self.tab.term.grid.scrollback.len() {
+                &self.tab.term.grid.scrollback[idx]
             } else {
-                &self.term.grid.cells[idx - self.term.grid.scrollback.len()]
+                &self.tab.term.grid.cells[idx - self.tab.term.grid.scrollback.len()]
             };
             let line: String = row.iter().map(|c| c.ch).collect::<String>().trim_end().to_string();
             if !line.is_empty() { lines.push(line); }
@@ -2062,24 +5908,56 @@ impl Spiltixal {
     fn ensure_background_texture(&mut self, ctx: &Context) {
         match &self.config.theme.background {
             Background::Image { path, .. } => {
-                if self.bg_texture_path.as_ref() == Some(path) { return; }
+                if self.bg_texture_path.as_ref() == Some(path) {
+                    if let Some(frames) = poll_gif_decode(ctx, "spiltixal-bg-image", &mut self.bg_frames_decode) { self.bg_frames = frames; }
+                    return;
+                }
                 self.bg_texture = image_from_path(path)
                     .map(|ci| ctx.load_texture("spiltixal-bg-image", ci, TextureOptions::LINEAR));
+                self.bg_frames.clear();
+                self.bg_frames_decode = spawn_gif_decode(path);
+                self.bg_video = None;
                 self.bg_texture_path = Some(path.clone());
             }
             Background::Video { path, .. } => {
-                if self.bg_texture_path.as_ref() == Some(path) { return; }
-                self.bg_texture = extract_video_poster(path)
+                if self.bg_texture_path.as_ref() == Some(path) {
+                    self.advance_background_video(ctx);
+                    return;
+                }
+                let poster = extract_video_poster(path);
+                self.bg_video = poster.as_ref()
+                    .filter(|_| self.config.animate_video_background)
+                    .and_then(|ci| VideoPlayer::spawn(path, ci.size));
+                self.bg_texture = poster
                     .map(|ci| ctx.load_texture("spiltixal-bg-video-poster", ci, TextureOptions::LINEAR));
+                self.bg_frames.clear();
+                self.bg_frames_decode = None;
                 self.bg_texture_path = Some(path.clone());
             }
             _ => {
                 self.bg_texture = None;
+                self.bg_frames.clear();
+                self.bg_frames_decode = None;
+                self.bg_video = None;
                 self.bg_texture_path = None;
             }
         }
     }
 
+    fn advance_background_video(&mut self, ctx: &Context) {
+        let Some(player) = &mut self.bg_video else { return };
+        if !player.is_running() {
+            self.bg_video = None;
+            let poster = self.bg_texture_path.as_ref().and_then(extract_video_poster);
+            if let (Some(poster), Some(tex)) = (poster, &mut self.bg_texture) { tex.set(poster, TextureOptions::LINEAR); }
+            return;
+        }
+        if let (Some(frame), Some(tex)) = (player.latest_frame(), &mut self.bg_texture) {
+            tex.set(frame, TextureOptions::LINEAR);
+        }
+        ctx.request_repaint_after(Duration::from_secs_f32(1.0 / VIDEO_FPS as f32));
+    }
+
     fn current_rss_bytes() -> Option<u64> {
         let data = std::fs::read_to_string("/proc/self/status").ok()?;
         let line = data.lines().find(|l| l.starts_with("VmRSS:"))?;
@@ -2108,11 +5986,9 @@ impl Spiltixal {
                 rss = rss.saturating_add(ollama_rss);
             }
         }
-        if rss > 0 {
-            if rss > AI_RAM_LIMIT_BYTES {
-                self.disable_ai();
-                self.mate.last_message = "had to turn off AI — hit the 1.5GB RAM limit.".into();
-            }
+        if rss > AI_RAM_LIMIT_BYTES {
+            self.disable_ai();
+            self.mate.last_message = "had to turn off AI — hit the 1.5GB RAM limit.".into();
         }
     }
 
@@ -2122,84 +5998,647 @@ impl Spiltixal {
         if !pointer_in_terminal { return; }
 
         let dy = ctx.input(|i| i.smooth_scroll_delta.y);
-        if dy.abs() < f32::EPSILON { return; }
+        if dy.abs() < f32::EPSILON || self.tab.term.alt_screen_active() || self.mouse_reporting(ctx) { return; }
 
         let lines = ((dy.abs() / self.cell_h).ceil() as usize).max(1);
-        let max_offset = self.term.grid.scrollback.len();
+        let max_offset = self.tab.term.grid.scrollback.len();
+        self.last_wheel_scroll = Instant::now();
         if dy > 0.0 {
-            self.term.grid.scroll_offset = (self.term.grid.scroll_offset + lines).min(max_offset);
+            self.tab.term.grid.scroll_offset = (self.tab.term.grid.scroll_offset + lines).min(max_offset);
         } else {
-            self.term.grid.scroll_offset = self.term.grid.scroll_offset.saturating_sub(lines);
+            self.tab.term.grid.scroll_offset = self.tab.term.grid.scroll_offset.saturating_sub(lines);
         }
     }
 
-    fn sync_terminal_size(&mut self, rect: Rect) {
+    fn snaps_to_bottom(&self, event: &Event) -> bool {
+        if !self.config.scroll_on_input || self.tab.term.grid.scroll_offset == 0 { return false; }
+        if self.last_wheel_scroll.elapsed() < SCROLL_SNAP_GRACE { return false; }
+        match event {
+            Event::Text(_) | Event::Paste(_) => true,
+            Event::Key { key, pressed: true, modifiers, .. } => {
+                let ctrl = Self::ctrl_or_cmd(*modifiers);
+                let zoom = ctrl && matches!(key, Key::Equals | Key::Plus | Key::Minus | Key::Num0);
+                let hotkey = modifiers.alt || (ctrl && modifiers.shift) || zoom || (modifiers.ctrl && *key == Key::Tab)
+                    || (*key == Key::F12 && self.dropdown.is_some());
+                !(hotkey || self.scrolls_view(*key, *modifiers))
+            }
+            _ => false,
+        }
+    }
+
+    fn scrolls_view(&self, key: Key, modifiers: Modifiers) -> bool {
+        if self.tab.term.alt_screen_active() || self.tab.paused_view.is_some() { return false; }
+        match key {
+            Key::Home | Key::End => {
+                (Self::ctrl_or_cmd(modifiers) && modifiers.shift && !modifiers.alt)
+                    || (modifiers.is_none() && self.tab.term.grid.scroll_offset > 0)
+            }
+            Key::PageUp | Key::PageDown => !modifiers.ctrl && !modifiers.command && !modifiers.alt,
+            _ => false,
+        }
+    }
+
+    fn scroll_view(&mut self, key: Key) {
+        let page = self.term_rows.max(1);
+        let grid = &mut self.tab.term.grid;
+        grid.scroll_offset = match key {
+            Key::Home     => grid.scrollback.len(),
+            Key::End      => 0,
+            Key::PageUp   => (grid.scroll_offset + page).min(grid.scrollback.len()),
+            Key::PageDown => grid.scroll_offset.saturating_sub(page),
+            _             => grid.scroll_offset,
+        };
+    }
+
+    fn mouse_reporting(&self, ctx: &Context) -> bool {
+        self.tab.term.grid.mouse_mode != MouseMode::Off && self.tab.paused_view.is_none() && !ctx.input(|i| i.modifiers.shift)
+    }
+
+    fn mouse_report(&self, button: u8, motion: bool, pressed: bool, cell: (usize, usize), modifiers: Modifiers) -> Vec<u8> {
+        let (row, col) = cell;
+        let mut cb = button;
+        if modifiers.alt  { cb += 8; }
+        if modifiers.ctrl { cb += 16; }
+        if motion         { cb += 32; }
+        if self.tab.term.grid.mouse_sgr {
+            return format!("\x1b[<{};{};{}{}", cb, col + 1, row + 1, if pressed { 'M' } else { 'm' }).into_bytes();
+        }
+        if !pressed && button < 64 { cb = (cb & !3) | 3; }
+        let encode = |v: usize| (32 + v + 1).min(255) as u8;
+        vec![0x1b, b'[', b'M', 32 + cb, encode(col), encode(row)]
+    }
+
+    fn report_mouse(&mut self, ctx: &Context, layer: LayerId, rect: Rect) {
+        if !self.mouse_reporting(ctx) {
+            self.mouse_button_down = None;
+            self.mouse_wheel_lines = 0.0;
+            return;
+        }
+        let mode = self.tab.term.grid.mouse_mode;
+        let on_terminal = |pos: Pos2| ctx.layer_id_at(pos).is_none_or(|l| l == layer);
+        let events = ctx.input(|i| i.events.clone());
+        for event in events {
+            match event {
+                Event::PointerButton { pos, pressed: true, .. } if !on_terminal(pos) => {}
+                Event::PointerButton { pos, button, pressed, modifiers } => {
+                    let Some(cell) = self.cell_at(rect, pos) else {
+                        if !pressed { self.mouse_button_down = None; }
+                        continue;
+                    };
+                    let code = match button {
+                        PointerButton::Primary   => 0,
+                        PointerButton::Middle    => 1,
+                        PointerButton::Secondary => 2,
+                        _ => continue,
+                    };
+                    if !pressed && self.mouse_button_down.is_none() { continue; }
+                    self.mouse_button_down = pressed.then_some(code);
+                    self.mouse_last_cell = Some(cell);
+                    self.terminal_has_focus = true;
+                    self.send_bytes(&self.mouse_report(code, false, pressed, cell, modifiers));
+                }
+                Event::PointerMoved(pos) => {
+                    let Some(cell) = self.cell_at(rect, pos).filter(|_| on_terminal(pos)) else { continue };
+                    if self.mouse_last_cell == Some(cell) { continue; }
+                    let button = match (self.mouse_button_down, mode) {
+                        (Some(b), MouseMode::Drag | MouseMode::Motion) => b,
+                        (None, MouseMode::Motion) => 3,
+                        _ => continue,
+                    };
+                    self.mouse_last_cell = Some(cell);
+                    let modifiers = ctx.input(|i| i.modifiers);
+                    self.send_bytes(&self.mouse_report(button, true, true, cell, modifiers));
+                }
+                _ => {}
+            }
+        }
+        let (dy, hover, modifiers) = ctx.input(|i| (i.smooth_scroll_delta.y, i.pointer.hover_pos(), i.modifiers));
+        let Some(cell) = hover.filter(|&p| on_terminal(p)).and_then(|p| self.cell_at(rect, p)) else {
+            self.mouse_wheel_lines = 0.0;
+            return;
+        };
+        self.mouse_wheel_lines += dy / self.cell_h.max(1.0);
+        let steps = self.mouse_wheel_lines.trunc();
+        self.mouse_wheel_lines -= steps;
+        let button = if steps > 0.0 { 64 } else { 65 };
+        for _ in 0..steps.abs() as usize {
+            self.send_bytes(&self.mouse_report(button, false, true, cell, modifiers));
+        }
+    }
+
+    fn timestamp_gutter_width(&self) -> f32 {
+        if self.config.show_timestamps { self.cell_w * 9.0 } else { 0.0 }
+    }
+
+    fn terminal_text_rect(&self, rect: Rect) -> Rect {
+        let mut text_rect = rect.shrink(self.config.terminal_padding.clamp(0.0, 48.0));
+        text_rect.min.x += self.timestamp_gutter_width();
+        text_rect
+    }
+
+    fn cell_at(&self, rect: Rect, pos: Pos2) -> Option<(usize, usize)> {
+        let text_rect = self.terminal_text_rect(rect);
+        if !text_rect.contains(pos) { return None; }
+        let col = ((pos.x - text_rect.left()) / self.cell_w) as usize;
+        let row = ((pos.y - text_rect.top()) / self.cell_h) as usize;
+        (row < self.tab.term.grid.rows && col < self.tab.term.grid.cols).then_some((row, col))
+    }
+
+    fn update_hovered_link(&mut self, ctx: &Context, rect: Rect) {
+        self.hovered_link = None;
+        self.hovered_url = None;
+        let Some(pos) = ctx.input(|i| i.pointer.hover_pos()) else { return };
+        let Some((row, col)) = self.cell_at(rect, pos) else { return };
+        self.hovered_url = self.url_link_at(row, col);
+        if self.hovered_url.is_none() {
+            let Some(cells) = self.display_row(row) else { return };
+            let line: String = cells.iter().map(|c| c.ch).collect();
+            let cwd = self.current_cwd();
+            self.hovered_link = find_file_link(&line, col, row, cwd.as_deref());
+        }
+        if (self.hovered_link.is_some() || self.hovered_url.is_some()) && ctx.input(|i| Self::ctrl_or_cmd(i.modifiers)) {
+            ctx.set_cursor_icon(CursorIcon::PointingHand);
+        }
+    }
+
+    fn update_hovered_mark(&mut self, ctx: &Context, rect: Rect) {
+        self.hovered_mark = None;
+        if self.tab.paused_view.is_some() { return; }
+        let Some(pos) = ctx.input(|i| i.pointer.hover_pos()) else { return };
+        let Some((row, _)) = self.cell_at(rect, pos) else { return };
+        let line = self.tab.term.grid.view_start_line() + row;
+        let Some(idx) = self.tab.term.command_marks.iter().rposition(|m| {
+            m.end_line.is_some_and(|end| line >= m.prompt_line && line <= end)
+        }) else { return };
+        let mark = &self.tab.term.command_marks[idx];
+        let anchor = mark.command_line.unwrap_or(mark.prompt_line);
+        let Some(anchor_row) = anchor.checked_sub(self.tab.term.grid.view_start_line()).filter(|r| *r < self.tab.term.grid.rows) else { return };
+        let text_rect = self.terminal_text_rect(rect);
+        let chip = Rect::from_min_size(
+            pos2(text_rect.right() - 86.0, text_rect.top() + anchor_row as f32 * self.cell_h),
+            vec2(86.0, self.cell_h),
+        );
+        if chip.contains(pos) { ctx.set_cursor_icon(CursorIcon::PointingHand); }
+        self.hovered_mark = Some((idx, chip));
+    }
+
+    fn mark_context(&self, idx: usize) -> Option<(String, String)> {
+        let mark = self.tab.term.command_marks.get(idx)?;
+        let command = self.tab.term.mark_command_text(mark);
+        let (output, truncated) = self.tab.term.mark_output_text(mark)?;
+        let mut body = format!("$ {command}\n{output}");
+        if truncated { body.push_str("\n(output truncated)"); }
+        if let Some(code) = mark.exit_code { body.push_str(&format!("\n[exit code: {code}]")); }
+        let label = if command.is_empty() { "last command".to_string() } else { command };
+        Some((label, body))
+    }
+
+    fn ask_bob_about_mark(&mut self, idx: usize) {
+        let Some((label, body)) = self.mark_context(idx) else { return };
+        self.mate.pending_context = Some(PendingContext { label, body });
+        self.mate.view = MateView::Chat;
+        self.mate.focus_input = true;
+        self.set_mate_open(true);
+    }
+
+    fn open_url(&mut self, url: &str) {
+        if let Err(e) = Command::new("xdg-open").arg(url).stdin(Stdio::null()).spawn() {
+            self.mate.last_message = format!("couldn't open {url}: {e}");
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+        }
+    }
+
+    fn open_file_link(&mut self, link: &FileLink) {
+        let template = if !self.config.editor_command.trim().is_empty() {
+            self.config.editor_command.clone()
+        } else if let Some(editor) = std::env::var("VISUAL").ok().or_else(|| std::env::var("EDITOR").ok()).filter(|e| !e.trim().is_empty()) {
+            format!("{editor} +{{line}} {{file}}")
+        } else {
+            "xdg-open {file}".into()
+        };
+        let file = link.path.display().to_string();
+        let args = template
+            .split_whitespace()
+            .map(|a| a.replace("{file}", &file).replace("{line}", &link.line.to_string()).replace("{col}", &link.col.to_string()))
+            .collect::<Vec<_>>();
+        let Some((program, rest)) = args.split_first() else { return };
+        if let Err(e) = Command::new(program).args(rest).stdin(Stdio::null()).spawn() {
+            self.mate.last_message = format!("couldn't open {file}: {e}");
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+        }
+    }
+
+    fn grid_size_for(&self, rect: Rect) -> (usize, usize) {
+        let rect = self.terminal_text_rect(rect);
         let rows = ((rect.height() / self.cell_h).floor() as usize).max(2);
         let cols = ((rect.width() / self.cell_w).floor() as usize).max(8);
-        if rows == self.term_rows && cols == self.term_cols { return; }
+        (rows, cols)
+    }
+
+    fn sync_terminal_size(&mut self, rect: Rect) {
+        let (rows, cols) = self.grid_size_for(rect);
         self.term_rows = rows;
         self.term_cols = cols;
-        self.term.resize(rows, cols);
-        if let Some(pty) = &self.pty {
-            let _ = pty.resize(rows as u16, cols as u16);
+        if rows == self.tab.term.grid.rows && cols == self.tab.term.grid.cols { return; }
+        self.tab.resize(rows, cols);
+        if self.search_open { self.search.search(&self.tab.term.grid); }
+    }
+
+    fn sync_fonts(&mut self, ctx: &Context) {
+        if self.fonts_family == self.config.theme.font_family { return; }
+        self.fonts_family = self.config.theme.font_family.clone();
+        let setup = install_fonts(ctx, &self.fonts_family);
+        self.nerd_font_loaded = setup.nerd_loaded;
+        self.styled_fonts = setup.styled;
+        if !setup.family_found && wants_custom_font(&self.fonts_family) {
+            self.mate.last_message = format!("couldn't find the font {}, using the built-in monospace font.", self.fonts_family);
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
         }
     }
 
+    fn zoom_font(&mut self, ctx: &Context, size: f32) {
+        let size = size.clamp(8.0, 32.0);
+        if size == self.config.theme.font_size { return; }
+        self.config.theme.font_size = size;
+        if let Some(state) = &mut self.customize { state.font_size = size; }
+        self.config.save();
+        self.update_cell_metrics(ctx);
+        if let Some(rect) = self.terminal_rect { self.sync_terminal_size(rect); }
+    }
+
     fn update_cell_metrics(&mut self, ctx: &Context) {
         let font_id = FontId::new(self.config.theme.font_size, FontFamily::Monospace);
-        let size = ctx.fonts(|f| f.layout_no_wrap("W".to_owned(), font_id, Color32::WHITE).size());
-        if size.x.is_finite() && size.y.is_finite() && size.x > 0.0 && size.y > 0.0 {
-            let w = (size.x * 10.0).round() / 10.0;
-            let h = ((size.y + 2.0) * 10.0).round() / 10.0;
+        let (advance, size) = ctx.fonts(|f| {
+            let mut widths = CELL_PROBE_GLYPHS.iter().map(|&c| f.glyph_width(&font_id, c)).collect::<Vec<_>>();
+            widths.sort_by(f32::total_cmp);
+            (widths[widths.len() / 2], f.layout_no_wrap("W".to_owned(), font_id.clone(), Color32::WHITE).size())
+        });
+        if advance.is_finite() && size.y.is_finite() && advance > 0.0 && size.y > 0.0 {
+            let factor = self.config.line_height_factor.clamp(0.8, 2.0);
+            let w = (advance * 10.0).round() / 10.0;
+            let h = ((size.y + 2.0) * factor * 10.0).round() / 10.0;
             self.cell_w = w.max(6.0);
             self.cell_h = h.max(10.0);
         }
     }
 
     fn endpoint_is_local_ollama(&self) -> bool {
-        self.config.ai_endpoint.contains("localhost") || self.config.ai_endpoint.contains("127.0.0.1")
+        matches!(self.config.ai_backend, AiBackend::Ollama | AiBackend::OllamaGenerate)
+            && parse_ai_endpoint(&self.config.ai_endpoint).is_ok_and(|url| endpoint_is_loopback(&url))
+    }
+
+    fn rebuild_ai_client(&mut self) -> Result<()> {
+        self.mate.ai_client = None;
+        let client = AiClient::from_config(&self.config)?;
+        self.mate.ai_client = Some(client);
+        self.start_ollama_serve_if_needed()
     }
 
     fn ollama_listening(&self) -> bool {
         TcpStream::connect("127.0.0.1:11434").is_ok()
     }
 
-    fn start_ollama_serve_if_needed(&mut self) -> Result<()> {
-        if !self.endpoint_is_local_ollama() || self.ollama_listening() {
-            return Ok(());
+    fn start_ollama_serve_if_needed(&mut self) -> Result<()> {
+        if !self.endpoint_is_local_ollama() || self.ollama_listening() {
+            return Ok(());
+        }
+        let child = Command::new("ollama")
+            .arg("serve")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to start `ollama serve`")?;
+        self.ollama_child = Some(child);
+        Ok(())
+    }
+
+    fn enable_ai(&mut self) {
+        self.config.ai_enabled = true;
+        if let Err(e) = self.rebuild_ai_client() {
+            self.ai_enable_feedback = format!("AI enabled, but couldn't start: {:#}", e);
+            self.mate.last_message = self.ai_enable_feedback.clone();
+        } else {
+            self.ai_enable_feedback.clear();
+        }
+        self.enforce_ai_ram_limit();
+        self.config.save();
+    }
+
+    fn disable_ai(&mut self) {
+        self.config.ai_enabled = false;
+        self.mate.ai_client = None;
+        if let Some(mut child) = self.ollama_child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.config.save();
+    }
+
+    fn watch_config_file(&mut self) {
+        if self.config_checked.elapsed() < CONFIG_POLL_INTERVAL { return; }
+        self.config_checked = Instant::now();
+        let mtime = Config::modified();
+        if mtime.is_none() || mtime == self.config_mtime { return; }
+        self.config_mtime = mtime;
+        match Config::read_from_disk() {
+            Ok(config) => {
+                if serde_json::to_value(&config).ok() == serde_json::to_value(&self.config).ok() { return; }
+                let before = self.mate.last_message.clone();
+                self.apply_loaded_config(config);
+                self.customize = None;
+                if self.mate.last_message == before { self.mate.last_message = "reloaded config.json.".into(); }
+            }
+            Err(e) => self.mate.last_message = format!("config.json has an error, keeping the current settings: {e:#}"),
+        }
+        self.mate.typing_target = self.mate.last_message.clone();
+        self.mate.typing_chars = 0;
+        self.mate.typing_tick = Instant::now();
+    }
+
+    fn apply_loaded_config(&mut self, config: Config) {
+        let log_path = std::mem::take(&mut self.config.log_output_path);
+        self.config = config;
+        let max_scrollback = self.config.scrollback_lines.clamp(*SCROLLBACK_RANGE.start(), *SCROLLBACK_RANGE.end());
+        for tab in self.all_tabs_mut() { tab.for_each_pane(|pane| pane.term.grid.set_max_scrollback(max_scrollback)); }
+        if log_path != self.config.log_output_path { self.reopen_output_log(); }
+        self.mate.name = self.config.mate_name.clone();
+        self.mate_textures.clear();
+        self.bg_texture_path = None;
+        let (danger_rules, rule_errors) = DangerRules::compile(&self.config);
+        self.danger_rules = danger_rules;
+        if !rule_errors.is_empty() {
+            self.mate.last_message = danger_rule_errors_message(&rule_errors);
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+        }
+        if self.config.ai_enabled {
+            if let Err(e) = self.rebuild_ai_client() {
+                self.mate.last_message = format!("AI enabled, but couldn't start: {:#}", e);
+            }
+        } else {
+            self.mate.ai_client = None;
+            if let Some(mut child) = self.ollama_child.take() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+
+    fn switch_profile(&mut self, name: &str) {
+        set_active_profile(name);
+        self.apply_loaded_config(Config::load());
+        self.mate.commands = SavedCommandStore::load();
+        self.command_history = CommandHistory::load();
+        self.mate.chat_history_path = Mate::default_chat_history_path();
+        self.mate.chat_history = Mate::load_chat_history(&self.mate.chat_history_path);
+        self.tab.history_idx = None;
+        let (layers, drawing) = Self::load_applied_layout();
+        self.applied_layers = layers;
+        self.applied_drawing = drawing;
+        self.customize = None;
+        self.mate.last_message = format!("switched to profile {name}.");
+        self.mate.typing_target = self.mate.last_message.clone();
+        self.mate.typing_chars = 0;
+        self.mate.typing_tick = Instant::now();
+    }
+
+    fn create_profile(&mut self, name: &str) -> Result<()> {
+        if !is_valid_profile_name(name) {
+            anyhow::bail!("Use letters, digits, '-' or '_' (max 40 chars).");
+        }
+        if list_profiles().iter().any(|p| p == name) {
+            anyhow::bail!("Profile {name} already exists.");
+        }
+        if let Some(dir) = profile_dir(name) {
+            std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        set_active_profile(name);
+        self.config.save();
+        self.switch_profile(name);
+        Ok(())
+    }
+
+    fn draw_profile_window(&mut self, ctx: &Context) {
+        if !self.profile_window_open { return; }
+        let current = active_profile();
+        let mut open = true;
+        let mut switch_to = None;
+        let mut create = false;
+        egui::Window::new("Profiles")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .default_width(260.0)
+            .show(ctx, |ui| {
+                for name in list_profiles() {
+                    if ui.selectable_label(name == current, &name).clicked() && name != current {
+                        switch_to = Some(name);
+                    }
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.profile_name_input).desired_width(150.0).hint_text("new profile"));
+                    if ui.button("Create").clicked() { create = true; }
+                });
+                if !self.profile_feedback.is_empty() {
+                    ui.colored_label(Color32::from_rgb(240, 110, 110), &self.profile_feedback);
+                }
+            });
+        if let Some(name) = switch_to {
+            self.switch_profile(&name);
+            self.profile_feedback.clear();
+        }
+        if create {
+            let name = self.profile_name_input.trim().to_string();
+            match self.create_profile(&name) {
+                Ok(()) => { self.profile_name_input.clear(); self.profile_feedback.clear(); }
+                Err(e) => self.profile_feedback = e.to_string(),
+            }
+        }
+        if !open { self.profile_window_open = false; }
+    }
+
+    fn default_export_path(format: ExportFormat) -> PathBuf {
+        let name = format!("spiltixal-{}.{}", Local::now().format("%Y%m%d-%H%M%S"), format.extension());
+        dirs::home_dir().unwrap_or_else(app_data_dir).join(name)
+    }
+
+    fn export_scrollback(&self, format: ExportFormat, path: &str) -> Result<PathBuf> {
+        let path = if path.trim().is_empty() { Self::default_export_path(format) } else { self.resolve_user_path(path.trim())? };
+        let stamps = self.config.show_timestamps;
+        let data = match format {
+            ExportFormat::Text => self.tab.term.grid.export_text(stamps),
+            ExportFormat::Html => self.tab.term.grid.export_html(&self.config.theme, stamps),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn reopen_output_log(&mut self) {
+        self.output_log = None;
+        let path = self.config.log_output_path.trim();
+        if path.is_empty() { return; }
+        if let Err(e) = self.start_output_log() {
+            self.mate.last_message = format!("couldn't open the output log: {e:#}");
+            self.mate.typing_target = self.mate.last_message.clone();
+            self.mate.typing_chars = 0;
+            self.mate.typing_tick = Instant::now();
+        }
+    }
+
+    fn start_output_log(&mut self) -> Result<PathBuf> {
+        self.output_log = None;
+        let path = match self.config.log_output_path.trim() {
+            "" => app_data_dir().join("output.log"),
+            p => expand_config_path(p),
+        };
+        self.output_log = Some(OutputLog::open(path.clone())?);
+        Ok(path)
+    }
+
+    fn toggle_recording(&mut self, path: &str) {
+        if self.tab.recorder.is_some() {
+            self.stop_recording();
+            return;
         }
-        let child = Command::new("ollama")
-            .arg("serve")
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()
-            .context("Failed to start `ollama serve`")?;
-        self.ollama_child = Some(child);
-        Ok(())
+        let path = if path.trim().is_empty() {
+            Ok(dirs::home_dir().unwrap_or_else(app_data_dir).join(format!("spiltixal-{}.cast", Local::now().format("%Y%m%d-%H%M%S"))))
+        } else {
+            self.resolve_user_path(path.trim())
+        };
+        let term_name = self.tab.pty.as_ref().map_or_else(|| resolve_term(&self.config), |p| p.term.clone());
+        let out = match path.and_then(|p| CastRecorder::start(p, self.term_rows, self.term_cols, &self.tab.launch.shell_spec(&self.config).shell, &term_name)) {
+            Ok(rec) => {
+                let out = format!("recording to {}. run /record again to stop.", rec.path.display());
+                self.tab.recorder = Some(rec);
+                out
+            }
+            Err(e) => format!("couldn't start recording: {e:#}"),
+        };
+        self.mate.last_message = out.clone();
+        self.mate.typing_target = out;
+        self.mate.typing_chars = 0;
+        self.mate.typing_tick = Instant::now();
     }
 
-    fn enable_ai(&mut self) {
-        self.config.ai_enabled = true;
-        self.mate.ai_client = Some(AiClient::new(&self.config.ai_endpoint, &self.config.ai_model, &self.config.ai_system_prompt));
-        if let Err(e) = self.start_ollama_serve_if_needed() {
-            self.ai_enable_feedback = format!("AI enabled, but couldn't start Ollama: {}", e);
-            self.mate.last_message = self.ai_enable_feedback.clone();
-        } else {
-            self.ai_enable_feedback.clear();
+    fn stop_recording(&mut self) {
+        let Some(rec) = self.tab.recorder.take() else { return };
+        let out = match rec.finish() {
+            Ok(path) => format!("saved the recording to {}", path.display()),
+            Err(e) => format!("recording stopped, but saving failed: {e:#}"),
+        };
+        self.mate.last_message = out.clone();
+        self.mate.typing_target = out;
+        self.mate.typing_chars = 0;
+        self.mate.typing_tick = Instant::now();
+    }
+
+    fn draw_export_window(&mut self, ctx: &Context) {
+        if !self.export_window_open { return; }
+        let mut open = true;
+        let mut export = false;
+        egui::Window::new("Export scrollback")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Format:");
+                    ui.selectable_value(&mut self.export_format, ExportFormat::Text, "Plain text");
+                    ui.selectable_value(&mut self.export_format, ExportFormat::Html, "HTML");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Path:");
+                    let hint = Self::default_export_path(self.export_format).display().to_string();
+                    ui.add(egui::TextEdit::singleline(&mut self.export_path).desired_width(240.0).hint_text(hint));
+                });
+                if ui.button("Export").clicked() { export = true; }
+                if !self.export_feedback.is_empty() {
+                    ui.label(&self.export_feedback);
+                }
+            });
+        if export {
+            self.export_feedback = match self.export_scrollback(self.export_format, &self.export_path) {
+                Ok(path) => format!("Saved to {}", path.display()),
+                Err(e) => format!("{e:#}"),
+            };
         }
-        self.enforce_ai_ram_limit();
-        self.config.save();
+        if !open { self.export_window_open = false; }
     }
 
-    fn disable_ai(&mut self) {
-        self.config.ai_enabled = false;
-        self.mate.ai_client = None;
-        if let Some(mut child) = self.ollama_child.take() {
-            let _ = child.kill();
-            let _ = child.wait();
+    fn draw_env_window(&mut self, ctx: &Context) {
+        if !self.env_window_open { return; }
+        if self.env_snapshot.is_none() {
+            self.env_snapshot = Some(match self.tab.pty.as_ref() {
+                Some(pty) => pty.shell_environ().map_err(|e| e.to_string()),
+                None => Err("no shell is running".into()),
+            });
+        }
+        let mut open = true;
+        let mut refresh = false;
+        let filter = self.env_filter.to_lowercase();
+        egui::Window::new("Environment")
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(420.0)
+            .default_height(360.0)
+            .show(ctx, |ui| {
+                ui.label(RichText::new("Injected by Spiltixal").strong());
+                if let Some(pty) = self.tab.pty.as_ref() {
+                    ui.monospace(format!("TERM={}", pty.term));
+                }
+                for (key, value) in INJECTED_ENV {
+                    ui.monospace(format!("{key}={value}"));
+                }
+                for (key, value) in &self.config.env {
+                    ui.monospace(format!("{key}={value}"));
+                }
+                ui.label(RichText::new(format!(
+                    "shell: {}{}",
+                    self.tab.launch.shell_spec(&self.config).shell,
+                    if self.config.login_shell { " (login)" } else { " (non-login)" },
+                )).color(Color32::from_gray(160)).size(11.0));
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Shell environment").strong());
+                    if ui.small_button("Refresh").clicked() { refresh = true; }
+                });
+                ui.add(egui::TextEdit::singleline(&mut self.env_filter).desired_width(f32::INFINITY).hint_text("filter"));
+                match self.env_snapshot.as_ref() {
+                    Some(Ok(vars)) => {
+                        egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                            for (key, value) in vars {
+                                if !filter.is_empty() && !key.to_lowercase().contains(&filter) && !value.to_lowercase().contains(&filter) {
+                                    continue;
+                                }
+                                ui.horizontal_wrapped(|ui| {
+                                    ui.monospace(RichText::new(key).color(Color32::from_rgb(120, 190, 255)));
+                                    ui.monospace(value);
+                                });
+                            }
+                        });
+                    }
+                    Some(Err(e)) => { ui.colored_label(Color32::from_rgb(240, 110, 110), e); }
+                    None => {}
+                }
+            });
+        if refresh { self.env_snapshot = None; }
+        if !open {
+            self.env_window_open = false;
+            self.env_snapshot = None;
         }
-        self.config.save();
     }
 
     fn draw_ai_enable_prompt(&mut self, ctx: &Context) -> bool {
@@ -2239,7 +6678,7 @@ impl Spiltixal {
     fn animate_mate_panel(&mut self) {
         let target = if self.mate_open_target { 1.0 } else { 0.0 };
         self.mate_open_anim = egui::emath::lerp(self.mate_open_anim..=target, 0.18);
-        if (self.mate_open_anim - target).abs() < 0.01 {
+        if self.config.reduce_motion || (self.mate_open_anim - target).abs() < 0.01 {
             self.mate_open_anim = target;
         }
     }
@@ -2257,7 +6696,7 @@ impl Spiltixal {
             .unwrap_or(false)
     }
 
-    fn run_picker(program: &str, args: &[&str]) -> Option<PathBuf> {
+    fn run_picker(program: &str, args: &[&str], must_exist: bool) -> Option<PathBuf> {
         let output = Command::new(program).args(args).output().ok()?;
         if !output.status.success() {
             return None;
@@ -2267,34 +6706,55 @@ impl Spiltixal {
             return None;
         }
         let p = PathBuf::from(raw);
-        if p.exists() { Some(p) } else { None }
+        if p.exists() || !must_exist { Some(p) } else { None }
+    }
+
+    fn pick_save_path_via_system(start_dir: Option<PathBuf>, file_name: &str) -> Result<PathBuf> {
+        let home = start_dir.filter(|d| d.is_dir()).or_else(dirs::home_dir).unwrap_or_else(|| PathBuf::from("/"));
+        let suggested = home.join(file_name).display().to_string();
+        let filename_arg = format!("--filename={suggested}");
+        if Self::command_exists("kdialog") {
+            if let Some(p) = Self::run_picker("kdialog", &["--getsavefilename", &suggested], false) {
+                return Ok(p);
+            }
+        }
+        for program in ["zenity", "yad", "qarma"] {
+            if Self::command_exists(program) {
+                if let Some(p) = Self::run_picker(program, &["--file-selection", "--save", "--confirm-overwrite", "--title=Save file", &filename_arg], false) {
+                    return Ok(p);
+                }
+            }
+        }
+        anyhow::bail!("No save dialog detected. Type the path into the export box instead.");
     }
 
-    fn pick_file_via_system(_is_video: bool) -> Result<PathBuf> {
+    fn pick_file_via_system(_is_video: bool, start_dir: Option<PathBuf>) -> Result<PathBuf> {
         let kde_filter = "All Files (*)";
+        let home = start_dir.filter(|d| d.is_dir()).or_else(dirs::home_dir).unwrap_or_else(|| PathBuf::from("/"));
+        let start = home.display().to_string();
+        let filename_arg = format!("--filename={}/", start.trim_end_matches('/'));
 
         if Self::command_exists("kdialog") {
-            if let Some(p) = Self::run_picker("kdialog", &["--getopenfilename", "", kde_filter]) {
+            if let Some(p) = Self::run_picker("kdialog", &["--getopenfilename", &start, kde_filter], true) {
                 return Ok(p);
             }
         }
         if Self::command_exists("zenity") {
-            if let Some(p) = Self::run_picker("zenity", &["--file-selection", "--title=Select file"]) {
+            if let Some(p) = Self::run_picker("zenity", &["--file-selection", "--title=Select file", &filename_arg], true) {
                 return Ok(p);
             }
         }
         if Self::command_exists("yad") {
-            if let Some(p) = Self::run_picker("yad", &["--file-selection", "--title=Select file"]) {
+            if let Some(p) = Self::run_picker("yad", &["--file-selection", "--title=Select file", &filename_arg], true) {
                 return Ok(p);
             }
         }
         if Self::command_exists("qarma") {
-            if let Some(p) = Self::run_picker("qarma", &["--file-selection", "--title=Select file"]) {
+            if let Some(p) = Self::run_picker("qarma", &["--file-selection", "--title=Select file", &filename_arg], true) {
                 return Ok(p);
             }
         }
 
-        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
         if Self::command_exists("dolphin") {
             let _ = Command::new("dolphin").arg(&home).spawn();
             anyhow::bail!("Opened Dolphin. Copy the file path and paste it into the path box.");
@@ -2318,15 +6778,22 @@ impl Spiltixal {
         anyhow::bail!("No file picker detected. Paste the full path manually.");
     }
 
-    fn start_picker(&mut self, is_video: bool) {
+    fn start_picker(&mut self, is_video: bool, target: PickerTarget) {
         if self.picker_in_progress {
             return;
         }
+        self.picker_target = target;
         let (tx, rx) = unbounded::<Result<String, String>>();
         self.picker_in_progress = true;
         self.picker_rx = Some(rx);
+        let start_dir = self.current_cwd();
         thread::spawn(move || {
-            let res = match Spiltixal::pick_file_via_system(is_video) {
+            let picked = match target {
+                PickerTarget::ThemeExport => Spiltixal::pick_save_path_via_system(start_dir, "spiltixal-theme.json"),
+                PickerTarget::LayoutExport => Spiltixal::pick_save_path_via_system(start_dir, "spiltixal-layout.json"),
+                _ => Spiltixal::pick_file_via_system(is_video, start_dir),
+            };
+            let res = match picked {
                 Ok(p) => Ok(p.display().to_string()),
                 Err(e) => Err(e.to_string()),
             };
@@ -2569,126 +7036,634 @@ impl Spiltixal {
         self.mate_textures.get(key).map(|t| t.id())
     }
 
+    fn is_terminal_event(event: &Event) -> bool {
+        matches!(event, Event::Text(_) | Event::Key { .. } | Event::Paste(_) | Event::Copy | Event::Cut)
+    }
+
     fn handle_keys(&mut self, ctx: &Context) {
-        if self.mate_input_focused { return; }
-        ctx.input(|i| {
-            let suppress_text = i.modifiers.ctrl || i.modifiers.command || i.modifiers.alt;
-            for event in &i.events {
-                match event {
-                    Event::Copy => {
-                        self.send_signal("INT");
-                        self.send_input("\x03");
-                        self.input_buf.clear();
-                    }
-                    Event::Cut => {
-                        self.send_input("\x18");
-                        self.input_buf.clear();
-                    }
-                    Event::Paste(text) => {
-                        self.input_buf.push_str(text);
-                        self.send_input(text);
-                    }
-                    Event::Key { key: Key::F, pressed: true, modifiers, .. } if modifiers.alt => {
-                        self.search_open = !self.search_open;
-                        if !self.search_open { self.search.query.clear(); self.search.matches.clear(); }
-                    }
-                    Event::Key { key: Key::M, pressed: true, modifiers, .. } if modifiers.alt => {
-                        self.set_mate_open(!self.mate_open_target);
-                    }
-                    Event::Text(t) if !suppress_text => {
-                        self.input_buf.push_str(&t);
-                        self.send_input(t);
-                    }
-                    Event::Text(t) if i.modifiers.alt => { self.send_input(&format!("\x1b{t}")); }
-                    Event::Key { key: Key::Enter, pressed: true, .. } => { self.send_input("\r"); self.input_buf.clear(); }
-                    Event::Key { key: Key::Backspace, pressed: true, .. } => {
-                        if !self.input_buf.is_empty() { self.input_buf.pop(); self.send_input("\x7f"); }
-                    }
-                    Event::Key { key: Key::C, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
-                        self.send_signal("INT");
-                        self.send_input("\x03");
-                        self.input_buf.clear();
-                    }
-                    Event::Key { key: Key::Z, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
-                        self.send_signal("TSTP");
-                        self.send_input("\x1a");
-                        self.input_buf.clear();
-                    }
-                    Event::Key { key: Key::Backslash, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
-                        self.send_signal("QUIT");
-                        self.send_input("\x1c");
-                        self.input_buf.clear();
+        let events = std::mem::take(&mut self.term_events);
+        if self.mate_input_focused || ctx.memory(|m| m.focused().is_some()) { return; }
+        let modifiers = self.term_modifiers;
+        let suppress_text = modifiers.ctrl || modifiers.command || modifiers.alt;
+        let mut hotkey_fired = false;
+        if let Some(code) = self.tab.shell_exited {
+            if events.iter().any(|e| matches!(e, Event::Key { key: Key::Enter, pressed: true, .. })) {
+                self.restart_shell(code);
+            }
+            return;
+        }
+        if self.terminal_has_focus && events.iter().any(|e| self.snaps_to_bottom(e)) {
+            self.tab.term.grid.scroll_offset = 0;
+        }
+        for event in &events {
+            match event {
+                Event::Key { key: Key::P, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::CommandPalette);
+                }
+                Event::Key { key: Key::F, pressed: true, modifiers, .. } if modifiers.alt => {
+                    self.run_action(Action::ToggleSearch);
+                }
+                Event::Key { key: Key::M, pressed: true, modifiers, .. } if modifiers.alt => {
+                    self.run_action(Action::ToggleBob);
+                }
+                Event::Key { key: Key::Enter, pressed: true, modifiers, .. } if modifiers.alt => {
+                    self.run_action(Action::FocusBob);
+                }
+                Event::Key { key: Key::E, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::ExportScrollback);
+                }
+                Event::Key { key: Key::R, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::ToggleRecording);
+                }
+                Event::Key { key: Key::S, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::TogglePause);
+                }
+                Event::Key { key: Key::T, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::NewTab);
+                }
+                Event::Key { key: Key::W, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::CloseTab);
+                }
+                Event::Key { key: Key::Tab, pressed: true, modifiers, .. } if modifiers.ctrl => {
+                    self.cycle_tab(!modifiers.shift);
+                }
+                Event::Key { key: Key::D, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::SplitSideBySide);
+                }
+                Event::Key { key: Key::O, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::SplitStacked);
+                }
+                Event::Key { key: Key::N, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::FocusNextPane);
+                }
+                Event::Key { key: Key::K, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::ClearScrollback);
+                }
+                Event::Copy if modifiers.shift => {
+                    self.run_action(Action::CopySelection);
+                }
+                Event::Key { key: Key::C, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::CopySelection);
+                }
+                Event::Key { key: Key::ArrowUp, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::PreviousPrompt);
+                }
+                Event::Key { key: Key::ArrowDown, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && modifiers.shift => {
+                    self.run_action(Action::NextPrompt);
+                }
+                Event::Key { key: Key::Equals | Key::Plus, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
+                    self.zoom_font(ctx, self.config.theme.font_size + 1.0);
+                    hotkey_fired = true;
+                }
+                Event::Key { key: Key::Minus, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
+                    self.zoom_font(ctx, self.config.theme.font_size - 1.0);
+                    hotkey_fired = true;
+                }
+                Event::Key { key: Key::Num0, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
+                    self.zoom_font(ctx, Theme::default().font_size);
+                    hotkey_fired = true;
+                }
+                Event::Key { key: Key::F12, pressed: true, .. } if self.dropdown.is_some() => {
+                    self.run_action(Action::ToggleDropdown);
+                }
+                Event::Key { key, pressed: true, modifiers, .. } if modifiers.alt && !modifiers.ctrl && !modifiers.shift && self.saved_command_for_key(*key).is_some() => {
+                    if let Some(id) = self.saved_command_for_key(*key) { self.run_saved_command(id); }
+                    hotkey_fired = true;
+                }
+                Event::Text(_) if hotkey_fired => {}
+                _ if !self.terminal_has_focus => {}
+                Event::Key { key, pressed: true, modifiers, .. } if self.scrolls_view(*key, *modifiers) => {
+                    self.scroll_view(*key);
+                }
+                Event::Copy => {
+                    self.send_signal("INT");
+                    self.send_typed("\x03");
+                    self.tab.input_buf.clear();
+                }
+                Event::Cut => {
+                    self.send_typed("\x18");
+                    self.tab.input_buf.clear();
+                }
+                Event::Paste(text) if self.tab.term.grid.bracketed_paste => {
+                    self.tab.input_buf.push_str(&text.replace("\x1b[201~", "").replace("\x1b[200~", ""));
+                    self.send_typed_with(|grid| grid.paste_seq(text));
+                }
+                Event::Paste(text) => {
+                    self.tab.input_buf.push_str(text);
+                    self.send_typed_with(|grid| grid.paste_seq(text));
+                }
+                Event::Text(t) if !suppress_text => {
+                    self.tab.input_buf.push_str(t);
+                    self.send_typed(t);
+                }
+                Event::Text(t) if modifiers.alt => { self.send_typed(&format!("\x1b{t}")); }
+                Event::Key { key: Key::Enter, pressed: true, .. } if self.tab.term.alt_screen_active() => {
+                    self.tab.input_buf.clear();
+                    self.send_typed("\r");
+                }
+                Event::Key { key: Key::Enter, pressed: true, .. } => { self.finalize_typed_command(); }
+                Event::Key { key: Key::Backspace, pressed: true, .. } if !self.tab.input_buf.is_empty() => {
+                    self.tab.input_buf.pop();
+                    self.send_typed("\x7f");
+                }
+                Event::Key { key: Key::C, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
+                    self.send_signal("INT");
+                    self.send_typed("\x03");
+                    self.tab.input_buf.clear();
+                }
+                Event::Key { key: Key::Z, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
+                    self.send_signal("TSTP");
+                    self.send_typed("\x1a");
+                    self.tab.input_buf.clear();
+                }
+                Event::Key { key: Key::Backslash, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt => {
+                    self.send_signal("QUIT");
+                    self.send_typed("\x1c");
+                    self.tab.input_buf.clear();
+                }
+                Event::Key { key, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt && !modifiers.shift => {
+                    if let Some(code) = Self::key_to_ctrl_byte(*key) {
+                        let ch = (code as char).to_string();
+                        self.send_typed(&ch);
+                        if code == 0x03 || code == 0x15 { self.tab.input_buf.clear(); }
                     }
-                    Event::Key { key, pressed: true, modifiers, .. } if Self::ctrl_or_cmd(*modifiers) && !modifiers.alt && !modifiers.shift => {
-                        if let Some(code) = Self::key_to_ctrl_byte(*key) {
-                            let ch = (code as char).to_string();
-                            self.send_input(&ch);
-                            if code == 0x03 || code == 0x15 { self.input_buf.clear(); }
-                        }
+                }
+                Event::Key { key: Key::Tab,        pressed: true, modifiers, .. } if modifiers.shift => { self.send_typed("\x1b[Z"); }
+                Event::Key { key: Key::Tab,        pressed: true, .. } => { self.send_typed("\t"); }
+                Event::Key { key: Key::Escape,     pressed: true, .. } => { self.send_typed("\x1b"); }
+                Event::Key { key: Key::ArrowUp,    pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_typed("\x1b[1;5A"); }
+                Event::Key { key: Key::ArrowDown,  pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_typed("\x1b[1;5B"); }
+                Event::Key { key: Key::ArrowRight, pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_typed("\x1b[1;5C"); }
+                Event::Key { key: Key::ArrowLeft,  pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_typed("\x1b[1;5D"); }
+                Event::Key { key: Key::ArrowUp,    pressed: true, modifiers, .. } if modifiers.is_none() && self.history_recall_enabled() => { self.recall_history(true); }
+                Event::Key { key: Key::ArrowDown,  pressed: true, modifiers, .. } if modifiers.is_none() && self.history_recall_enabled() => { self.recall_history(false); }
+                Event::Key { key: Key::ArrowUp,    pressed: true, .. } => { self.send_typed_with(|grid| grid.cursor_key_seq('A')); }
+                Event::Key { key: Key::ArrowDown,  pressed: true, .. } => { self.send_typed_with(|grid| grid.cursor_key_seq('B')); }
+                Event::Key { key: Key::ArrowLeft,  pressed: true, .. } => { self.send_typed_with(|grid| grid.cursor_key_seq('D')); }
+                Event::Key { key: Key::ArrowRight, pressed: true, .. } => { self.send_typed_with(|grid| grid.cursor_key_seq('C')); }
+                Event::Key { key: Key::Home,       pressed: true, .. } => { self.send_typed_with(|grid| grid.cursor_key_seq('H')); }
+                Event::Key { key: Key::End,        pressed: true, .. } => { self.send_typed_with(|grid| grid.cursor_key_seq('F')); }
+                Event::Key { key: Key::Delete,     pressed: true, .. } => { self.send_typed("\x1b[3~"); }
+                Event::Key { key: Key::PageUp,     pressed: true, .. } => { self.send_typed("\x1b[5~"); }
+                Event::Key { key: Key::PageDown,   pressed: true, .. } => { self.send_typed("\x1b[6~"); }
+                _ => {}
+            }
+        }
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::CommandPalette => {
+                self.palette_open = !self.palette_open;
+                self.palette_query.clear();
+                self.palette_selected = 0;
+            }
+            Action::ToggleSearch => {
+                self.search_open = !self.search_open;
+                if !self.search_open { self.search.close(); }
+            }
+            Action::ToggleBob => self.set_mate_open(!self.mate_open_target),
+            Action::FocusBob => self.focus_bob_input(),
+            Action::ToggleBobDock => {
+                self.config.bob_dock = if self.config.bob_dock == BobDock::Floating { BobDock::Right } else { BobDock::Floating };
+                self.config.save();
+                self.set_mate_open(true);
+            }
+            Action::ToggleAi => {
+                if self.config.ai_enabled { self.disable_ai(); } else { self.ai_enable_prompt_open = true; }
+            }
+            Action::OpenCustomize => self.open_customize(),
+            Action::OpenSettings => {
+                self.open_customize();
+                if let Some(state) = self.customize.as_mut() { state.settings_open = true; }
+            }
+            Action::Profiles => {
+                if CONFIG_PATH_OVERRIDE.get().is_none() { self.profile_window_open = true; }
+            }
+            Action::Environment => {
+                self.env_window_open = true;
+                self.env_snapshot = None;
+            }
+            Action::ToggleBroadcast => {
+                if self.broadcast_input { self.broadcast_input = false; } else { self.broadcast_confirm = true; }
+            }
+            Action::ToggleDropdown => {
+                if let Some(dd) = self.dropdown.as_mut() { dd.shown = !dd.shown; }
+            }
+            Action::CopySelection => self.copy_selection(),
+            Action::PreviousPrompt => self.jump_to_prompt(false),
+            Action::NextPrompt => self.jump_to_prompt(true),
+            Action::ExportScrollback => {
+                self.export_window_open = true;
+                self.export_feedback.clear();
+            }
+            Action::ToggleRecording => self.toggle_recording(""),
+            Action::NewTab => self.new_tab(),
+            Action::CloseTab => self.request_close_tab(),
+            Action::NextTab => self.cycle_tab(true),
+            Action::SplitSideBySide => self.split_pane(SplitAxis::SideBySide),
+            Action::SplitStacked => self.split_pane(SplitAxis::Stacked),
+            Action::FocusNextPane => self.focus_other_pane(),
+            Action::ClearScrollback => self.clear_scrollback(),
+            Action::TogglePause => {
+                self.tab.paused_view = match self.tab.paused_view {
+                    Some(_) => None,
+                    None => {
+                        let grid = &self.tab.term.grid;
+                        Some(PausedView {
+                            rows:   (0..grid.rows).filter_map(|y| grid.visible_row(y).cloned()).collect(),
+                            stamps: (0..grid.rows).map(|y| grid.visible_stamp(y)).collect(),
+                            wrapped: (0..grid.rows).map(|y| grid.line_wrapped_at(grid.view_start_line() + y)).collect(),
+                            sizes:  (0..grid.rows).map(|y| grid.line_size_at(grid.view_start_line() + y)).collect(),
+                            since:  Instant::now(),
+                        })
                     }
-                    Event::Key { key: Key::Tab,        pressed: true, modifiers, .. } if modifiers.shift => { self.send_input("\x1b[Z"); }
-                    Event::Key { key: Key::Tab,        pressed: true, .. } => { self.send_input("\t"); }
-                    Event::Key { key: Key::Escape,     pressed: true, .. } => { self.send_input("\x1b"); }
-                    Event::Key { key: Key::ArrowUp,    pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_input("\x1b[1;5A"); }
-                    Event::Key { key: Key::ArrowDown,  pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_input("\x1b[1;5B"); }
-                    Event::Key { key: Key::ArrowRight, pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_input("\x1b[1;5C"); }
-                    Event::Key { key: Key::ArrowLeft,  pressed: true, modifiers, .. } if modifiers.ctrl => { self.send_input("\x1b[1;5D"); }
-                    Event::Key { key: Key::ArrowUp,    pressed: true, .. } => { self.send_input("\x1b[A"); }
-                    Event::Key { key: Key::ArrowDown,  pressed: true, .. } => { self.send_input("\x1b[B"); }
-                    Event::Key { key: Key::ArrowLeft,  pressed: true, .. } => { self.send_input("\x1b[D"); }
-                    Event::Key { key: Key::ArrowRight, pressed: true, .. } => { self.send_input("\x1b[C"); }
-                    Event::Key { key: Key::Home,       pressed: true, .. } => { self.send_input("\x1b[H"); }
-                    Event::Key { key: Key::End,        pressed: true, .. } => { self.send_input("\x1b[F"); }
-                    Event::Key { key: Key::Delete,     pressed: true, .. } => { self.send_input("\x1b[3~"); }
-                    Event::Key { key: Key::PageUp,     pressed: true, .. } => { self.send_input("\x1b[5~"); }
-                    Event::Key { key: Key::PageDown,   pressed: true, .. } => { self.send_input("\x1b[6~"); }
-                    _ => {}
+                };
+            }
+        }
+    }
+
+    fn jump_to_prompt(&mut self, forward: bool) {
+        if self.tab.term.alt_screen_active() || self.tab.paused_view.is_some() { return; }
+        let grid = &self.tab.term.grid;
+        let top = grid.view_start_line();
+        let target = if forward {
+            self.tab.term.command_marks.iter().map(|m| m.prompt_line).find(|&l| l > top)
+        } else {
+            self.tab.term.command_marks.iter().rev().map(|m| m.prompt_line).find(|&l| l < top)
+        };
+        let bottom = grid.evicted_lines + grid.scrollback.len();
+        self.tab.term.grid.scroll_offset = match target {
+            Some(line) => bottom.saturating_sub(line).min(grid.scrollback.len()),
+            None if forward => 0,
+            None => return,
+        };
+    }
+
+    fn clear_scrollback(&mut self) {
+        self.tab.term.grid.clear_all();
+        self.tab.term.command_marks.clear();
+        self.tab.selection = None;
+        self.tab.paused_view = None;
+    }
+
+    fn copy_selection(&mut self) {
+        let Some(sel) = self.tab.selection.filter(|s| !s.is_empty()) else { return };
+        let text = sel.text(&self.tab.term.grid);
+        if !text.is_empty() { self.copy_request = Some(text); }
+    }
+
+    fn selection_point(&self, pos: Pos2, rect: Rect) -> SelectionPoint {
+        let text_rect = self.terminal_text_rect(rect);
+        let row = (((pos.y - text_rect.top()) / self.cell_h).max(0.0) as usize).min(self.tab.term.grid.rows.saturating_sub(1));
+        let cell_w = if self.display_line_size(row).is_double() { self.cell_w * 2.0 } else { self.cell_w };
+        let col = (((pos.x - text_rect.left()) / cell_w).max(0.0) as usize).min(self.tab.term.grid.cols.saturating_sub(1));
+        SelectionPoint { line: self.tab.term.grid.view_start_line() + row, col }
+    }
+
+    fn select_word_or_line(&mut self, pos: Pos2, rect: Rect, whole_line: bool) {
+        if self.tab.paused_view.is_some() { return; }
+        let point = self.selection_point(pos, rect);
+        let grid = &self.tab.term.grid;
+        let (anchor, head) = if whole_line {
+            let (start, end) = grid.logical_line_bounds(point.line);
+            (SelectionPoint { line: start, col: 0 }, SelectionPoint { line: end, col: grid.cols.saturating_sub(1) })
+        } else {
+            let Some((start, end)) = grid.word_bounds(point.line, point.col) else { return };
+            (SelectionPoint { line: point.line, col: start }, SelectionPoint { line: point.line, col: end })
+        };
+        self.tab.selection = Some(Selection { anchor, head, block: false });
+    }
+
+    fn update_selection(&mut self, ctx: &Context, resp: &Response, rect: Rect) {
+        if self.tab.paused_view.is_some() { return; }
+        let Some(pos) = resp.interact_pointer_pos() else { return };
+        let text_rect = self.terminal_text_rect(rect);
+        if resp.dragged() && self.tab.selection.is_some() && !self.tab.term.alt_screen_active() {
+            let grid = &mut self.tab.term.grid;
+            if pos.y < text_rect.top() {
+                grid.scroll_offset = (grid.scroll_offset + 1).min(grid.scrollback.len());
+                ctx.request_repaint();
+            } else if pos.y > text_rect.bottom() {
+                grid.scroll_offset = grid.scroll_offset.saturating_sub(1);
+                ctx.request_repaint();
+            }
+        }
+        let point = self.selection_point(pos, rect);
+        if resp.drag_started() {
+            let block = ctx.input(|i| i.modifiers.alt);
+            self.tab.selection = Some(Selection { anchor: point, head: point, block });
+        } else if resp.dragged() {
+            if let Some(sel) = self.tab.selection.as_mut() { sel.head = point; }
+        }
+    }
+
+    fn display_row(&self, y: usize) -> Option<&Vec<Cell>> {
+        match &self.tab.paused_view {
+            Some(view) => view.rows.get(y),
+            None => self.tab.term.grid.visible_row(y),
+        }
+    }
+
+    fn display_wrapped(&self, y: usize) -> bool {
+        match &self.tab.paused_view {
+            Some(view) => view.wrapped.get(y).copied().unwrap_or(false),
+            None => self.tab.term.grid.line_wrapped_at(self.tab.term.grid.view_start_line() + y),
+        }
+    }
+
+    fn url_link_at(&self, row: usize, col: usize) -> Option<UrlLink> {
+        let mut first = row;
+        while first > 0 && self.display_wrapped(first - 1) { first -= 1; }
+        let mut last = row;
+        while self.display_wrapped(last) && self.display_row(last + 1).is_some() { last += 1; }
+        let mut text = String::new();
+        let mut cells = Vec::new();
+        for y in first..=last {
+            for (x, cell) in self.display_row(y)?.iter().enumerate() {
+                if cell.width == 0 { continue; }
+                text.push(cell.ch);
+                cells.push((y, x, cell.width as usize));
+            }
+        }
+        let hovered = cells.iter().rposition(|&(y, x, _)| y == row && x <= col)?;
+        for m in URL_RE.find_iter(&text) {
+            let url = trim_url(m.as_str());
+            let start = text[..m.start()].chars().count();
+            let end = start + url.chars().count();
+            if hovered < start || hovered >= end { continue; }
+            let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+            for &(y, x, w) in &cells[start..end] {
+                match spans.last_mut() {
+                    Some(span) if span.0 == y => span.2 = x + w,
+                    _ => spans.push((y, x, x + w)),
                 }
             }
-        });
+            return Some(UrlLink { url: url.to_string(), spans });
+        }
+        None
+    }
+
+    fn display_line_size(&self, y: usize) -> LineSize {
+        match &self.tab.paused_view {
+            Some(view) => view.sizes.get(y).copied().unwrap_or_default(),
+            None => self.tab.term.grid.line_size_at(self.tab.term.grid.view_start_line() + y),
+        }
+    }
+
+    fn display_stamp(&self, y: usize) -> Option<DateTime<Local>> {
+        match &self.tab.paused_view {
+            Some(view) => view.stamps.get(y).copied().flatten(),
+            None => self.tab.term.grid.visible_stamp(y),
+        }
+    }
+
+    fn window_opacity(&self) -> f32 {
+        let dimmed = self.config.unfocused_opacity.min(self.config.opacity);
+        egui::emath::lerp(self.config.opacity..=dimmed, self.unfocus_dim)
+    }
+
+    fn sync_window_title(&mut self, ctx: &Context) {
+        let wanted = match self.tab.term.title.trim() { "" => "Spiltixal", t => t };
+        if wanted == self.window_title { self.window_title_since = None; return; }
+        let since = *self.window_title_since.get_or_insert_with(Instant::now);
+        let waited = since.elapsed();
+        if waited < WINDOW_TITLE_DEBOUNCE {
+            ctx.request_repaint_after(WINDOW_TITLE_DEBOUNCE - waited);
+            return;
+        }
+        self.window_title = wanted.to_string();
+        self.window_title_since = None;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Title(self.window_title.clone()));
+    }
+
+    fn animate_unfocus_dim(&mut self, ctx: &Context) {
+        let focused = ctx.input(|i| i.viewport().focused).unwrap_or(true);
+        let target = if self.config.dim_on_unfocus && is_hyprland() && !focused { 1.0 } else { 0.0 };
+        if self.unfocus_dim == target { return; }
+        self.unfocus_dim = if self.config.reduce_motion { target } else { egui::emath::lerp(self.unfocus_dim..=target, 0.2) };
+        if (self.unfocus_dim - target).abs() < 0.01 { self.unfocus_dim = target; }
+        ctx.request_repaint();
+    }
+
+    fn animate_dropdown(&mut self, ctx: &Context) {
+        let reduce_motion = self.config.reduce_motion;
+        let Some(dd) = self.dropdown.as_mut() else { return };
+        let Some(monitor) = ctx.input(|i| i.viewport().monitor_size) else { return };
+        let height = (monitor.y * DROPDOWN_HEIGHT_FRACTION).round();
+        let first = dd.applied != Some(monitor);
+        if first {
+            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(vec2(monitor.x, height)));
+            dd.applied = Some(monitor);
+        }
+        let target = if dd.shown { 1.0 } else { 0.0 };
+        let prev = dd.anim;
+        dd.anim = if reduce_motion { target } else { egui::emath::lerp(prev..=target, 0.25) };
+        if (dd.anim - target).abs() < 0.01 { dd.anim = target; }
+        if first || dd.anim != prev {
+            let visible = DROPDOWN_PEEK + (height - DROPDOWN_PEEK) * dd.anim;
+            ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(pos2(0.0, visible - height)));
+        }
+        if dd.anim != target { ctx.request_repaint(); }
+        if !dd.shown && dd.anim == 0.0 && ctx.input(|i| i.pointer.primary_clicked()) { dd.shown = true; }
+    }
+
+    fn draw_broadcast_confirm(&mut self, ctx: &Context) {
+        if !self.broadcast_confirm { return; }
+        let mut enable = false; let mut cancel = false;
+        egui::Window::new("Broadcast input?")
+            .collapsible(false).resizable(false).anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(RichText::new("Every keystroke will be sent to all sessions at once.").color(Color32::from_rgb(255, 160, 100)));
+                ui.label(RichText::new("Commands you type will run everywhere. Turn it off from the title bar or the command palette.").color(Color32::from_gray(190)));
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new(RichText::new("Enable broadcast").color(Color32::from_rgb(255, 80, 80)).strong())
+                        .fill(Color32::from_rgba_unmultiplied(80, 20, 20, 200))).clicked() { enable = true; }
+                    ui.add_space(8.0);
+                    if ui.button("Cancel").clicked() { cancel = true; }
+                });
+            });
+        if enable { self.broadcast_input = true; }
+        if enable || cancel { self.broadcast_confirm = false; }
+    }
+
+    fn draw_command_palette(&mut self, ctx: &Context) {
+        if !self.palette_open { return; }
+        let mut matches: Vec<(i32, Action)> = Action::ALL.iter()
+            .filter(|a| **a != Action::CommandPalette)
+            .filter(|a| **a != Action::ToggleDropdown || self.dropdown.is_some())
+            .filter_map(|a| fuzzy_score(&self.palette_query, a.label()).map(|score| (score, *a)))
+            .collect();
+        matches.sort_by_key(|m| std::cmp::Reverse(m.0));
+        if !matches.is_empty() { self.palette_selected = self.palette_selected.min(matches.len() - 1); }
+
+        let mut chosen = None;
+        let mut close = false;
+        egui::Area::new("command_palette".into())
+            .anchor(Align2::CENTER_TOP, vec2(0.0, 70.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                egui::Frame::none()
+                    .fill(Color32::from_rgba_unmultiplied(16, 16, 28, 245))
+                    .rounding(8.0)
+                    .stroke(Stroke::new(1.0, Color32::from_rgb(70, 100, 170)))
+                    .inner_margin(Margin::symmetric(10.0, 8.0))
+                    .show(ui, |ui| {
+                        ui.set_width(420.0);
+                        ui.input_mut(|i| {
+                            if i.consume_key(Modifiers::NONE, Key::ArrowDown) { self.palette_selected += 1; }
+                            if i.consume_key(Modifiers::NONE, Key::ArrowUp) { self.palette_selected = self.palette_selected.saturating_sub(1); }
+                            if i.consume_key(Modifiers::NONE, Key::Escape) { close = true; }
+                            if i.consume_key(Modifiers::NONE, Key::Enter) { chosen = matches.get(self.palette_selected).map(|m| m.1); }
+                        });
+                        let query = ui.add(
+                            egui::TextEdit::singleline(&mut self.palette_query)
+                                .desired_width(f32::INFINITY)
+                                .hint_text("type a command...")
+                        );
+                        query.request_focus();
+                        if query.changed() { self.palette_selected = 0; }
+                        ui.add_space(4.0);
+                        for (idx, (_, action)) in matches.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                let selected = idx == self.palette_selected.min(matches.len().saturating_sub(1));
+                                if ui.selectable_label(selected, action.label()).clicked() { chosen = Some(*action); }
+                                if let Some(keys) = action.keybinding() {
+                                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                                        ui.label(RichText::new(keys).color(Color32::from_gray(140)).size(11.0));
+                                    });
+                                }
+                            });
+                        }
+                        if matches.is_empty() {
+                            ui.label(RichText::new("no matching commands").color(Color32::from_gray(140)).size(11.0));
+                        }
+                    });
+            });
+
+        if close || chosen.is_some() {
+            self.palette_open = false;
+            self.palette_query.clear();
+            ctx.memory_mut(|m| m.stop_text_input());
+        }
+        if let Some(action) = chosen { self.run_action(action); }
+    }
+
+    fn focus_bob_input(&mut self) {
+        self.set_mate_open(true);
+        self.mate.view = MateView::Chat;
+        self.mate.focus_input = true;
+        self.mate_input_focused = true;
+        self.terminal_has_focus = false;
+    }
+
+    fn draw_danger_prompt(&mut self, ctx: &Context) -> bool {
+        let Some(dp) = &self.danger_prompt else { return false };
+        let (command, reason, run) = (dp.command.clone(), dp.reason.clone(), dp.run);
+        let mut confirmed = false; let mut cancelled = false;
+        egui::Window::new("Dangerous Command Detected")
+            .collapsible(false).resizable(false).anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("WARNING").color(Color32::from_rgb(255, 80, 80)).size(18.0).strong());
+                    ui.label(RichText::new("This command seems dangerous...").color(Color32::from_rgb(255, 160, 100)).size(14.0));
+                });
+                ui.add_space(6.0);
+                egui::Frame::none().fill(Color32::from_rgba_unmultiplied(60,15,15,200)).rounding(4.0)
+                    .inner_margin(Margin::symmetric(10.0, 8.0)).show(ui, |ui| {
+                    ui.label(RichText::new(&command).code().color(Color32::from_rgb(255, 200, 100)));
+                });
+                ui.add_space(6.0);
+                ui.label(RichText::new(reason).color(Color32::from_gray(200)));
+                ui.add_space(8.0);
+                ui.label(RichText::new("Are you sure you want to run this?").strong());
+                ui.add_space(6.0);
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new(RichText::new("[y] YES, I KNOW WHAT I'M DOING").color(Color32::from_rgb(255,80,80)).strong())
+                        .fill(Color32::from_rgba_unmultiplied(80,20,20,200))).clicked() { confirmed = true; }
+                    ui.add_space(8.0);
+                    if ui.add(egui::Button::new(RichText::new("[n] No, cancel").color(Color32::WHITE))
+                        .fill(Color32::from_rgba_unmultiplied(40,80,40,200))).clicked() { cancelled = true; }
+                });
+            });
+        if confirmed && run {
+            self.danger_prompt = None;
+            self.send_command(command);
+        }
+        else if confirmed {
+            if self.prompt_command().is_some() { self.command_history.record(&command); }
+            self.tab.history_idx = None;
+            self.tab.input_buf.clear();
+            self.danger_prompt = None;
+            self.send_typed("\r");
+        }
+        else if cancelled {
+            self.danger_prompt = None;
+            if !run {
+                self.tab.input_buf.clear();
+                self.send_typed("\x15");
+            }
+        }
+        true
+    }
+
+    fn running_jobs(&mut self) -> Vec<String> {
+        let mut jobs = Vec::new();
+        for tab in self.all_tabs_mut() {
+            tab.for_each_pane(|pane| {
+                if let Some(job) = pane.pty.as_ref().and_then(|pty| pty.foreground_job()) { jobs.push(job); }
+            });
+        }
+        jobs
     }
 
-    fn draw_danger_prompt(&mut self, ctx: &Context) -> bool {
-        let Some(dp) = &self.danger_prompt else { return false };
-        let (command, reason) = (dp.command.clone(), dp.reason);
+    fn draw_close_confirm(&mut self, ctx: &Context) -> bool {
+        let Some((scope, jobs)) = &self.close_confirm else { return false };
+        let (scope, jobs) = (*scope, jobs.clone());
+        let (title, warning) = match scope {
+            CloseScope::Window => ("Close Spiltixal?", "Closing the window will terminate it."),
+            CloseScope::Tab    => ("Close tab?",       "Closing the tab will terminate it."),
+            CloseScope::Pane   => ("Close pane?",      "Closing the pane will terminate it."),
+        };
         let mut confirmed = false; let mut cancelled = false;
-        egui::Window::new("Dangerous Command Detected")
+        egui::Window::new(title)
             .collapsible(false).resizable(false).anchor(Align2::CENTER_CENTER, Vec2::ZERO)
             .show(ctx, |ui| {
                 ui.add_space(8.0);
-                ui.horizontal(|ui| {
-                    ui.label(RichText::new("WARNING").color(Color32::from_rgb(255, 80, 80)).size(18.0).strong());
-                    ui.label(RichText::new("This command seems dangerous...").color(Color32::from_rgb(255, 160, 100)).size(14.0));
-                });
+                ui.label(RichText::new(if jobs.len() == 1 { "A program is still running:" } else { "Programs are still running:" })
+                    .color(Color32::from_rgb(255, 160, 100)).size(14.0));
                 ui.add_space(6.0);
                 egui::Frame::none().fill(Color32::from_rgba_unmultiplied(60,15,15,200)).rounding(4.0)
                     .inner_margin(Margin::symmetric(10.0, 8.0)).show(ui, |ui| {
-                    ui.label(RichText::new(&command).code().color(Color32::from_rgb(255, 200, 100)));
+                    for job in &jobs {
+                        ui.label(RichText::new(job).code().color(Color32::from_rgb(255, 200, 100)));
+                    }
                 });
-                ui.add_space(6.0);
-                ui.label(RichText::new(reason).color(Color32::from_gray(200)));
                 ui.add_space(8.0);
-                ui.label(RichText::new("Are you sure you want to run this?").strong());
+                ui.label(RichText::new(warning).strong());
                 ui.add_space(6.0);
                 ui.horizontal(|ui| {
-                    if ui.add(egui::Button::new(RichText::new("[y] YES, I KNOW WHAT I'M DOING").color(Color32::from_rgb(255,80,80)).strong())
+                    if ui.add(egui::Button::new(RichText::new("[y] Close anyway").color(Color32::from_rgb(255,80,80)).strong())
                         .fill(Color32::from_rgba_unmultiplied(80,20,20,200))).clicked() { confirmed = true; }
                     ui.add_space(8.0);
-                    if ui.add(egui::Button::new(RichText::new("[n] No, cancel").color(Color32::WHITE))
+                    if ui.add(egui::Button::new(RichText::new("[n] Keep running").color(Color32::WHITE))
                         .fill(Color32::from_rgba_unmultiplied(40,80,40,200))).clicked() { cancelled = true; }
                 });
             });
-        if confirmed {
-            self.command_history.push(command.clone());
-            self.history_idx = None;
-            self.input_buf.clear();
-            self.danger_prompt = None;
-            self.send_input("\n");
+        let (yes, no) = ctx.input(|i| (i.key_pressed(Key::Y), i.key_pressed(Key::N) || i.key_pressed(Key::Escape)));
+        if confirmed || yes {
+            self.close_confirm = None;
+            if scope == CloseScope::Window {
+                self.close_confirmed = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            } else {
+                self.close_tab();
+            }
         }
-        else if cancelled {
-            self.danger_prompt = None;
-            self.input_buf.clear();
-            self.replace_terminal_input_line("");
+        else if cancelled || no {
+            self.close_confirm = None;
         }
         true
     }
@@ -2703,10 +7678,31 @@ impl Spiltixal {
         painter.line_segment([rect.left_bottom(),  rect.left_top()],     Stroke::new(1.5, c2));
     }
 
-    fn draw_terminal(&mut self, ui: &mut Ui, rect: Rect) {
+    fn cell_format(styled_fonts: [bool; 3], theme: &Theme, attrs: Attrs, fg: Color32, bg: Color32, size: f32, is_link: bool) -> (TextFormat, bool) {
+        let slot = match (attrs.bold, attrs.italic) {
+            (true, true) if styled_fonts[2] => Some(2),
+            (true, _) if styled_fonts[0]   => Some(0),
+            (false, true) if styled_fonts[1] => Some(1),
+            _ => None,
+        };
+        let family = slot.map_or(FontFamily::Monospace, |s| FontFamily::Name(TERM_STYLE_FAMILIES[s].into()));
+        let color = if attrs.dim {
+            let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+            Color32::from_rgba_unmultiplied(mix(fg.r(), bg.r()), mix(fg.g(), bg.g()), mix(fg.b(), bg.b()), fg.a())
+        } else { fg };
+        let mut fmt = TextFormat { font_id: FontId::new(size, family), color, ..Default::default() };
+        fmt.italics = attrs.italic && !matches!(slot, Some(1 | 2));
+        if attrs.underline == UnderlineStyle::Single || is_link {
+            fmt.underline = Stroke::new(1.0, attrs.underline_color.map_or(color, |c| c.resolve(true, theme)));
+        }
+        if attrs.strikeout { fmt.strikethrough = Stroke::new(1.0, color); }
+        (fmt, attrs.bold && !matches!(slot, Some(0 | 2)))
+    }
+
+    fn draw_terminal(&mut self, ui: &mut Ui, rect: Rect, focused: bool) {
         let painter = ui.painter_at(rect);
         let bg = if is_hyprland() {
-            self.config.theme.bg_alpha((self.config.opacity * 255.0) as u8)
+            self.config.theme.bg_alpha((self.window_opacity() * 255.0) as u8)
         } else {
             self.config.theme.bg()
         };
@@ -2724,7 +7720,11 @@ impl Spiltixal {
         };
         painter.rect_filled(Rect::from_min_max(rect.min, pos2(rect.max.x, rect.min.y + 20.0)), 4.0, glow);
 
-        if let Some(tex) = &self.bg_texture {
+        let bg_frame = animation_frame(&self.bg_frames, self.anim_t).map(|(tex, next)| {
+            painter.ctx().request_repaint_after(next);
+            tex
+        });
+        if let Some(tex) = bg_frame.or(self.bg_texture.as_ref()) {
             let tint = match &self.config.theme.background {
                 Background::Image { opacity, .. } | Background::Video { opacity, .. } => {
                     Color32::from_rgba_unmultiplied(255, 255, 255, (opacity * 255.0) as u8)
@@ -2756,20 +7756,44 @@ impl Spiltixal {
             }
         }
 
+        let text_rect = self.terminal_text_rect(rect);
         let theme   = &self.config.theme;
         let font_id = FontId::new(theme.font_size, FontFamily::Monospace);
-        let (cw, ch, cx, cy) = (self.cell_w, self.cell_h, self.term.grid.cursor_x, self.term.grid.cursor_y);
-
-        for row_idx in 0..self.term.grid.rows {
-            let Some(row) = self.term.grid.visible_row(row_idx) else { continue };
-            for col_idx in 0..self.term.grid.cols {
+        let (cw, ch, cx, cy) = (self.cell_w, self.cell_h, self.tab.term.grid.cursor_x, self.tab.term.grid.cursor_y);
+        let blink_half = self.config.blink_half_period();
+        let blink_off = blink_half.is_some_and(|h| (self.anim_t / h) as u64 % 2 == 1);
+        let (cursor_shape, cursor_blinks) = self.tab.term.grid.cursor_style.unwrap_or((self.config.cursor_shape, true));
+        let mut blink_seen = false;
+
+        for row_idx in 0..self.tab.term.grid.rows {
+            let Some(row) = self.display_row(row_idx) else { continue };
+            let line = self.tab.term.grid.view_start_line() + row_idx;
+            let failed = self.tab.paused_view.is_none() && self.tab.term.command_marks.iter().rev().any(|m| {
+                m.exit_code.is_some_and(|c| c != 0) && m.end_line.is_some_and(|end| line >= m.prompt_line && line < end)
+            });
+            if failed {
+                let bar_x = (text_rect.left() - 4.0).max(rect.left());
+                let bar = Rect::from_min_size(pos2(bar_x, text_rect.top() + row_idx as f32 * ch), vec2(2.0, ch));
+                painter.rect_filled(bar, 0.0, Color32::from_rgb(230, 85, 85));
+            }
+            let line_size = self.display_line_size(row_idx);
+            let scale_x = if line_size.is_double() { 2.0 } else { 1.0 };
+            let mut glyphs: Vec<(usize, char, u8, TextFormat, bool)> = Vec::new();
+            for col_idx in 0..self.tab.term.grid.cols {
+                if line_size.is_double() && col_idx >= (self.tab.term.grid.cols / 2).max(1) { break; }
                 let Some(cell) = row.get(col_idx) else { continue };
-                let x = rect.left() + col_idx as f32 * cw;
-                let y = rect.top()  + row_idx  as f32 * ch;
-                let cell_rect = Rect::from_min_size(pos2(x, y), vec2(cw, ch));
-
-                let is_match   = self.search.is_match_at(row_idx, col_idx);
-                let is_current = self.search.is_current_at(row_idx, col_idx);
+                let x = text_rect.left() + col_idx as f32 * cw * scale_x;
+                let y = text_rect.top()  + row_idx  as f32 * ch;
+                let cell_rect = Rect::from_min_size(pos2(x, y), vec2(cw * scale_x, ch));
+
+                let view_line  = self.tab.term.grid.view_start_line() + row_idx;
+                let search_row = view_line - self.tab.term.grid.evicted_lines;
+                let is_match   = focused && self.search.is_match_at(search_row, col_idx);
+                let is_current = focused && self.search.is_current_at(search_row, col_idx);
+                let is_selected = self.tab.paused_view.is_none()
+                    && self.tab.selection.is_some_and(|s| !s.is_empty() && s.contains(view_line, col_idx));
+                let is_link    = focused && (self.hovered_link.as_ref().is_some_and(|l| l.row == row_idx && col_idx >= l.start_col && col_idx < l.end_col)
+                    || self.hovered_url.as_ref().is_some_and(|u| u.covers(row_idx, col_idx)));
 
                 let (mut fg, mut bg_cell) = if cell.attrs.reverse {
                     (cell.bg.resolve(false, theme), cell.fg.resolve(true, theme))
@@ -2779,36 +7803,148 @@ impl Spiltixal {
 
                 if is_current     { bg_cell = Color32::from_rgb(255, 200, 0); fg = Color32::BLACK; }
                 else if is_match  { bg_cell = Color32::from_rgb(70, 155, 50); fg = Color32::WHITE; }
+                else if is_selected {
+                    let sc = theme.selection_color;
+                    bg_cell = Color32::from_rgba_unmultiplied(sc[0], sc[1], sc[2], sc[3].max(140));
+                }
 
-                if bg_cell != theme.bg() || is_match || is_current {
+                if bg_cell != theme.bg() || is_match || is_current || is_selected {
                     painter.rect_filled(cell_rect, 0.0, bg_cell);
                 }
 
-                if row_idx == cy && col_idx == cx && self.cursor_visible {
+                if row_idx == cy && col_idx == cx && !focused && self.tab.term.grid.cursor_visible_app && self.tab.paused_view.is_none() {
+                    let cc = theme.cursor_color;
+                    painter.rect_stroke(cell_rect, 2.0, Stroke::new(1.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 160)));
+                } else if row_idx == cy && col_idx == cx && (self.cursor_visible || !cursor_blinks) && self.tab.term.grid.cursor_visible_app && self.tab.paused_view.is_none() {
                     let cc = theme.cursor_color;
-                    painter.rect_filled(cell_rect, 2.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 200));
-                    painter.rect_stroke(cell_rect, 2.0, Stroke::new(1.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 100)));
+                    let cursor_rect = if cell.width == 2 { Rect::from_min_size(pos2(x, y), vec2(cw * scale_x * 2.0, ch)) } else { cell_rect };
+                    match cursor_shape {
+                        CursorShape::Block => {
+                            painter.rect_filled(cursor_rect, 2.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 200));
+                            painter.rect_stroke(cursor_rect, 2.0, Stroke::new(1.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 100)));
+                        }
+                        CursorShape::Underline => {
+                            let bar = Rect::from_min_max(pos2(cursor_rect.min.x, cursor_rect.max.y - 2.0), cursor_rect.max);
+                            painter.rect_filled(bar, 0.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 230));
+                        }
+                        CursorShape::Bar => {
+                            let bar = Rect::from_min_max(cursor_rect.min, pos2(cursor_rect.min.x + 2.0, cursor_rect.max.y));
+                            painter.rect_filled(bar, 0.0, Color32::from_rgba_unmultiplied(cc[0], cc[1], cc[2], 230));
+                        }
+                    }
                 }
 
                 if cell.width == 0 {
                     continue;
                 }
 
-                if cell.ch != ' ' && !cell.attrs.invisible {
-                    let mut job = text::LayoutJob::default();
-                    let mut fmt = TextFormat { font_id: font_id.clone(), color: fg, ..Default::default() };
-                    if cell.attrs.underline { fmt.underline     = Stroke::new(1.0, fg); }
-                    if cell.attrs.strikeout { fmt.strikethrough = Stroke::new(1.0, fg); }
-                    job.append(&cell.ch.to_string(), 0.0, fmt);
-                    let galley = ui.ctx().fonts(|f| f.layout_job(job));
-                    let y_off = ((ch - galley.size().y) * 0.5).max(0.0);
-                    painter.galley(pos2(x, y + y_off), galley, fg);
+                if cell.attrs.blink && cell.ch != ' ' { blink_seen = true; }
+                let hidden = cell.attrs.invisible || (cell.attrs.blink && blink_off);
+                if !hidden {
+                    let (fmt, fake_bold) = Self::cell_format(self.styled_fonts, &self.config.theme, cell.attrs, fg, bg_cell, font_id.size, is_link);
+                    if !is_link && !matches!(cell.attrs.underline, UnderlineStyle::None | UnderlineStyle::Single) {
+                        let color = cell.attrs.underline_color.map_or(fmt.color, |c| c.resolve(true, theme));
+                        paint_underline(&painter, x..=x + cw * scale_x * cell.width as f32, y + ch - 1.5, cell.attrs.underline, color);
+                    }
+                    glyphs.push((col_idx, cell.ch, cell.width, fmt, fake_bold));
+                }
+            }
+
+            let runs = ui.ctx().fonts(|f| {
+                let mut runs = Vec::new();
+                let mut i = 0;
+                while i < glyphs.len() {
+                    if glyphs[i].1 == ' ' { i += 1; continue; }
+                    let (start, _, _, ref fmt, fake_bold) = glyphs[i];
+                    let (mut text, mut next_col) = (String::new(), start);
+                    while let Some((col, c, w, gf, gb)) = glyphs.get(i) {
+                        let aligned = *c == ' ' || (f.glyph_width(&gf.font_id, *c) - cw * *w as f32).abs() < 0.5;
+                        if *col != next_col || gf != fmt || *gb != fake_bold || (!aligned && !text.is_empty()) { break; }
+                        text.push(*c);
+                        next_col = col + *w as usize;
+                        i += 1;
+                        if !aligned { break; }
+                    }
+                    let job = text::LayoutJob::single_section(text.trim_end().to_string(), fmt.clone());
+                    runs.push((start, f.layout_job(job), fmt.color, fake_bold));
+                }
+                runs
+            });
+            let y = text_rect.top() + row_idx as f32 * ch;
+            let row_clip = Rect::from_min_max(pos2(text_rect.left(), y), pos2(text_rect.right(), y + ch));
+            for (col, galley, color, fake_bold) in runs {
+                let x = text_rect.left() + col as f32 * cw * scale_x;
+                let y_off = ((ch - galley.size().y) * 0.5).max(0.0);
+                let (anchor, scale) = match line_size {
+                    LineSize::Single       => (pos2(x, y), Vec2::splat(1.0)),
+                    LineSize::DoubleWidth  => (pos2(x, y), vec2(2.0, 1.0)),
+                    LineSize::DoubleTop    => (pos2(x, y), Vec2::splat(2.0)),
+                    LineSize::DoubleBottom => (pos2(x, y - ch), Vec2::splat(2.0)),
+                };
+                if !line_size.is_double() {
+                    if fake_bold { painter.galley(pos2(x + 0.6, y + y_off), galley.clone(), color); }
+                    painter.galley(pos2(x, y + y_off), galley, color);
+                    continue;
                 }
+                let clipped = painter.with_clip_rect(row_clip);
+                if fake_bold { paint_scaled_galley(&clipped, galley.clone(), anchor + vec2(0.6, y_off), anchor, scale, color); }
+                paint_scaled_galley(&clipped, galley, anchor + vec2(0.0, y_off), anchor, scale, color);
+            }
+        }
+
+        if focused { self.blink_seen = blink_seen; } else { self.blink_seen |= blink_seen; }
+
+        if self.config.show_timestamps {
+            let stamp_font = FontId::new((theme.font_size * 0.85).max(8.0), FontFamily::Monospace);
+            for row_idx in 0..self.tab.term.grid.rows {
+                let Some(stamp) = self.display_stamp(row_idx) else { continue };
+                painter.text(
+                    pos2(text_rect.left() - self.timestamp_gutter_width(), text_rect.top() + (row_idx as f32 + 0.5) * ch),
+                    Align2::LEFT_CENTER,
+                    stamp.format("%H:%M:%S").to_string(),
+                    stamp_font.clone(),
+                    Color32::from_gray(105),
+                );
             }
         }
 
-        if self.terminal_has_focus && !self.input_buf.is_empty() {
-            let hint = format!("Typing: {}", self.input_buf);
+        if let Some(view) = &self.tab.paused_view {
+            let badge = Rect::from_min_size(pos2(rect.right() - 118.0, rect.top() + 8.0), vec2(110.0, 22.0));
+            painter.rect_filled(badge, 5.0, Color32::from_rgba_unmultiplied(150, 40, 40, 220));
+            painter.text(
+                badge.center(),
+                Align2::CENTER_CENTER,
+                format!("PAUSED {}s", view.since.elapsed().as_secs()),
+                FontId::new(12.0, FontFamily::Proportional),
+                Color32::WHITE,
+            );
+        } else if self.tab.term.grid.scroll_offset > 0 {
+            let badge = Rect::from_min_size(pos2(rect.right() - 138.0, rect.top() + 8.0), vec2(130.0, 22.0));
+            painter.rect_filled(badge, 5.0, Color32::from_rgba_unmultiplied(40, 52, 96, 220));
+            painter.rect_stroke(badge, 5.0, Stroke::new(1.0, Color32::from_rgba_unmultiplied(120, 160, 240, 180)));
+            painter.text(
+                badge.center(),
+                Align2::CENTER_CENTER,
+                format!("↑ {} lines up", self.tab.term.grid.scroll_offset),
+                FontId::new(12.0, FontFamily::Proportional),
+                Color32::from_rgb(190, 215, 255),
+            );
+        }
+
+        if let Some((_, chip)) = self.hovered_mark.filter(|_| focused) {
+            painter.rect_filled(chip, 4.0, Color32::from_rgba_unmultiplied(40, 52, 96, 220));
+            painter.rect_stroke(chip, 4.0, Stroke::new(1.0, Color32::from_rgba_unmultiplied(120, 160, 240, 180)));
+            painter.text(
+                chip.center(),
+                Align2::CENTER_CENTER,
+                format!("ask {}", self.config.mate_name),
+                FontId::new(11.0, FontFamily::Proportional),
+                Color32::from_rgb(190, 215, 255),
+            );
+        }
+
+        if focused && self.terminal_has_focus && !self.tab.input_buf.is_empty() {
+            let hint = format!("Typing: {}", self.tab.input_buf);
             painter.text(
                 rect.left_bottom() - vec2(0.0, 6.0),
                 Align2::LEFT_BOTTOM,
@@ -2835,32 +7971,71 @@ impl Spiltixal {
             .show(ui, |ui| {
                 ui.horizontal(|ui| {
                     ui.label(RichText::new("Search").color(Color32::from_rgb(130, 160, 230)).size(13.0));
+                    let query_id = Id::new("search_query");
+                    let mut recalled = false;
+                    if ui.memory(|m| m.has_focus(query_id)) {
+                        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::ArrowUp)) { recalled |= self.search.history_older(); }
+                        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::ArrowDown)) { recalled |= self.search.history_newer(); }
+                    }
                     let r = ui.add(
                         egui::TextEdit::singleline(&mut self.search.query)
+                            .id(query_id)
                             .desired_width(220.0)
-                            .hint_text("type to search...")
+                            .hint_text("type to search... (↑/↓ history)")
                     );
-                    if r.changed() { self.search.search(&self.term.grid.scrollback, &self.term.grid.cells); }
-                    let label = if self.search.matches.is_empty() { "no matches".into() }
-                                else { format!("{} / {}", self.search.current_idx + 1, self.search.matches.len()) };
-                    ui.label(RichText::new(label).color(Color32::from_gray(150)).size(11.0));
-                    if ui.small_button("Prev").clicked() { self.search.prev(); }
-                    if ui.small_button("Next").clicked() { self.search.next(); }
+                    if r.changed() { self.search.history_idx = None; }
+                    let case = ui.selectable_label(self.search.case_sensitive, RichText::new("Aa").size(11.0)).on_hover_text("Match case");
+                    if case.clicked() { self.search.case_sensitive = !self.search.case_sensitive; }
+                    let regex = ui.selectable_label(self.search.use_regex, RichText::new(".*").size(11.0)).on_hover_text("Regular expression");
+                    if regex.clicked() { self.search.use_regex = !self.search.use_regex; }
+                    if r.changed() || recalled || case.clicked() || regex.clicked() {
+                        self.search.search(&self.tab.term.grid);
+                        self.reveal_current_match();
+                    }
+                    if r.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                        self.search.history.record(&self.search.query);
+                        self.search.history_idx = None;
+                        if ui.input(|i| i.modifiers.shift) { self.search.prev(); } else { self.search.next(); }
+                        self.reveal_current_match();
+                        r.request_focus();
+                    }
+                    if let Some(err) = &self.search.error {
+                        ui.label(RichText::new(err).color(Color32::from_rgb(230, 110, 110)).size(11.0));
+                    } else {
+                        let label = if self.search.matches.is_empty() { "no matches".into() }
+                                    else { format!("{} / {}", self.search.current_idx + 1, self.search.matches.len()) };
+                        ui.label(RichText::new(label).color(Color32::from_gray(150)).size(11.0));
+                    }
+                    if self.search.recently_wrapped() {
+                        ui.label(RichText::new("wrapped").color(Color32::from_rgb(255, 200, 0)).size(11.0));
+                    }
+                    if ui.small_button("Prev").clicked() { self.search.prev(); self.reveal_current_match(); }
+                    if ui.small_button("Next").clicked() { self.search.next(); self.reveal_current_match(); }
+                    let all = !self.search.current_only;
+                    if ui.selectable_label(all, RichText::new("all").size(11.0)).on_hover_text("Highlight every match").clicked() {
+                        self.search.current_only = all;
+                    }
                     if ui.small_button("X").clicked() {
                         self.search_open = false;
-                        self.search.query.clear();
-                        self.search.matches.clear();
+                        self.search.close();
                     }
                 });
             });
     }
 
-    fn draw_floating_bob(&mut self, ctx: &Context) {
-        let emotion  = self.mate.emotion;
-        let is_open  = self.mate_open_target;
-        let anim     = self.mate_open_anim;
+    fn reveal_current_match(&mut self) {
+        if self.tab.term.alt_screen_active() { return; }
+        let Some(m) = self.search.current_match() else { return };
+        let grid = &mut self.tab.term.grid;
+        let total = grid.scrollback.len() + grid.rows;
+        let view_start = total.saturating_sub(grid.rows + grid.scroll_offset);
+        if m.row >= view_start && m.row < view_start + grid.rows { return; }
+        let wanted_start = m.row.saturating_sub(grid.rows / 2);
+        grid.scroll_offset = total.saturating_sub(grid.rows + wanted_start).min(grid.scrollback.len());
+    }
 
-        let dot_color = match emotion {
+    fn bob_dot_color(&self) -> Color32 {
+        match self.mate.emotion {
             Emotion::Happy    => Color32::from_rgb(100, 220, 120),
             Emotion::Neutral  => Color32::from_rgb(200, 190, 100),
             Emotion::Thinking => Color32::from_rgb(100, 160, 255),
@@ -2868,11 +8043,130 @@ impl Spiltixal {
             Emotion::Worried  => Color32::from_rgb(255, 130, 80),
             Emotion::Excited  => Color32::from_rgb(255, 220, 60),
             Emotion::Confused => Color32::from_rgb(180, 180, 180),
+        }
+    }
+
+    fn is_bob_docked(&self) -> bool {
+        self.config.bob_dock != BobDock::Floating && self.mate_open_target
+    }
+
+    fn draw_bob_header(&mut self, ui: &mut Ui) {
+        let is_open   = self.mate_open_target;
+        let dot_color = self.bob_dot_color();
+        ui.horizontal(|ui| {
+            let pulse = (self.anim_t * 3.0).sin() * 0.15 + 0.85;
+            let (r, _) = ui.allocate_exact_size(Vec2::splat(12.0), Sense::hover());
+            ui.painter().circle_filled(r.center(), 6.0, dot_color.linear_multiply(pulse));
+            ui.label(RichText::new(&self.config.mate_name).strong().size(14.0).color(Color32::from_rgb(140, 200, 255)));
+            ui.add_space(8.0);
+            let (ai_label, ai_color) = if self.config.ai_enabled {
+                ("AI:ON",  Color32::from_rgb(90, 210, 120))
+            } else {
+                ("AI:OFF", Color32::from_rgb(210, 80, 80))
+            };
+            if ui.add_sized(
+                vec2(78.0, 24.0),
+                egui::Button::new(RichText::new(ai_label).color(ai_color).size(11.0))
+            ).clicked() {
+                if self.config.ai_enabled { self.disable_ai(); } else { self.ai_enable_prompt_open = true; }
+            }
+            if is_open {
+                let docked = self.config.bob_dock != BobDock::Floating;
+                let pin = ui.add_sized(vec2(44.0, 24.0), egui::Button::new(if docked { "float" } else { "dock" }));
+                if pin.on_hover_text("Switch between a floating overlay and a docked side panel").clicked() {
+                    self.config.bob_dock = if docked { BobDock::Floating } else { BobDock::Right };
+                    self.config.save();
+                }
+            }
+            let toggle_label = if is_open { "X" } else { "+" };
+            if ui.add_sized(vec2(30.0, 24.0), egui::Button::new(toggle_label)).clicked() {
+                self.set_mate_open(!is_open);
+            }
+        });
+    }
+
+    fn draw_bob_body(&mut self, ui: &mut Ui, ctx: &Context, bob_w: f32, full_h: f32) {
+        let texture_id = self.mate_texture(ctx, self.mate.emotion);
+
+        ui.horizontal(|ui| {
+            if let Some(tid) = texture_id {
+                let side = (bob_w * 0.30).clamp(70.0, 110.0 * full_h / default_bob_height());
+                let (resp, painter) = ui.allocate_painter(vec2(side, side), Sense::hover());
+                painter.image(tid, resp.rect, Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0)), Color32::WHITE);
+            }
+            ui.vertical(|ui| {
+                egui::Frame::none()
+                    .fill(Color32::from_rgba_unmultiplied(24, 28, 52, 230))
+                    .rounding(Rounding { nw: 2.0, ne: 10.0, sw: 10.0, se: 10.0 })
+                    .inner_margin(Margin::symmetric(8.0, 6.0))
+                    .show(ui, |ui| {
+                        ui.set_max_width((bob_w * 0.60).max(150.0));
+                        let typed = self.mate.typed_text().to_string();
+                        let display = if self.mate.is_typing() {
+                            format!("{typed}▍")
+                        } else {
+                            typed
+                        };
+                        ui.label(RichText::new(display).color(Color32::from_gray(225)).size(11.5));
+                    });
+            });
+        });
+
+        ui.add_space(4.0);
+
+        ui.horizontal(|ui| {
+            let chat = self.mate.view == MateView::Chat;
+            if ui.selectable_label(chat,  "Chat").clicked()  { self.mate.view = MateView::Chat; }
+            if ui.selectable_label(!chat, "Saved").clicked() { self.mate.view = MateView::SavedCommands; }
+        });
+        ui.add_space(4.0);
+
+        match self.mate.view {
+            MateView::Chat          => self.draw_bob_chat(ui, ctx, full_h),
+            MateView::SavedCommands => self.draw_saved_commands(ui, full_h),
+        }
+    }
+
+    fn draw_docked_bob(&mut self, ctx: &Context) {
+        let panel = match self.config.bob_dock {
+            BobDock::Left => egui::SidePanel::left("bob_dock"),
+            _             => egui::SidePanel::right("bob_dock"),
         };
+        let resp = panel
+            .resizable(true)
+            .default_width(self.config.bob_width.clamp(340.0, 1200.0))
+            .width_range(340.0..=1200.0)
+            .frame(
+                egui::Frame::none()
+                    .fill(Color32::from_rgba_unmultiplied(10, 12, 24, 235))
+                    .stroke(Stroke::new(1.0, Color32::from_rgba_unmultiplied(85, 110, 210, 160)))
+                    .inner_margin(Margin::symmetric(14.0, 12.0))
+            )
+            .show(ctx, |ui| {
+                self.draw_bob_header(ui);
+                ui.add_space(4.0);
+                let width  = ui.available_width();
+                let height = ui.available_height() + 40.0;
+                self.draw_bob_body(ui, ctx, width, height);
+            });
+        let rect = resp.response.rect;
+        self.mate_rect = Some(rect);
+        if (rect.width() - self.config.bob_width).abs() > 0.5 && !ctx.input(|i| i.pointer.any_down()) {
+            self.config.bob_width = rect.width();
+            self.config.save();
+        }
+    }
+
+    fn draw_floating_bob(&mut self, ctx: &Context) {
+        let anim = if self.config.bob_dock == BobDock::Floating { self.mate_open_anim } else { 0.0 };
 
-        let bob_w = 430.0 * anim;
+        let full_w = self.config.bob_width.clamp(340.0, 1200.0);
+        let full_h = self.config.bob_height.clamp(360.0, 1400.0);
+        let bob_w = full_w * anim;
+        let mut resize_delta = Vec2::ZERO;
+        let mut resize_done = false;
 
-        egui::Area::new("bob_float".into())
+        let area = egui::Area::new("bob_float".into())
             .anchor(Align2::RIGHT_TOP, vec2(-10.0, 42.0))
             .order(egui::Order::Foreground)
             .show(ctx, |ui| {
@@ -2882,84 +8176,49 @@ impl Spiltixal {
                     .stroke(Stroke::new(1.8, Color32::from_rgba_unmultiplied(85, 110, 210, 220)))
                     .inner_margin(Margin::symmetric(14.0, 12.0))
                     .show(ui, |ui| {
-                        ui.horizontal(|ui| {
-                            let pulse = (self.anim_t * 3.0).sin() * 0.15 + 0.85;
-                            let (r, _) = ui.allocate_exact_size(Vec2::splat(12.0), Sense::hover());
-                            ui.painter().circle_filled(r.center(), 6.0, dot_color.linear_multiply(pulse));
-                            ui.label(RichText::new(&self.config.mate_name).strong().size(14.0).color(Color32::from_rgb(140, 200, 255)));
-                            ui.add_space(8.0);
-                            let (ai_label, ai_color) = if self.config.ai_enabled {
-                                ("AI:ON",  Color32::from_rgb(90, 210, 120))
-                            } else {
-                                ("AI:OFF", Color32::from_rgb(210, 80, 80))
-                            };
-                            if ui.add_sized(
-                                vec2(78.0, 24.0),
-                                egui::Button::new(RichText::new(ai_label).color(ai_color).size(11.0))
-                            ).clicked() {
-                                if self.config.ai_enabled { self.disable_ai(); } else { self.ai_enable_prompt_open = true; }
-                            }
-                            let toggle_label = if is_open { "X" } else { "+" };
-                            if ui.add_sized(vec2(30.0, 24.0), egui::Button::new(toggle_label)).clicked() {
-                                self.set_mate_open(!is_open);
-                            }
-                        });
+                        self.draw_bob_header(ui);
 
                         if anim > 0.1 {
                             ui.add_space(4.0);
                             ui.set_min_width(bob_w.max(340.0));
                             ui.set_max_width(bob_w.max(340.0));
 
-                            let texture_id = self.mate_texture(ctx, emotion);
-
-                            ui.horizontal(|ui| {
-                                if let Some(tid) = texture_id {
-                                    let side = (bob_w * 0.30).clamp(70.0, 110.0);
-                                    let (resp, painter) = ui.allocate_painter(vec2(side, side), Sense::hover());
-                                    painter.image(tid, resp.rect, Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0)), Color32::WHITE);
-                                }
-                                ui.vertical(|ui| {
-                                    egui::Frame::none()
-                                        .fill(Color32::from_rgba_unmultiplied(24, 28, 52, 230))
-                                        .rounding(Rounding { nw: 2.0, ne: 10.0, sw: 10.0, se: 10.0 })
-                                        .inner_margin(Margin::symmetric(8.0, 6.0))
-                                        .show(ui, |ui| {
-                                            ui.set_max_width((bob_w * 0.60).max(150.0));
-                                            let typed = self.mate.typed_text().to_string();
-                                            let display = if self.mate.is_typing() {
-                                                format!("{typed}▍")
-                                            } else {
-                                                typed
-                                            };
-                                            ui.label(RichText::new(display).color(Color32::from_gray(225)).size(11.5));
-                                        });
-                                });
-                            });
-
-                            ui.add_space(4.0);
-
-                            ui.horizontal(|ui| {
-                                let chat = self.mate.view == MateView::Chat;
-                                if ui.selectable_label(chat,  "Chat").clicked()  { self.mate.view = MateView::Chat; }
-                                if ui.selectable_label(!chat, "Saved").clicked() { self.mate.view = MateView::SavedCommands; }
-                            });
-                            ui.add_space(4.0);
+                            self.draw_bob_body(ui, ctx, bob_w, full_h);
 
-                            match self.mate.view {
-                                MateView::Chat          => self.draw_bob_chat(ui, ctx, bob_w),
-                                MateView::SavedCommands => self.draw_saved_commands(ui),
+                            let (grip_rect, grip) = ui.allocate_exact_size(Vec2::splat(14.0), Sense::drag());
+                            let grip_color = if grip.hovered() || grip.dragged() {
+                                Color32::from_rgb(140, 170, 240)
+                            } else {
+                                Color32::from_rgba_unmultiplied(110, 130, 200, 160)
+                            };
+                            for i in 1..=3 {
+                                let o = i as f32 * 4.0;
+                                ui.painter().line_segment(
+                                    [pos2(grip_rect.left(), grip_rect.bottom() - o), pos2(grip_rect.left() + o, grip_rect.bottom())],
+                                    Stroke::new(1.2, grip_color),
+                                );
                             }
+                            if grip.hovered() || grip.dragged() { ctx.set_cursor_icon(CursorIcon::ResizeNeSw); }
+                            if grip.dragged() { resize_delta = grip.drag_delta(); }
+                            if grip.drag_stopped() { resize_done = true; }
                         }
                     });
             });
+        self.mate_rect = Some(area.response.rect);
+
+        if resize_delta != Vec2::ZERO {
+            self.config.bob_width  = (full_w - resize_delta.x).clamp(340.0, 1200.0);
+            self.config.bob_height = (full_h + resize_delta.y).clamp(360.0, 1400.0);
+        }
+        if resize_done { self.config.save(); }
     }
 
-    fn draw_bob_chat(&mut self, ui: &mut Ui, _ctx: &Context, _panel_w: f32) {
+    fn draw_bob_chat(&mut self, ui: &mut Ui, _ctx: &Context, panel_h: f32) {
         let mut any_focused = false;
 
         egui::ScrollArea::vertical()
             .id_source("bob_chat_hist")
-            .max_height(120.0)
+            .max_height((panel_h - 400.0).max(80.0))
             .stick_to_bottom(true)
             .auto_shrink([false, true])
             .show(ui, |ui| {
@@ -2992,6 +8251,16 @@ impl Spiltixal {
         ui.separator();
         ui.add_space(2.0);
 
+        let mut clear_context = false;
+        if let Some(pending) = &self.mate.pending_context {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(format!("context: {}", pending.label)).size(10.5).color(Color32::from_rgb(150, 190, 255)))
+                    .on_hover_text(&pending.body);
+                if ui.small_button("x").clicked() { clear_context = true; }
+            });
+        }
+        if clear_context { self.mate.pending_context = None; }
+
         let text_resp = ui.add(
             egui::TextEdit::multiline(&mut self.mate.input_text)
                 .desired_rows(2)
@@ -2999,9 +8268,28 @@ impl Spiltixal {
                 .hint_text("message Bob... or type \"customize\"")
                 .font(FontId::proportional(11.5))
         );
+        if self.mate.focus_input {
+            text_resp.request_focus();
+            self.terminal_has_focus = false;
+            self.mate.focus_input = false;
+            any_focused = true;
+        }
         if text_resp.has_focus() { any_focused = true; }
+        if text_resp.lost_focus() && ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Escape)) {
+            self.terminal_has_focus = true;
+        }
 
-        let send_clicked = ui.button("send").clicked();
+        let send_clicked = ui.horizontal(|ui| {
+            let send = ui.button("send").clicked();
+            if self.mate.reply_pending && ui.small_button("cancel").on_hover_text("Stop waiting for the reply").clicked() {
+                self.mate.abandon_reply("Request cancelled.");
+            }
+            if !self.mate.reply_pending && !self.mate.chat_history.is_empty()
+                && ui.small_button("clear conversation").on_hover_text("Forget this chat, on disk too").clicked() {
+                self.mate.clear_conversation();
+            }
+            send
+        }).inner;
         let enter_pressed = text_resp.has_focus() && ui.input(|i| i.key_pressed(Key::Enter) && !i.modifiers.shift);
 
         if send_clicked || enter_pressed {
@@ -3020,7 +8308,9 @@ impl Spiltixal {
                         let is_video = matches!(ext.as_str(), "mp4" | "mkv" | "avi" | "mov" | "webm");
                         if is_image || is_video {
                             full_msg.push_str(&format!("\n\n[attached {} file: {}]", if is_video { "video" } else { "image" }, attach));
-                            if is_video {
+                            if is_image && self.config.ai_vision {
+                                self.mate.pending_images = vec![path.to_path_buf()];
+                            } else if is_video {
                                 full_msg.push_str("\n(video attached - analyze based on filename, extension, and any metadata you can infer)");
                             } else {
                                 full_msg.push_str("\n(image attached - describe what you know about this file type and what the user might want)");
@@ -3047,12 +8337,21 @@ impl Spiltixal {
                 }
 
                 if let Some(cmd) = msg.strip_prefix("/run ").map(str::trim).filter(|c| !c.is_empty()) {
-                    self.execute_command(cmd.to_string());
-                    let ran = format!("ran: {cmd}");
+                    let ran = if self.execute_command(cmd.to_string()) { format!("ran: {cmd}") }
+                              else { format!("waiting for confirmation: {cmd}") };
                     self.mate.last_message = ran.clone();
                     self.mate.typing_target = ran;
                     self.mate.typing_chars = 0;
                     self.mate.typing_tick = Instant::now();
+                } else if let Some(dir) = msg.strip_prefix("/cd ").map(str::trim).filter(|d| !d.is_empty()) {
+                    let out = match self.change_directory(dir) {
+                        Ok(path) => format!("now in {}", path.display()),
+                        Err(e) => format!("cd failed: {e}"),
+                    };
+                    self.mate.last_message = out.clone();
+                    self.mate.typing_target = out;
+                    self.mate.typing_chars = 0;
+                    self.mate.typing_tick = Instant::now();
                 } else if let Some(ctrl) = msg.strip_prefix("/ctrl ").map(str::trim) {
                     let normalized = ctrl.to_ascii_lowercase();
                     let out = match normalized.as_str() {
@@ -3101,10 +8400,76 @@ impl Spiltixal {
                     self.mate.typing_target = out;
                     self.mate.typing_chars = 0;
                     self.mate.typing_tick = Instant::now();
+                } else if msg == "/export" || msg.starts_with("/export ") {
+                    let args = msg["/export".len()..].trim();
+                    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                    let (format, path) = match first.to_ascii_lowercase().as_str() {
+                        "text" | "txt" => (ExportFormat::Text, rest.trim()),
+                        "html" => (ExportFormat::Html, rest.trim()),
+                        _ if args.ends_with(".html") || args.ends_with(".htm") => (ExportFormat::Html, args),
+                        _ => (ExportFormat::Text, args),
+                    };
+                    let out = match self.export_scrollback(format, path) {
+                        Ok(path) => format!("exported the scrollback to {}", path.display()),
+                        Err(e) => format!("export failed: {e:#}"),
+                    };
+                    self.mate.last_message = out.clone();
+                    self.mate.typing_target = out;
+                    self.mate.typing_chars = 0;
+                    self.mate.typing_tick = Instant::now();
+                } else if msg == "/log" || msg.starts_with("/log ") {
+                    let out = match msg["/log".len()..].trim() {
+                        "on" => match self.start_output_log() {
+                            Ok(path) => format!("logging output to {}", path.display()),
+                            Err(e) => format!("couldn't start logging: {e:#}"),
+                        },
+                        "off" => match self.output_log.take() {
+                            Some(log) => format!("stopped logging to {}", log.path.display()),
+                            None => "output logging is already off.".to_string(),
+                        },
+                        "" => match &self.output_log {
+                            Some(log) => format!("logging output to {}. use /log off to stop.", log.path.display()),
+                            None => "output logging is off. use /log on to start.".to_string(),
+                        },
+                        _ => "usage: /log on, /log off".to_string(),
+                    };
+                    self.mate.last_message = out.clone();
+                    self.mate.typing_target = out;
+                    self.mate.typing_chars = 0;
+                    self.mate.typing_tick = Instant::now();
+                } else if msg == "/record" || msg.starts_with("/record ") {
+                    self.toggle_recording(&msg["/record".len()..]);
+                } else if msg == "/clear" {
+                    self.clear_scrollback();
+                    self.mate.last_message = "cleared the screen and scrollback.".into();
+                    self.mate.typing_target = self.mate.last_message.clone();
+                    self.mate.typing_chars = 0;
+                    self.mate.typing_tick = Instant::now();
+                } else if msg == "/summarize" {
+                    let (text, truncated) = self.scrollback_text(MAX_SUMMARY_CHARS);
+                    let note = if truncated { " Older output was cut off to fit." } else { "" };
+                    self.mate.send_message(format!(
+                        "Summarize what happened in this terminal session: the commands run, their outcomes, and anything that still needs attention.{note}\n\n[scrollback]\n{text}"
+                    ));
+                } else if msg == "/explain" {
+                    match self.last_error_context() {
+                        Some((label, body)) => self.mate.send_message(format!(
+                            "Explain why this failed ({label}) and suggest how to fix it. Keep it short and concrete.\n\n[command output]\n{body}"
+                        )),
+                        None => {
+                            self.mate.last_message = "no failed command or error output to explain.".into();
+                            self.mate.typing_target = self.mate.last_message.clone();
+                            self.mate.typing_chars = 0;
+                            self.mate.typing_tick = Instant::now();
+                        }
+                    }
                 } else {
                     let is_customize = msg.trim().eq_ignore_ascii_case("customize");
                     let term = self.terminal_context();
-                    if !term.is_empty() {
+                    if let Some(pending) = self.mate.pending_context.take() {
+                        full_msg.push_str("\n\n[command output]\n");
+                        full_msg.push_str(&pending.body);
+                    } else if !term.is_empty() {
                         full_msg.push_str("\n\n[terminal context]\n");
                         full_msg.push_str(&term);
                     }
@@ -3134,28 +8499,10 @@ impl Spiltixal {
         if r1.has_focus() || r2.has_focus() { any_focused = true; }
         if ui.button("save").clicked() { self.mate.save_command(); }
 
-        self.mate_input_focused = any_focused;
         if any_focused { self.terminal_has_focus = false; }
     }
 
-    fn draw_mate_panel(&mut self, ui: &mut Ui, ctx: &Context, panel_w: f32) {
-        let emotion    = self.mate.emotion;
-        let texture_id = self.mate_texture(ctx, emotion);
-
-        egui::Frame::none()
-            .fill(Color32::from_rgba_unmultiplied(13, 13, 22, 248))
-            .rounding(Rounding::same(12.0))
-            .stroke(Stroke::new(1.5, Color32::from_rgba_unmultiplied(65, 85, 155, 210)))
-            .inner_margin(Margin::symmetric(10.0, 10.0))
-            .show(ui, |ui| {
-                ui.set_min_width(panel_w.max(280.0));
-                ui.set_max_width(panel_w);
-                self.draw_bob_chat(ui, ctx, panel_w);
-                let _ = texture_id;
-            });
-    }
-
-    fn draw_saved_commands(&mut self, ui: &mut Ui) {
+    fn draw_saved_commands(&mut self, ui: &mut Ui, panel_h: f32) {
         let mut filter = String::new();
         let fr = ui.add(
             egui::TextEdit::singleline(&mut filter)
@@ -3163,13 +8510,13 @@ impl Spiltixal {
                 .hint_text("filter commands...")
                 .font(FontId::proportional(12.0))
         );
-        if fr.has_focus() { self.mate_input_focused = true; }
+        if fr.has_focus() { self.terminal_has_focus = false; }
 
         let cmds: Vec<_> = self.mate.commands.search(&filter)
-            .iter().map(|c| (c.id, c.command.clone(), c.description.clone())).collect();
+            .iter().map(|c| (c.id, c.command.clone(), c.description.clone(), c.hotkey)).collect();
 
-        egui::ScrollArea::vertical().max_height(340.0).show(ui, |ui| {
-            for (id, cmd, desc) in &cmds {
+        egui::ScrollArea::vertical().max_height((panel_h - 180.0).max(120.0)).show(ui, |ui| {
+            for (id, cmd, desc, hotkey) in &cmds {
                 egui::Frame::none()
                     .fill(Color32::from_rgba_unmultiplied(20, 26, 46, 220))
                     .rounding(6.0)
@@ -3177,15 +8524,43 @@ impl Spiltixal {
                     .inner_margin(Margin::symmetric(8.0, 5.0))
                     .show(ui, |ui| {
                         ui.horizontal(|ui| {
-                            if ui.small_button("Run").clicked() {
-                                let c = cmd.clone();
-                                self.mate.commands.increment_use(*id);
-                                self.execute_command(c);
-                            }
+                            if ui.small_button("Run").clicked() { self.run_saved_command(*id); }
                             if ui.small_button("Del").clicked() { self.mate.delete_saved(*id); }
+                            if ui.small_button("Edit").clicked() { self.mate.edit_saved(*id); }
+                            let key_label = hotkey.map(|k| format!("Alt+{k}")).unwrap_or_else(|| "key".into());
+                            egui::ComboBox::from_id_source(("saved_hotkey", *id))
+                                .width(52.0)
+                                .selected_text(RichText::new(key_label).size(10.0))
+                                .show_ui(ui, |ui| {
+                                    if ui.selectable_label(hotkey.is_none(), "none").clicked() { self.mate.commands.set_hotkey(*id, None); }
+                                    for k in "1234567890".chars() {
+                                        if ui.selectable_label(*hotkey == Some(k), format!("Alt+{k}")).clicked() {
+                                            self.mate.commands.set_hotkey(*id, Some(k));
+                                        }
+                                    }
+                                });
                             ui.label(RichText::new(cmd).code().color(Color32::from_rgb(165, 220, 125)).size(11.0));
                         });
-                        if !desc.is_empty() {
+                        if let Some(edit) = self.mate.editing_saved.as_mut().filter(|e| e.id == *id) {
+                            let r1 = ui.add(
+                                egui::TextEdit::singleline(&mut edit.command)
+                                    .desired_width(f32::INFINITY)
+                                    .hint_text("command...")
+                                    .font(FontId::proportional(11.0))
+                            );
+                            let r2 = ui.add(
+                                egui::TextEdit::singleline(&mut edit.description)
+                                    .desired_width(f32::INFINITY)
+                                    .hint_text("description (optional)")
+                                    .font(FontId::proportional(11.0))
+                            );
+                            if r1.has_focus() || r2.has_focus() { self.terminal_has_focus = false; }
+                            let submitted = (r1.lost_focus() || r2.lost_focus()) && ui.input(|i| i.key_pressed(Key::Enter));
+                            ui.horizontal(|ui| {
+                                if ui.small_button("Save").clicked() || submitted { self.mate.finish_edit(); }
+                                if ui.small_button("Cancel").clicked() { self.mate.editing_saved = None; }
+                            });
+                        } else if !desc.is_empty() {
                             ui.label(RichText::new(desc).color(Color32::from_gray(140)).size(10.0));
                         }
                     });
@@ -3194,7 +8569,7 @@ impl Spiltixal {
         });
     }
 
-    fn draw_title_bar(&self, ui: &mut Ui, t: f32) {
+    fn draw_title_bar(&mut self, ui: &mut Ui, t: f32) {
         let accent = if self.is_theme_one() {
             Color32::from_rgb(200, 145, 255)
         } else {
@@ -3226,13 +8601,62 @@ impl Spiltixal {
                     }
 
                     ui.add_space(8.0);
-                    ui.label(RichText::new(&self.term.title).color(Color32::from_gray(195)).size(13.0));
+                    if self.tab_count() > 1 {
+                        let labels = self.tabs_in_order().map(TerminalTab::label).collect::<Vec<_>>();
+                        let mut clicked = None;
+                        for (i, label) in labels.into_iter().enumerate() {
+                            let active = i == self.active_tab;
+                            let color = if active { Color32::from_gray(235) } else { Color32::from_gray(145) };
+                            let resp = ui.selectable_label(active, RichText::new(label).color(color).size(12.0));
+                            if resp.clicked() { clicked = Some(i); }
+                        }
+                        if let Some(i) = clicked { self.switch_tab(i); }
+                    } else {
+                        ui.label(RichText::new(&self.tab.term.title).color(Color32::from_gray(195)).size(13.0));
+                    }
+                    let new_tab = ui.add(egui::Button::new(RichText::new("+").color(Color32::from_gray(175)).size(13.0)).frame(false));
+                    if new_tab.on_hover_text("New tab (Ctrl+Shift+T)").clicked() { self.new_tab(); }
+                    if let Some(dir) = &self.tab.term.cwd {
+                        let shown = match dirs::home_dir().and_then(|h| dir.strip_prefix(h).ok().map(Path::to_path_buf)) {
+                            Some(rel) if rel.as_os_str().is_empty() => "~".to_string(),
+                            Some(rel) => format!("~/{}", rel.display()),
+                            None => dir.display().to_string(),
+                        };
+                        ui.label(RichText::new(shown).color(Color32::from_gray(130)).size(11.0)).on_hover_text(dir.display().to_string());
+                    }
+                    if let Some(code) = self.tab.term.last_exit_code {
+                        let color = if code == 0 { Color32::from_rgb(90, 210, 120) } else { Color32::from_rgb(230, 85, 85) };
+                        let (rect, resp) = ui.allocate_exact_size(Vec2::splat(10.0), Sense::hover());
+                        ui.painter().circle_filled(rect.center(), 3.5, color);
+                        resp.on_hover_text(format!("last exit code: {code}"));
+                    }
 
                     ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                         ui.label(RichText::new("Spiltixal").color(accent).size(12.0).strong());
                         ui.add_space(6.0);
                         ui.label(RichText::new(APP_VERSION).color(Color32::from_gray(160)).size(10.0));
                         ui.add_space(8.0);
+                        let fixed_config = CONFIG_PATH_OVERRIDE.get().is_some();
+                        let profile_btn = ui.add_enabled(
+                            !fixed_config,
+                            egui::Button::new(RichText::new(format!("profile: {}", active_profile())).color(Color32::from_gray(175)).size(10.0)).frame(false),
+                        );
+                        if fixed_config {
+                            profile_btn.on_disabled_hover_text("Profiles are disabled while --config is set");
+                        } else if profile_btn.clicked() {
+                            self.profile_window_open = !self.profile_window_open;
+                        }
+                        ui.add_space(6.0);
+                        let env_btn = ui.add(egui::Button::new(RichText::new("env").color(Color32::from_gray(175)).size(10.0)).frame(false));
+                        if env_btn.on_hover_text("Inspect the shell environment").clicked() {
+                            self.env_window_open = !self.env_window_open;
+                            self.env_snapshot = None;
+                        }
+                        if self.broadcast_input {
+                            ui.add_space(6.0);
+                            let stop = ui.add(egui::Button::new(RichText::new("BROADCAST").color(Color32::from_rgb(255, 90, 90)).size(10.0).strong()).frame(false));
+                            if stop.on_hover_text("Input is sent to every session. Click to stop.").clicked() { self.broadcast_input = false; }
+                        }
                         if self.nerd_font_loaded {
                             ui.add_space(6.0);
                             ui.label(RichText::new("NF").color(Color32::from_rgb(80, 170, 80)).size(10.0));
@@ -3248,6 +8672,16 @@ impl Spiltixal {
 }
 
 impl eframe::App for Spiltixal {
+    fn raw_input_hook(&mut self, ctx: &Context, raw_input: &mut egui::RawInput) {
+        self.mate_input_focused = ctx.memory(|m| m.focused().is_some());
+        self.term_modifiers = raw_input.modifiers;
+        self.term_events.clear();
+        if self.mate_input_focused || self.close_confirm.is_some() || self.danger_prompt.is_some() { return; }
+        let (term, rest): (Vec<Event>, Vec<Event>) = raw_input.events.drain(..).partition(Self::is_terminal_event);
+        raw_input.events = rest;
+        self.term_events = term;
+    }
+
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         let high_motion = self.is_theme_one() || self.mate.is_typing() || (self.mate_open_anim > 0.0 && self.mate_open_anim < 1.0);
         ctx.request_repaint_after(if high_motion { Duration::from_millis(33) } else { Duration::from_millis(90) });
@@ -3262,19 +8696,48 @@ impl eframe::App for Spiltixal {
             }
         }
 
+        self.animate_dropdown(ctx);
+        self.animate_unfocus_dim(ctx);
+        let show_timestamps = self.config.show_timestamps;
+        self.tab.for_each_pane(|pane| pane.term.grid.stamp_lines = show_timestamps);
         self.poll_pty();
-        if let Some(pty) = &mut self.pty {
+        self.sync_window_title(ctx);
+        if let Some(pty) = &mut self.tab.pty {
             if !pty.is_alive() {
-                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                return;
+                let code = pty.exit_code();
+                if self.tab.recorder.is_some() { self.stop_recording(); }
+                match self.config.on_shell_exit {
+                    ShellExitAction::Close if self.tab.split.is_some() || !self.inactive_tabs.is_empty() => self.close_tab(),
+                    ShellExitAction::Close => {
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        return;
+                    }
+                    ShellExitAction::Restart if self.tab.shell_started.elapsed() >= Duration::from_secs(1) => self.restart_shell(code),
+                    ShellExitAction::Restart | ShellExitAction::Hold => self.tab.hold(code),
+                }
+            }
+        }
+        self.reap_exited_shells();
+        if std::mem::take(&mut self.close_requested) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+        if ctx.input(|i| i.viewport().close_requested()) && self.config.confirm_close && !self.close_confirmed {
+            let jobs = self.running_jobs();
+            if !jobs.is_empty() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.close_confirm = Some((CloseScope::Window, jobs));
             }
         }
+        self.mate.typing_interval = self.config.typing_interval();
         self.mate.poll_ai();
         self.mate.tick_typing();
         self.update_cursor_blink();
         self.animate_mate_panel();
         self.enforce_ai_ram_limit();
         self.ensure_background_texture(ctx);
+        self.watch_config_file();
+        self.sync_fonts(ctx);
         if self.last_metrics_update.elapsed() >= Duration::from_millis(220) {
             self.update_cell_metrics(ctx);
             self.last_metrics_update = Instant::now();
@@ -3283,15 +8746,18 @@ impl eframe::App for Spiltixal {
         self.anim_t = ctx.input(|i| i.time) as f32;
 
         let bg = if is_hyprland() {
-            self.config.theme.bg_alpha((self.config.opacity * 255.0) as u8)
+            self.config.theme.bg_alpha((self.window_opacity() * 255.0) as u8)
         } else {
             self.config.theme.bg()
         };
 
+        if self.draw_close_confirm(ctx) { return; }
         if self.draw_danger_prompt(ctx) { return; }
         if self.draw_first_launch_prompt(ctx) { return; }
         if self.draw_ai_enable_prompt(ctx) { return; }
 
+        if self.is_bob_docked() { self.draw_docked_bob(ctx); }
+
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(Color32::TRANSPARENT))
             .show(ctx, |ui| {
@@ -3302,43 +8768,555 @@ impl eframe::App for Spiltixal {
                 } else {
                     ui.painter().rect_stroke(full_rect, 0.0, Stroke::new(1.0, Color32::from_rgba_unmultiplied(70, 95, 170, 70)));
                 }
+                if self.broadcast_input {
+                    ui.painter().rect_stroke(full_rect.shrink(1.0), 0.0, Stroke::new(2.0, Color32::from_rgb(230, 60, 60)));
+                }
 
                 ui.vertical(|ui| {
                     self.draw_title_bar(ui, self.anim_t);
 
                     self.draw_search_bar(ui);
-                    let term_rect = ui.available_rect_before_wrap();
+                    let full_term_rect = ui.available_rect_before_wrap();
+                    if let (_, Some(other)) = self.pane_rects(full_term_rect) {
+                        let activate = ctx.input(|i| {
+                            i.pointer.hover_pos().is_some_and(|p| other.contains(p))
+                                && (i.pointer.any_pressed() || i.smooth_scroll_delta.y.abs() > f32::EPSILON)
+                        });
+                        if activate {
+                            self.focus_other_pane();
+                            self.terminal_has_focus = true;
+                        }
+                    }
+                    let (term_rect, other_pane_rect) = self.pane_rects(full_term_rect);
                     self.terminal_rect = Some(term_rect);
                     self.sync_terminal_size(term_rect);
+                    self.report_mouse(ctx, ui.layer_id(), term_rect);
                     self.handle_terminal_scroll(ctx);
-                    self.draw_terminal(ui, term_rect);
-                    let term_resp = ui.allocate_rect(term_rect, Sense::click());
-                    if term_resp.clicked() { self.terminal_has_focus = true; }
+                    self.update_hovered_link(ctx, term_rect);
+                    self.update_hovered_mark(ctx, term_rect);
+                    self.draw_terminal(ui, term_rect, true);
+                    if let Some(other) = other_pane_rect {
+                        self.draw_split_pane(ui, other);
+                        let accent = Color32::from_rgba_unmultiplied(self.config.theme.cursor_color[0], self.config.theme.cursor_color[1], self.config.theme.cursor_color[2], 200);
+                        ui.painter().rect_stroke(term_rect, 4.0, Stroke::new(1.5, accent));
+                    }
+                    if let Some(half) = self.config.blink_half_period().filter(|_| self.blink_seen) {
+                        let until_flip = half - self.anim_t.rem_euclid(half);
+                        ctx.request_repaint_after(Duration::from_secs_f32(until_flip.max(0.01)));
+                    }
+                    let term_resp = ui.allocate_rect(term_rect, Sense::click_and_drag());
+                    if !self.mouse_reporting(ctx) { self.update_selection(ctx, &term_resp, term_rect); }
+                    let chip_click = term_resp.interact_pointer_pos()
+                        .and_then(|pos| self.hovered_mark.filter(|(_, chip)| chip.contains(pos)));
+                    let multi_click = term_resp.interact_pointer_pos().filter(|_| !self.mouse_reporting(ctx));
+                    if let (true, Some((idx, _))) = (term_resp.clicked(), chip_click) {
+                        self.ask_bob_about_mark(idx);
+                    } else if let (true, Some(pos)) = (term_resp.triple_clicked(), multi_click) {
+                        self.select_word_or_line(pos, term_rect, true);
+                    } else if let (true, Some(pos)) = (term_resp.double_clicked(), multi_click) {
+                        self.select_word_or_line(pos, term_rect, false);
+                    } else if term_resp.clicked() {
+                        self.terminal_has_focus = true;
+                        self.tab.selection = None;
+                        if ctx.input(|i| Self::ctrl_or_cmd(i.modifiers)) {
+                            if let Some(url) = self.hovered_url.clone() { self.open_url(&url.url); }
+                            else if let Some(link) = self.hovered_link.clone() { self.open_file_link(&link); }
+                        }
+                    }
                     if self.customize.as_ref().is_some_and(|s| s.open) {
-                        self.draw_customize_editor(ctx, term_rect);
+                        self.draw_customize_editor(ctx, full_term_rect);
                     }
                 });
             });
 
-        self.draw_floating_bob(ctx);
+        if !self.is_bob_docked() { self.draw_floating_bob(ctx); }
+        self.draw_profile_window(ctx);
+        self.draw_export_window(ctx);
+        self.draw_env_window(ctx);
+        self.draw_template_form(ctx);
+        self.draw_command_palette(ctx);
+        self.draw_broadcast_confirm(ctx);
         self.handle_keys(ctx);
+        if let Some(text) = self.copy_request.take() { ctx.output_mut(|o| o.copied_text = text); }
+    }
+}
+
+#[derive(Default)]
+struct CliArgs {
+    show_help:    bool,
+    show_version: bool,
+    config:       Option<PathBuf>,
+    profile:      Option<String>,
+    dropdown:     bool,
+    launch:       LaunchOptions,
+}
+impl CliArgs {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut out = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = |name: &str| -> Result<String> {
+                inline.clone().or_else(|| args.next()).with_context(|| format!("{name} needs a value"))
+            };
+            match flag.as_str() {
+                "-h" | "--help"    => out.show_help = true,
+                "-V" | "--version" => out.show_version = true,
+                "--config"         => out.config = Some(PathBuf::from(value("--config")?)),
+                "--dropdown"       => out.dropdown = true,
+                "--shell"          => out.launch.shell = Some(value("--shell")?),
+                "--working-directory" | "--cwd" => {
+                    out.launch.working_directory = Some(PathBuf::from(value("--working-directory")?));
+                }
+                "-e" | "--command" => {
+                    let words: Vec<String> = inline.clone().into_iter().chain(args.by_ref()).collect();
+                    out.launch.command = match words.as_slice() {
+                        [] => anyhow::bail!("{flag} needs a command"),
+                        [single] => Some(single.clone()),
+                        many => Some(many.iter().map(|w| format!("'{}'", Spiltixal::shell_escape_single(w))).collect::<Vec<_>>().join(" ")),
+                    };
+                }
+                "--profile"        => {
+                    let name = value("--profile")?;
+                    if !is_valid_profile_name(&name) {
+                        anyhow::bail!("invalid profile name '{name}'");
+                    }
+                    out.profile = Some(name);
+                }
+                other => anyhow::bail!("unknown option '{other}'"),
+            }
+        }
+        Ok(out)
     }
 }
 
+fn print_help() {
+    println!("Spiltixal {APP_VERSION} - a cool looking terminal");
+    println!();
+    println!("Usage: spiltixal [OPTIONS]");
+    println!();
+    println!("Options:");
+    println!("  -h, --help             Print this help and exit");
+    println!("  -V, --version          Print the version and exit");
+    println!("      --config <PATH>    Load and save settings from PATH instead of");
+    println!("                         $XDG_CONFIG_HOME/spiltixal/config.json");
+    println!("      --profile <NAME>   Start with the named profile (created if missing)");
+    println!("      --dropdown         Run as a drop-down terminal at the top of the screen");
+    println!("                         (F12 shows and hides it)");
+    println!("      --shell <PROGRAM>  Run PROGRAM instead of the configured shell");
+    println!("      --working-directory <DIR>");
+    println!("                         Start the shell in DIR (alias: --cwd)");
+    println!("  -e, --command <CMD>... Run CMD through the shell instead of an interactive");
+    println!("                         session; every argument after it belongs to CMD.");
+    println!("                         When it exits, the on_shell_exit setting applies");
+    println!();
+    println!("Command-line options take precedence over the matching config settings.");
+}
+
 fn main() -> Result<()> {
+    let cli = match CliArgs::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("spiltixal: {e}");
+            eprintln!("Try 'spiltixal --help' for more information.");
+            std::process::exit(2);
+        }
+    };
+    if cli.show_help { print_help(); return Ok(()); }
+    if cli.show_version { println!("spiltixal {APP_VERSION}"); return Ok(()); }
+    if let Some(path) = cli.config {
+        let _ = CONFIG_PATH_OVERRIDE.set(std::path::absolute(&path).unwrap_or(path));
+    }
+    let mut launch = cli.launch;
+    if let Some(dir) = launch.working_directory.take() {
+        let dir = std::path::absolute(&dir).unwrap_or(dir);
+        if !dir.is_dir() {
+            eprintln!("spiltixal: --working-directory: {} is not a directory", dir.display());
+            std::process::exit(2);
+        }
+        launch.working_directory = Some(dir);
+    }
+    if let Some(name) = cli.profile {
+        if let Some(dir) = profile_dir(&name) {
+            std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        set_active_profile(&name);
+    }
+
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
     let hyprland = is_hyprland();
 
-    let native_options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_title("Spiltixal")
-            .with_inner_size([1280.0, 780.0])
-            .with_min_inner_size([640.0, 420.0])
-            .with_transparent(hyprland),
-        ..Default::default()
-    };
-
-    eframe::run_native("Spiltixal", native_options, Box::new(|cc| Box::new(Spiltixal::new(cc))))
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_title("Spiltixal")
+        .with_inner_size([1280.0, 780.0])
+        .with_min_inner_size([640.0, 420.0])
+        .with_transparent(hyprland);
+    if cli.dropdown {
+        viewport = viewport
+            .with_decorations(false)
+            .with_always_on_top()
+            .with_position([0.0, 0.0])
+            .with_min_inner_size([320.0, DROPDOWN_PEEK]);
+    }
+    let native_options = eframe::NativeOptions { viewport, ..Default::default() };
+
+    let dropdown = cli.dropdown;
+    eframe::run_native("Spiltixal", native_options, Box::new(move |cc| {
+        let mut app = Spiltixal::new(cc, launch);
+        if dropdown { app.dropdown = Some(DropdownState::default()); }
+        Box::new(app)
+    }))
         .map_err(|e| anyhow::anyhow!("eframe: {e}"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(input: &[u8]) -> TerminalState {
+        let mut term = TerminalState::new(24, 80, 1000);
+        term.process_bytes(input);
+        term
+    }
+
+    fn row_text(term: &TerminalState, y: usize) -> String {
+        term.grid.cells[y].iter().filter(|c| c.width > 0).map(|c| c.ch).collect::<String>().trim_end().to_string()
+    }
+
+    #[test]
+    fn cursor_moves_snap_to_wide_cell_starts() {
+        let mut t = term("\u{65e5}\u{672c}\u{8a9e}".as_bytes());
+        assert_eq!(t.grid.cursor_x, 6);
+        t.process_bytes(b"\x1b[D");
+        assert_eq!(t.grid.cursor_x, 4);
+        t.process_bytes(b"\x1b[2G");
+        assert_eq!(t.grid.cursor_x, 0);
+        t.process_bytes(b"\x1b[C");
+        assert_eq!(t.grid.cursor_x, 2);
+    }
+
+    #[test]
+    fn restore_cursor_returns_to_saved_position() {
+        for (save, restore) in [(&b"\x1b7"[..], &b"\x1b8"[..]), (b"\x1b[s", b"\x1b[u")] {
+            let mut t = term(b"abc");
+            t.process_bytes(save);
+            t.process_bytes(b"\x1b[5;10Hxyz");
+            t.process_bytes(restore);
+            assert_eq!((t.grid.cursor_x, t.grid.cursor_y), (3, 0));
+            t.process_bytes(b"Z");
+            assert_eq!(row_text(&t, 0), "abcZ");
+            assert_eq!(row_text(&t, 4), "         xyz");
+        }
+    }
+
+    #[test]
+    fn tab_aligns_to_custom_stop() {
+        let mut t = term(b"\t");
+        assert_eq!(t.grid.cursor_x, 8);
+        t.process_bytes(b"\r\x1b[3g\x1b[4G\x1bH\rab\tX");
+        assert_eq!(row_text(&t, 0), "ab X");
+        t.process_bytes(b"\tY");
+        assert_eq!(t.grid.cells[0][79].ch, 'Y');
+        t.process_bytes(b"\r\n\x1b[4G\x1b[g\r\tZ");
+        assert_eq!(t.grid.cells[1][79].ch, 'Z');
+    }
+
+    #[test]
+    fn decckm_switches_cursor_keys_to_ss3() {
+        let mut t = term(b"");
+        assert_eq!(t.grid.cursor_key_seq('A'), "\x1b[A");
+        t.process_bytes(b"\x1b[?1h");
+        assert_eq!(t.grid.cursor_key_seq('A'), "\x1bOA");
+        t.process_bytes(b"\x1b[?1l");
+        assert_eq!(t.grid.cursor_key_seq('D'), "\x1b[D");
+    }
+
+    #[test]
+    fn bracketed_paste_wraps_and_strips_markers() {
+        let mut t = term(b"");
+        assert_eq!(t.grid.paste_seq("ls\x1b[201~"), "ls\x1b[201~");
+        t.process_bytes(b"\x1b[?2004h");
+        assert_eq!(t.grid.paste_seq("ls\x1b[201~; rm x"), "\x1b[200~ls; rm x\x1b[201~");
+    }
+
+    #[test]
+    fn dec_special_graphics_draws_box_lines() {
+        let t = term(b"\x1b(0lqk\x1b(Bq");
+        assert_eq!(row_text(&t, 0), "\u{250c}\u{2500}\u{2510}q");
+    }
+
+    #[test]
+    fn erase_chars_blanks_without_moving_cursor() {
+        let t = term(b"HELLO\x1b[2G\x1b[3X");
+        assert_eq!(row_text(&t, 0), "H   O");
+        assert_eq!((t.grid.cursor_x, t.grid.cursor_y), (1, 0));
+    }
+
+    #[test]
+    fn styled_fonts_come_from_the_regular_face_file_name() {
+        let dir = std::env::temp_dir().join(format!("spiltixal-fonts-{}", std::process::id())).join("Mono-Regular");
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = ["UbuntuMono-R.ttf", "UbuntuMono-B.ttf", "UbuntuMono-RI.ttf", "DejaVuSansMono.ttf", "DejaVuSansMono-Oblique.ttf",
+            "Code Regular.otf", "Code Bold.otf", "Code BoldItalic.otf"];
+        for name in files { std::fs::write(dir.join(name), b"").unwrap(); }
+        let styled = |name: &str| find_styled_mono_fonts(Some(&dir.join(name)))
+            .map(|p| p.and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned())));
+        let ubuntu = styled("UbuntuMono-R.ttf");
+        let dejavu = styled("DejaVuSansMono.ttf");
+        let code = styled("Code Regular.otf");
+        let inconsolata = styled("Inconsolata.otf");
+        let _ = std::fs::remove_dir_all(dir.parent().unwrap());
+        assert_eq!(ubuntu, [Some("UbuntuMono-B.ttf".into()), Some("UbuntuMono-RI.ttf".into()), None]);
+        assert_eq!(dejavu, [None, Some("DejaVuSansMono-Oblique.ttf".into()), None]);
+        assert_eq!(code, [Some("Code Bold.otf".into()), None, Some("Code BoldItalic.otf".into())]);
+        assert_eq!(inconsolata, [None, None, None]);
+    }
+
+    #[test]
+    fn cell_format_reflects_bold_italic_and_dim() {
+        let theme = Theme::default();
+        let (fg, bg) = (Color32::from_rgb(200, 100, 50), Color32::from_rgb(0, 0, 0));
+        let format = |fonts, attrs| Spiltixal::cell_format(fonts, &theme, attrs, fg, bg, 14.0, false);
+        let (plain, fake_bold) = format([false; 3], Attrs::default());
+        assert_eq!((plain.color, plain.italics, fake_bold), (fg, false, false));
+        assert_eq!(plain.font_id.family, FontFamily::Monospace);
+        let (_, fake_bold) = format([false; 3], Attrs { bold: true, ..Default::default() });
+        assert!(fake_bold);
+        let (bold, fake_bold) = format([true; 3], Attrs { bold: true, ..Default::default() });
+        assert_eq!((bold.font_id.family, fake_bold), (FontFamily::Name("TermBold".into()), false));
+        let (italic, _) = format([false; 3], Attrs { italic: true, ..Default::default() });
+        assert!(italic.italics);
+        let (italic, _) = format([true; 3], Attrs { italic: true, ..Default::default() });
+        assert_eq!((italic.font_id.family, italic.italics), (FontFamily::Name("TermItalic".into()), false));
+        let (dim, _) = format([false; 3], Attrs { dim: true, ..Default::default() });
+        assert_eq!(dim.color, Color32::from_rgb(100, 50, 25));
+    }
+
+    #[test]
+    fn scrollback_eviction_stays_linear() {
+        let mut grid = Grid::new(24, 80, 10_000);
+        let started = Instant::now();
+        for _ in 0..100_000 { grid.newline(); }
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+        assert_eq!(grid.scrollback.len(), 10_000);
+        assert_eq!(grid.scrollback_stamps.len(), 10_000);
+        assert_eq!(grid.scrollback_wrapped.len(), 10_000);
+    }
+
+    #[test]
+    fn dangerous_command_is_held_for_confirmation() {
+        let (rules, errors) = DangerRules::compile(&Config::default());
+        assert!(errors.is_empty());
+        let prompt = DangerPrompt::check(&rules, "sudo rm -rf /".into()).unwrap_err();
+        assert_eq!(prompt.command, "sudo rm -rf /");
+        assert!(prompt.run && !prompt.reason.is_empty());
+        assert_eq!(DangerPrompt::check(&rules, "ls -la".into()).ok().as_deref(), Some("ls -la"));
+    }
+
+    #[test]
+    fn clear_all_empties_scrollback() {
+        let mut t = term(&b"line\r\n".repeat(60));
+        t.grid.scroll_offset = 5;
+        assert!(!t.grid.scrollback.is_empty());
+        t.grid.clear_all();
+        assert!(t.grid.scrollback.is_empty() && t.grid.scrollback_stamps.is_empty());
+        assert!(t.grid.scrollback_wrapped.is_empty() && t.grid.scrollback_sizes.is_empty());
+        assert_eq!(t.grid.scroll_offset, 0);
+        assert!((0..t.grid.rows).all(|y| row_text(&t, y).is_empty()));
+    }
+
+    #[test]
+    fn updating_saved_command_keeps_identity_and_stats() {
+        let path = std::env::temp_dir().join(format!("spiltixal-saved-{}.json", std::process::id()));
+        let mut store = SavedCommandStore::load_from(path.clone());
+        let id = store.add("ls -la", "list");
+        store.increment_use(id);
+        store.increment_use(id);
+        let created_at = store.commands[0].created_at;
+        assert!(store.update(id, "ls -lah", "list all"));
+        assert!(!store.update(id + 1, "nope", ""));
+        let reloaded = SavedCommandStore::load_from(path.clone());
+        let _ = std::fs::remove_file(&path);
+        for store in [&store, &reloaded] {
+            let saved = &store.commands[0];
+            assert_eq!((saved.id, saved.created_at, saved.use_count), (id, created_at, 2));
+            assert_eq!((saved.command.as_str(), saved.label.as_str(), saved.description.as_str()), ("ls -lah", "ls -lah", "list all"));
+        }
+    }
+
+    #[test]
+    fn shell_args_round_trip_through_customize() {
+        let mut config = Config { shell_args: vec!["--rcfile".into(), "/tmp/my rc".into()], ..Default::default() };
+        CustomizeState::from_config(&config).apply_to(&mut config);
+        assert_eq!(config.shell_args, ["--rcfile", "/tmp/my rc"]);
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/src"), home.join("src"));
+        assert_eq!(expand_tilde("~user/src"), PathBuf::from("~user/src"));
+        config.working_directory = "~/src".into();
+        assert_eq!(LaunchOptions::default().shell_spec(&config).cwd, Some(home.join("src")));
+    }
+
+    #[test]
+    fn gif_frames_stop_at_the_first_broken_frame() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+            for shade in [0u8, 120, 240] {
+                let buf = image::RgbaImage::from_pixel(4, 4, image::Rgba([shade, shade, shade, 255]));
+                encoder.encode_frame(image::Frame::from_parts(buf, 0, 0, image::Delay::from_numer_denom_ms(50, 1))).unwrap();
+            }
+        }
+        let path = std::env::temp_dir().join(format!("spiltixal-broken-{}.gif", std::process::id()));
+        std::fs::write(&path, &gif[..gif.len() - 8]).unwrap();
+        let frames = gif_frames_from_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let frames = frames.expect("the intact frames still animate");
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|(ci, delay)| ci.size == [4, 4] && *delay == Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn fit_context_counts_attached_images() {
+        let mut client = AiClient::new("http://localhost:11434", "llava", "").unwrap();
+        client.context_chars = 5000;
+        let msg = |role: &str, len: usize| ChatMessage { role: role.into(), content: "x".repeat(len), ..Default::default() };
+        let mut history = vec![msg("user", 1000), msg("assistant", 1000), msg("user", 10)];
+        assert_eq!(client.fit_context(&mut history.clone()), 0);
+        history[2].image_paths = vec![PathBuf::from("shot.png")];
+        assert_eq!(client.fit_context(&mut history), 2);
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn saved_command_placeholders_skip_shell_expansions() {
+        let saved = SavedCommand::new(1, "ssh {user}@{host} -p {port} && echo ${HOME} {user} {1x} {} {a b}", "");
+        assert_eq!(saved.placeholders(), ["user", "host", "port"]);
+        let values = HashMap::from([("user".to_string(), "root".to_string()), ("host".to_string(), "example.com".to_string())]);
+        assert_eq!(saved.fill(&values), "ssh root@example.com -p '' && echo ${HOME} root {1x} {} {a b}");
+    }
+
+    #[test]
+    fn saved_command_fill_quotes_for_surrounding_context() {
+        let fill = |command: &str, value: &str| SavedCommand::new(1, command, "").fill(&HashMap::from([("x".to_string(), value.to_string())]));
+        assert_eq!(fill("echo {x}", "a b"), "echo 'a b'");
+        assert_eq!(fill("echo '{x}'", "; rm -rf ~"), "echo '; rm -rf ~'");
+        assert_eq!(fill("echo '{x}'", "'; rm -rf ~'"), "echo ''\"'\"'; rm -rf ~'\"'\"''");
+        assert_eq!(fill("echo \"{x}\"", "$(cmd)"), "echo \"\"'$(cmd)'\"\"");
+        assert_eq!(fill("echo \"{x}\"", "plain-value"), "echo \"plain-value\"");
+        assert_eq!(fill("echo \\'{x}", "a b"), "echo \\''a b'");
+        assert_eq!(fill("printf $'{x}\\n'", "\\' x"), "printf $'\\\\\\' x\\n'");
+        for command in ["echo {x}", "echo '{x}'", "echo \"{x}\"", "echo \"a '{x}' b\"", "echo $'{x}'"] {
+            for value in ["; rm -rf ~", "'; touch /tmp/x; '", "$(id) `id` \"q\" \\ !x", ""] {
+                let out = std::process::Command::new("bash").arg("-c").arg(fill(command, value).replacen("echo", "printf %s", 1)).output().unwrap();
+                let expected = if command.contains("a '") { format!("a '{value}' b") } else { value.to_string() };
+                assert_eq!(String::from_utf8_lossy(&out.stdout), expected, "{command} with {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn sgr_extended_colors_accept_legacy_and_colon_forms() {
+        let mut t = term(b"\x1b[38;2;10;20;30;1ma");
+        assert_eq!(t.current_fg, TermColor::Rgb(10, 20, 30));
+        assert!(t.current_attrs.bold);
+        t.process_bytes(b"\x1b[0;38:2::40:50:60;48:5:200mb");
+        assert_eq!((t.current_fg, t.current_bg), (TermColor::Rgb(40, 50, 60), TermColor::Ansi256(200)));
+        t.process_bytes(b"\x1b[0;38:2:70:80:90;4:3m");
+        assert_eq!(t.current_fg, TermColor::Rgb(70, 80, 90));
+        assert_eq!(t.current_attrs.underline, UnderlineStyle::Curly);
+        t.process_bytes(b"\x1b[0;48;5;300;38;2;999;256;7m");
+        assert_eq!((t.current_fg, t.current_bg), (TermColor::Rgb(255, 255, 7), TermColor::Ansi256(255)));
+    }
+
+    #[test]
+    fn sgr_truncated_extended_color_is_skipped() {
+        let t = term(b"\x1b[38;2;255m");
+        assert_eq!(t.current_fg, TermColor::Default);
+        assert!(!t.current_attrs.dim);
+        let t = term(b"\x1b[48;5m");
+        assert_eq!(t.current_bg, TermColor::Default);
+        assert_eq!(t.current_attrs, Attrs::default());
+    }
+
+    #[test]
+    fn reflow_keeps_double_size_lines() {
+        let mut t = term(b"\x1b#6");
+        t.process_bytes(&[b'x'; 30]);
+        t.process_bytes(b"\r\n\x1b#3top\r\n\x1b#4top\r\nplain");
+        t.grid.reflow(24, 40);
+        let sizes = &t.grid.line_sizes[..5];
+        assert_eq!(sizes, [LineSize::DoubleWidth, LineSize::DoubleWidth, LineSize::DoubleTop, LineSize::DoubleBottom, LineSize::Single]);
+        assert_eq!((row_text(&t, 0).len(), row_text(&t, 1).len()), (20, 10));
+        assert!(t.grid.line_wrapped[0] && !t.grid.line_wrapped[1]);
+        assert_eq!((row_text(&t, 2), row_text(&t, 4)), ("top".to_string(), "plain".to_string()));
+        assert_eq!((t.grid.cursor_x, t.grid.cursor_y), (5, 4));
+    }
+
+    #[test]
+    fn cursor_moves_stay_within_double_width_lines() {
+        let mut t = term(b"\x1b#6\x1b[200C");
+        assert_eq!(t.grid.cursor_x, 39);
+        t.process_bytes(b"\x1b[80G");
+        assert_eq!(t.grid.cursor_x, 39);
+        t.process_bytes(b"\x1b[2;80H\x1b#6\x1b[1;80H");
+        assert_eq!((t.grid.cursor_x, t.grid.cursor_y), (39, 0));
+        t.process_bytes(b"\x1b[3;80H");
+        assert_eq!((t.grid.cursor_x, t.grid.cursor_y), (79, 2));
+    }
+
+    #[test]
+    fn typing_animation_respects_char_boundaries() {
+        let dir = std::env::temp_dir().join(format!("spiltixal-mate-{}", std::process::id()));
+        let commands = SavedCommandStore::load_from(dir.join("saved_commands.json"));
+        let mut mate = Mate::with_storage("Bob".into(), None, commands, dir.join("chat_history.json"));
+        let text = "h\u{e9}llo \u{65e5}\u{672c} w\u{f6}rld \u{1f600}!";
+        mate.typing_target = text.into();
+        mate.typing_chars = 2;
+        assert_eq!(mate.typed_text(), "h");
+        for interval in [Duration::ZERO, Duration::from_millis(18)] {
+            mate.typing_interval = Some(interval);
+            mate.typing_chars = 0;
+            let mut steps = 0;
+            while mate.typing_chars < text.len() {
+                mate.typing_tick = Instant::now() - interval;
+                mate.tick_typing();
+                assert!(text.is_char_boundary(mate.typing_chars.min(text.len())));
+                assert!(text.starts_with(mate.typed_text()));
+                steps += 1;
+                assert!(steps <= text.chars().count());
+            }
+            assert_eq!(mate.typed_text(), text);
+        }
+    }
+
+    #[test]
+    fn shell_prompt_tracks_command_marks() {
+        let mut t = term(b"");
+        assert!(!t.at_shell_prompt());
+        t.process_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07");
+        assert!(t.at_shell_prompt());
+        t.process_bytes(b"python3\r\n\x1b]133;C\x07>>> ");
+        assert!(!t.at_shell_prompt());
+        t.process_bytes(b"\x1b]133;D;0\x07\x1b]133;A\x07$ ");
+        assert!(!t.at_shell_prompt());
+        t.process_bytes(b"\x1b]133;B\x07");
+        assert!(t.at_shell_prompt());
+    }
+
+    #[test]
+    fn only_prompt_input_counts_as_a_command() {
+        let mut t = term(b"\x1b]133;A\x07$ \x1b]133;B\x07");
+        assert_eq!(t.prompt_command("", "sudo ls\n").as_deref(), Some("sudo ls"));
+        t.process_bytes(b"sudo ls\r\n\x1b]133;C\x07[sudo] password: ");
+        assert_eq!(t.prompt_command("", "hunter2"), None);
+        assert_eq!(t.prompt_command("^\\$ ", "hunter2"), None);
+        let t = term(b"user@host:~$ git st");
+        assert_eq!(t.prompt_command("", "git st"), None);
+        assert_eq!(t.prompt_command(r"\$ ", "").as_deref(), Some("git st"));
+    }
+}